@@ -0,0 +1,166 @@
+//! Benchmarks of the emulator core
+//!
+//! Every workload runs on the synthetic ROMs below, and on each `.ch8`
+//! file found in `benches/roms`.
+
+use chip_8_rust::emu::console::{Console, BUFF_HEIGHT, BUFF_WIDTH};
+use chip_8_rust::emu::instructions::Instr;
+use criterion::{
+    black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput,
+};
+use std::fs;
+use std::path::Path;
+
+/// Number of cycles executed per iteration
+const CYCLES: u64 = 10_000;
+
+/// Number of instructions executed in a frame (about 700 per second)
+const INSTR_PER_FRAME: usize = 12;
+
+/// Directory containing additional ROMs
+const ROMS_DIR: &str = "benches/roms";
+
+/// Small ALU-heavy loop which never draws
+const ALU_LOOP: [u8; 14] = [
+    0x60, 0x01, // 0x200: LD V0, 1
+    0x71, 0x01, // 0x202: ADD V1, 1
+    0x80, 0x14, // 0x204: ADD V0, V1
+    0xA3, 0x00, // 0x206: LD I, 0x300
+    0x3F, 0x00, // 0x208: SE VF, 0
+    0x82, 0x06, // 0x20A: SHR V2
+    0x12, 0x02, // 0x20C: JP 0x202
+];
+
+/// Loop drawing a 15 rows sprite at a moving position, also across the edges
+const DRAW_LOOP: [u8; 31] = [
+    0xA2, 0x10, // 0x200: LD I, 0x210
+    0x70, 0x03, // 0x202: ADD V0, 3
+    0x71, 0x05, // 0x204: ADD V1, 5
+    0xD0, 0x1F, // 0x206: DRW V0, V1, 15
+    0x12, 0x02, // 0x208: JP 0x202
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    // 0x210: sprite
+    0xFF, 0x81, 0xBD, 0xA5, 0xA5, 0xBD, 0x81, 0xFF,
+    0x18, 0x3C, 0x7E, 0xFF, 0x7E, 0x3C, 0x18,
+];
+
+/// Returns the ROMs used by the benchmarks, along with their names
+fn roms() -> Vec<(String, Vec<u8>)> {
+    let mut roms = vec![
+        ("alu_loop".to_string(), ALU_LOOP.to_vec()),
+        ("draw_loop".to_string(), DRAW_LOOP.to_vec()),
+    ];
+
+    if let Ok(entries) = fs::read_dir(Path::new(ROMS_DIR)) {
+        let mut paths: Vec<_> = entries
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|p| p.extension().is_some_and(|ext| ext == "ch8"))
+            .collect();
+        paths.sort();
+
+        for path in paths {
+            let name = path.file_stem().unwrap().to_string_lossy().into_owned();
+            roms.push((name, fs::read(&path).expect("Error reading ROM")));
+        }
+    }
+
+    roms
+}
+
+/// Creates a console with the given ROM loaded
+fn console_with(rom: &[u8], cache: bool) -> Console {
+    let mut cons = Console::new();
+    cons.set_decode_cache(cache);
+    cons.load_rom_bytes(rom);
+    cons
+}
+
+/// Decodes every possible opcode
+fn decode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("decode");
+    group.throughput(Throughput::Elements(0x10000));
+
+    group.bench_function("all_opcodes", |b| {
+        b.iter(|| {
+            for op in 0..=u16::MAX {
+                black_box(Instr::decode(black_box(op)));
+            }
+        });
+    });
+
+    group.finish();
+}
+
+/// Executes cycles with and without the decoded instructions cache
+fn cycle(c: &mut Criterion) {
+    let mut group = c.benchmark_group("cycle");
+    group.throughput(Throughput::Elements(CYCLES));
+
+    for (name, rom) in roms() {
+        for cache in [false, true] {
+            let id = BenchmarkId::new(&name, if cache { "cached" } else { "uncached" });
+
+            group.bench_function(id, |b| {
+                let mut cons = console_with(&rom, cache);
+                b.iter(|| {
+                    for _ in 0..CYCLES {
+                        black_box(cons.step(false, false));
+                    }
+                });
+            });
+        }
+    }
+
+    group.finish();
+}
+
+/// Executes the DXYN-heavy loop, where drawing dominates
+fn draw(c: &mut Criterion) {
+    let mut group = c.benchmark_group("draw");
+    group.throughput(Throughput::Elements(CYCLES));
+
+    group.bench_function("dxyn_15_rows", |b| {
+        let mut cons = console_with(&DRAW_LOOP, true);
+        b.iter(|| {
+            for _ in 0..CYCLES {
+                black_box(cons.step(false, false));
+            }
+        });
+    });
+
+    group.finish();
+}
+
+/// Converts the buffer into RGBA pixels, the way a renderer would
+fn render(cons: &Console, pixels: &mut [u32]) {
+    for (y, row) in cons.rows().iter().enumerate() {
+        for x in 0..BUFF_WIDTH {
+            let lit = row & (1 << (BUFF_WIDTH - 1 - x)) != 0;
+            pixels[y * BUFF_WIDTH + x] = if lit { 0xFFFF_FFFF } else { 0x0000_00FF };
+        }
+    }
+}
+
+/// Executes full frames: the frame's instructions, then a render of the buffer
+fn frame(c: &mut Criterion) {
+    let mut group = c.benchmark_group("frame");
+    group.throughput(Throughput::Elements(1));
+
+    for (name, rom) in roms() {
+        group.bench_function(name, |b| {
+            let mut cons = console_with(&rom, true);
+            let mut pixels = vec![0u32; BUFF_WIDTH * BUFF_HEIGHT];
+
+            b.iter(|| {
+                black_box(cons.run_frame(INSTR_PER_FRAME, false));
+                render(&cons, &mut pixels);
+                black_box(&pixels);
+            });
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, decode, cycle, draw, frame);
+criterion_main!(benches);
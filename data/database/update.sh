@@ -0,0 +1,29 @@
+#!/bin/sh
+# Embeds the community CHIP-8 database, along with its licence
+#
+# The programs of crate-programs.json, distributed with this crate, are
+# added after the community ones. Run from the root of the repository:
+#
+#     data/database/update.sh
+
+set -eu
+
+url=https://raw.githubusercontent.com/chip-8-community/chip-8-database/master
+dir=data/database
+tmp=$(mktemp -d)
+trap 'rm -rf "$tmp"' EXIT
+
+for file in programs.json sha1-hashes.json platforms.json; do
+  curl -fsSLo "$tmp/$file" "$url/database/$file"
+done
+curl -fsSLo "$dir/LICENSE" "$url/LICENSE"
+
+jq -n --slurpfile community "$tmp/programs.json" --slurpfile own "$dir/crate-programs.json" \
+  '$community[0] + $own[0]' > "$dir/programs.json"
+jq -n --slurpfile hashes "$tmp/sha1-hashes.json" --slurpfile community "$tmp/programs.json" \
+  --slurpfile own "$dir/crate-programs.json" \
+  '$hashes[0] + ([$own[0] | to_entries[] | .key as $index | .value.roms | keys[]
+    | {(.): (($community[0] | length) + $index)}] | add // {})' > "$dir/sha1-hashes.json"
+cp "$tmp/platforms.json" "$dir/platforms.json"
+
+cargo test --lib rom::database
@@ -0,0 +1,13 @@
+//! Assembles arbitrary Octo source code, as found in cartridges
+//! The assembly must either fail or give a ROM fitting into the memory
+
+#![no_main]
+
+use chip_8_rust::rom::octo;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|source: &str| {
+    if let Ok(rom) = octo::assemble(source) {
+        assert!(rom.len() <= 0x10000 - 0x200, "{} bytes", rom.len());
+    }
+});
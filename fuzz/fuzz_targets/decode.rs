@@ -0,0 +1,14 @@
+//! Decodes arbitrary opcodes
+//! Every opcode must either be unknown or encode back to itself
+
+#![no_main]
+
+use chip_8_rust::emu::instructions::Instr;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|op: u16| {
+    match Instr::decode(op) {
+        Instr::NIL => (),
+        instr => assert_eq!(instr.encode(), Some(op), "{:04x} - {:?}", op, instr),
+    }
+});
@@ -0,0 +1,29 @@
+//! Runs arbitrary ROMs for a bounded number of cycles
+//! The first two bytes of the input are the keys held down during the run
+
+#![no_main]
+
+use chip_8_rust::emu::console::Console;
+use libfuzzer_sys::fuzz_target;
+
+/// Number of cycles executed for each input
+const CYCLES: usize = 10_000;
+
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 2 {
+        return;
+    }
+
+    let keys = u16::from_be_bytes([data[0], data[1]]);
+
+    let mut cons = Console::new();
+    cons.load_rom_bytes(&data[2..]);
+    for key in 0..16 {
+        cons.set_key(key, keys & (1 << key) != 0);
+    }
+
+    for _ in 0..CYCLES {
+        cons.step(false, false);
+        cons.tick_timers();
+    }
+});
@@ -0,0 +1,345 @@
+//! Contains code producing the sound of the console, outside of any audio
+//! backend
+
+use std::f32::consts::PI;
+use std::str::FromStr;
+
+pub mod wav;
+
+/// Shape of the beep
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Waveform {
+    /// Square wave, as most interpreters
+    Square,
+    /// Triangle wave, softer than the square
+    Triangle,
+    /// Sine wave, the softest
+    Sine,
+    /// White noise, the frequency is ignored
+    Noise,
+}
+
+impl FromStr for Waveform {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "square" => Ok(Waveform::Square),
+            "triangle" => Ok(Waveform::Triangle),
+            "sine" => Ok(Waveform::Sine),
+            "noise" => Ok(Waveform::Noise),
+            _ => Err(format!(
+                "unknown waveform '{}' (expected square, triangle, sine or noise)",
+                s
+            )),
+        }
+    }
+}
+
+/// Settings of the beep
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ToneSettings {
+    /// Frequency, in Hz
+    pub frequency: f32,
+    /// Shape of the wave
+    pub waveform: Waveform,
+    /// Volume, from 0 to 1
+    pub volume: f32,
+    /// Time to reach the full volume when the beep starts, in seconds
+    pub attack: f32,
+    /// Time to fall to silence when the beep stops, in seconds
+    pub release: f32,
+}
+
+impl Default for ToneSettings {
+    /// 240 Hz square wave at a quarter of the volume, with 5 ms ramps
+    fn default() -> Self {
+        ToneSettings {
+            frequency: 240.0,
+            waveform: Waveform::Square,
+            volume: 0.25,
+            attack: 0.005,
+            release: 0.005,
+        }
+    }
+}
+
+/// Generates the samples of the beep
+/// The beep is gated on and off, the envelope ramps its amplitude linearly
+/// to avoid the clicks of an abrupt start or stop
+#[derive(Debug, Clone)]
+pub struct ToneGenerator {
+    /// Settings of the beep
+    settings: ToneSettings,
+    /// Number of samples per second
+    sample_rate: u32,
+    /// Position in the period of the wave, from 0 to 1
+    phase: f32,
+    /// Current amplitude of the envelope, from 0 to 1
+    envelope: f32,
+    /// True while the beep is playing
+    gate: bool,
+    /// True if the beep is silenced, whatever the gate
+    muted: bool,
+    /// State of the noise generator
+    noise: u32,
+}
+
+impl ToneGenerator {
+    /// Creates a silent generator
+    pub fn new(settings: ToneSettings, sample_rate: u32) -> Self {
+        ToneGenerator {
+            settings,
+            sample_rate,
+            phase: 0.0,
+            envelope: 0.0,
+            gate: false,
+            muted: false,
+            noise: 0x1234_5678,
+        }
+    }
+
+    /// Starts or stops the beep
+    pub fn set_gate(&mut self, on: bool) {
+        self.gate = on;
+    }
+
+    /// Silences or restores the beep
+    pub fn set_muted(&mut self, muted: bool) {
+        self.muted = muted;
+    }
+
+    /// Returns true if the beep is silenced
+    pub fn muted(&self) -> bool {
+        self.muted
+    }
+
+    /// Sets the volume, clamped between 0 and 1
+    pub fn set_volume(&mut self, volume: f32) {
+        self.settings.volume = volume.clamp(0.0, 1.0);
+    }
+
+    /// Returns the volume
+    pub fn volume(&self) -> f32 {
+        self.settings.volume
+    }
+
+    /// Fills the buffer with the next samples
+    pub fn fill(&mut self, out: &mut [f32]) {
+        let rate = self.sample_rate as f32;
+        let phase_inc = self.settings.frequency / rate;
+        let attack_step = ramp_step(self.settings.attack, rate);
+        let release_step = ramp_step(self.settings.release, rate);
+        let volume = if self.muted { 0.0 } else { self.settings.volume };
+
+        for x in out.iter_mut() {
+            self.envelope = if self.gate {
+                (self.envelope + attack_step).min(1.0)
+            } else {
+                (self.envelope - release_step).max(0.0)
+            };
+
+            *x = volume * self.envelope * self.wave();
+            self.phase = (self.phase + phase_inc) % 1.0;
+        }
+    }
+
+    /// Returns the value of the wave at the current phase, from -1 to 1
+    fn wave(&mut self) -> f32 {
+        let phase = self.phase;
+        match self.settings.waveform {
+            Waveform::Square => if phase < 0.5 { 1.0 } else { -1.0 },
+            Waveform::Triangle => 1.0 - 4.0 * (phase - 0.5).abs(),
+            Waveform::Sine => (2.0 * PI * phase).sin(),
+            Waveform::Noise => {
+                // xorshift32
+                self.noise ^= self.noise << 13;
+                self.noise ^= self.noise >> 17;
+                self.noise ^= self.noise << 5;
+                self.noise as f32 / u32::MAX as f32 * 2.0 - 1.0
+            }
+        }
+    }
+}
+
+/// Number of frames per second, the rate of the timers of the console
+pub const FRAME_RATE: u64 = 60;
+
+/// Renders the beep frame by frame, following the emulated sound timer
+/// rather than the host clock
+/// Every frame lasts exactly 1/60 second: when the sample rate isn't a
+/// multiple of 60, frames get one sample more or less so that they never
+/// drift
+#[derive(Debug, Clone)]
+pub struct FrameAudio {
+    /// Generator of the beep
+    tone: ToneGenerator,
+    /// Number of samples per second
+    sample_rate: u32,
+    /// Number of frames rendered
+    frame: u64,
+}
+
+impl FrameAudio {
+    /// Creates the renderer of a beep
+    pub fn new(settings: ToneSettings, sample_rate: u32) -> Self {
+        FrameAudio {
+            tone: ToneGenerator::new(settings, sample_rate),
+            sample_rate,
+            frame: 0,
+        }
+    }
+
+    /// Number of samples per second
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Generator of the beep, to change its volume
+    pub fn tone_mut(&mut self) -> &mut ToneGenerator {
+        &mut self.tone
+    }
+
+    /// Returns the number of samples of the next frame
+    pub fn frame_len(&self) -> usize {
+        let rate = self.sample_rate as u64;
+        let start = self.frame * rate / FRAME_RATE;
+        let end = (self.frame + 1) * rate / FRAME_RATE;
+        (end - start) as usize
+    }
+
+    /// Renders the samples of the next frame, with or without the beep
+    pub fn render(&mut self, sound: bool) -> Vec<f32> {
+        let mut out = vec![0.0; self.frame_len()];
+        self.tone.set_gate(sound);
+        self.tone.fill(&mut out);
+        self.frame += 1;
+        out
+    }
+}
+
+/// Returns the change of the envelope per sample for a ramp of the given
+/// duration, in seconds
+fn ramp_step(duration: f32, rate: f32) -> f32 {
+    if duration <= 0.0 {
+        1.0
+    } else {
+        1.0 / (duration * rate)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Generator of a square wave of 1/8 of the sample rate, without ramps
+    fn square() -> ToneGenerator {
+        let settings = ToneSettings {
+            frequency: 1000.0,
+            volume: 0.5,
+            attack: 0.0,
+            release: 0.0,
+            ..ToneSettings::default()
+        };
+        ToneGenerator::new(settings, 8000)
+    }
+
+    #[test]
+    fn silent_until_gated() {
+        let mut tone = square();
+        let mut out = [1.0; 16];
+        tone.fill(&mut out);
+        assert!(out.iter().all(|&x| x == 0.0));
+    }
+
+    #[test]
+    fn square_wave() {
+        let mut tone = square();
+        tone.set_gate(true);
+        let mut out = [0.0; 8];
+        tone.fill(&mut out);
+        assert_eq!(out, [0.5, 0.5, 0.5, 0.5, -0.5, -0.5, -0.5, -0.5]);
+    }
+
+    #[test]
+    fn envelope_ramps_without_jumps() {
+        let settings = ToneSettings {
+            waveform: Waveform::Sine,
+            volume: 1.0,
+            attack: 0.01,
+            release: 0.01,
+            ..ToneSettings::default()
+        };
+        let mut tone = ToneGenerator::new(settings, 44100);
+        let mut out = vec![0.0; 2000];
+
+        tone.set_gate(true);
+        tone.fill(&mut out[..1000]);
+        tone.set_gate(false);
+        tone.fill(&mut out[1000..]);
+
+        // A 240 Hz sine at full volume moves by at most 2*pi*240/44100
+        // between two samples, the envelope adds at most 1/441
+        let max_step = 2.0 * PI * 240.0 / 44100.0 + 1.0 / 441.0;
+        for pair in out.windows(2) {
+            assert!((pair[1] - pair[0]).abs() <= max_step + 1e-6);
+        }
+        assert_eq!(out[1999], 0.0);
+    }
+
+    #[test]
+    fn mute_and_volume() {
+        let mut tone = square();
+        tone.set_gate(true);
+        tone.set_muted(true);
+        let mut out = [1.0; 8];
+        tone.fill(&mut out);
+        assert!(out.iter().all(|&x| x == 0.0));
+
+        tone.set_muted(false);
+        tone.set_volume(2.0);
+        assert_eq!(tone.volume(), 1.0);
+        tone.fill(&mut out);
+        assert_eq!(out[0], 1.0);
+    }
+
+    #[test]
+    fn frames_last_exactly_a_sixtieth_of_a_second() {
+        let settings = ToneSettings {
+            attack: 0.0,
+            release: 0.0,
+            ..ToneSettings::default()
+        };
+
+        // Beep during frames 1 to 5
+        let mut audio = FrameAudio::new(settings, 44100);
+        let samples: Vec<f32> = (0..8)
+            .flat_map(|frame| audio.render((1..6).contains(&frame)))
+            .collect();
+        assert_eq!(samples.len(), 8 * 735);
+        assert_eq!(samples.iter().filter(|&&x| x != 0.0).count(), 5 * 735);
+        assert!(samples[735..6 * 735].iter().all(|&x| x != 0.0));
+
+        let mut audio = FrameAudio::new(settings, 22050);
+        let total: usize = (0..120).map(|_| audio.render(true).len()).sum();
+        assert_eq!(total, 2 * 22050);
+    }
+
+    #[test]
+    fn waveforms_stay_in_range() {
+        for waveform in [Waveform::Square, Waveform::Triangle, Waveform::Sine, Waveform::Noise] {
+            let settings = ToneSettings {
+                waveform,
+                volume: 1.0,
+                attack: 0.0,
+                ..ToneSettings::default()
+            };
+            let mut tone = ToneGenerator::new(settings, 44100);
+            tone.set_gate(true);
+            let mut out = vec![0.0; 1000];
+            tone.fill(&mut out);
+            assert!(out.iter().all(|x| (-1.0..=1.0).contains(x)));
+            assert!(out.iter().any(|&x| x != 0.0));
+        }
+    }
+}
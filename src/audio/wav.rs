@@ -0,0 +1,138 @@
+//! Writes audio into WAV files
+//!
+//! The files are mono 16-bit PCM, the most widely supported format.
+
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// Size of the header of a WAV file, up to the samples
+const HEADER_SIZE: u32 = 44;
+
+/// Number of bytes of a sample
+const SAMPLE_SIZE: u32 = 2;
+
+/// Largest number of samples, as the sizes of the header are 32-bit
+const MAX_SAMPLES: u32 = (u32::MAX - (HEADER_SIZE - 8)) / SAMPLE_SIZE;
+
+/// Writes samples into a WAV file
+/// The sizes of the header are only valid once finish is called
+pub struct WavWriter<W: Write + Seek> {
+    /// Where the file is written
+    out: W,
+    /// Number of samples written
+    samples: u32,
+}
+
+impl WavWriter<BufWriter<File>> {
+    /// Creates a WAV file
+    pub fn create<P: AsRef<Path>>(path: P, sample_rate: u32) -> io::Result<Self> {
+        Self::new(BufWriter::new(File::create(path)?), sample_rate)
+    }
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    /// Creates a WAV writer, writing the header with empty sizes
+    pub fn new(mut out: W, sample_rate: u32) -> io::Result<Self> {
+        out.write_all(b"RIFF")?;
+        out.write_all(&0u32.to_le_bytes())?;
+        out.write_all(b"WAVE")?;
+
+        out.write_all(b"fmt ")?;
+        out.write_all(&16u32.to_le_bytes())?;
+        out.write_all(&1u16.to_le_bytes())?; // PCM
+        out.write_all(&1u16.to_le_bytes())?; // Mono
+        out.write_all(&sample_rate.to_le_bytes())?;
+        let byte_rate = sample_rate.checked_mul(SAMPLE_SIZE).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "the sample rate is too large")
+        })?;
+        out.write_all(&byte_rate.to_le_bytes())?;
+        out.write_all(&(SAMPLE_SIZE as u16).to_le_bytes())?;
+        out.write_all(&(SAMPLE_SIZE as u16 * 8).to_le_bytes())?;
+
+        out.write_all(b"data")?;
+        out.write_all(&0u32.to_le_bytes())?;
+
+        Ok(WavWriter { out, samples: 0 })
+    }
+
+    /// Writes samples, clamped between -1 and 1
+    /// Nothing is written if the file would go past the 4 GiB limit of WAV
+    pub fn write(&mut self, samples: &[f32]) -> io::Result<()> {
+        let total = u32::try_from(samples.len())
+            .ok()
+            .and_then(|len| self.samples.checked_add(len))
+            .filter(|&total| total <= MAX_SAMPLES)
+            .ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidInput, "the WAV file is too large")
+            })?;
+
+        for sample in samples {
+            let value = (sample.clamp(-1.0, 1.0) * i16::MAX as f32).round() as i16;
+            self.out.write_all(&value.to_le_bytes())?;
+        }
+        self.samples = total;
+
+        Ok(())
+    }
+
+    /// Writes the sizes into the header and returns the output
+    pub fn finish(mut self) -> io::Result<W> {
+        // Can't overflow, as write keeps the number of samples under MAX_SAMPLES
+        let data_size = self.samples * SAMPLE_SIZE;
+
+        self.out.seek(SeekFrom::Start(4))?;
+        self.out.write_all(&(HEADER_SIZE - 8 + data_size).to_le_bytes())?;
+        self.out.seek(SeekFrom::Start(HEADER_SIZE as u64 - 4))?;
+        self.out.write_all(&data_size.to_le_bytes())?;
+        self.out.seek(SeekFrom::End(0))?;
+        self.out.flush()?;
+
+        Ok(self.out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn header_and_samples() {
+        let mut wav = WavWriter::new(Cursor::new(vec![]), 44100).unwrap();
+        wav.write(&[0.0, 1.0, -1.0]).unwrap();
+        wav.write(&[2.0, 0.5]).unwrap();
+        let bytes = wav.finish().unwrap().into_inner();
+
+        assert_eq!(bytes.len(), 44 + 5 * 2);
+        assert_eq!(&bytes[0..4], b"RIFF");
+        assert_eq!(bytes[4..8], (36u32 + 10).to_le_bytes());
+        assert_eq!(&bytes[8..16], b"WAVEfmt ");
+        assert_eq!(bytes[24..28], 44100u32.to_le_bytes());
+        assert_eq!(&bytes[36..40], b"data");
+        assert_eq!(bytes[40..44], 10u32.to_le_bytes());
+
+        let samples: Vec<i16> = bytes[44..]
+            .chunks(2)
+            .map(|b| i16::from_le_bytes([b[0], b[1]]))
+            .collect();
+        assert_eq!(samples, [0, 32767, -32767, 32767, 16384]);
+    }
+
+    #[test]
+    fn files_past_the_size_limit_are_refused() {
+        assert!(WavWriter::new(Cursor::new(vec![]), u32::MAX).is_err());
+
+        let mut wav = WavWriter::new(Cursor::new(vec![]), 44100).unwrap();
+        wav.samples = MAX_SAMPLES - 1;
+        assert!(wav.write(&[0.5, 0.5]).is_err());
+        wav.write(&[0.5]).unwrap();
+        assert!(wav.write(&[0.5]).is_err());
+
+        let bytes = wav.finish().unwrap().into_inner();
+        assert_eq!(bytes.len(), 44 + 2);
+        assert_eq!(bytes[4..8], (u32::MAX - 1).to_le_bytes());
+        assert_eq!(bytes[40..44], (u32::MAX - 37).to_le_bytes());
+    }
+}
@@ -0,0 +1,85 @@
+//! Finds the first divergence between two execution traces
+//!
+//! Usage: `trace-diff <trace_a> <trace_b>`
+//!
+//! Traces can be in the text or binary format described in
+//! `chip_8_rust::emu::trace`, and don't need to be in the same format.
+//! Exits with 0 if the traces are identical, 1 if they diverge, and 2 if
+//! they can't be read.
+
+use chip_8_rust::emu::trace::{TraceReader, TraceRecord};
+use std::io;
+use std::process;
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() != 3 {
+        eprintln!("usage: {} <trace_a> <trace_b>", args[0]);
+        process::exit(2);
+    }
+
+    match diff(&args[1], &args[2]) {
+        Ok(same) => process::exit(if same { 0 } else { 1 }),
+        Err(e) => {
+            eprintln!("error: {}", e);
+            process::exit(2);
+        }
+    }
+}
+
+/// Compares the two traces and prints their first divergence
+/// Returns true if they are identical
+fn diff(path_a: &str, path_b: &str) -> io::Result<bool> {
+    let mut trace_a = TraceReader::open(path_a)
+        .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path_a, e)))?;
+    let mut trace_b = TraceReader::open(path_b)
+        .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path_b, e)))?;
+
+    let mut last: Option<TraceRecord> = None;
+    let mut index: u64 = 0;
+
+    loop {
+        let (a, b) = match (trace_a.next().transpose()?, trace_b.next().transpose()?) {
+            (None, None) => {
+                println!("traces are identical ({} records)", index);
+                return Ok(true);
+            }
+            (Some(a), Some(b)) => (a, b),
+            (a, b) => {
+                println!("traces diverge at record {}: one trace ends", index);
+                print_context(last);
+                print_record(path_a, a);
+                print_record(path_b, b);
+                return Ok(false);
+            }
+        };
+
+        let fields = a.diff(&b);
+        if !fields.is_empty() {
+            println!("traces diverge at record {}: {}", index, fields.join(", "));
+            print_context(last);
+            print_record(path_a, Some(a));
+            print_record(path_b, Some(b));
+            return Ok(false);
+        }
+
+        last = Some(a);
+        index += 1;
+    }
+}
+
+/// Prints the last record common to both traces
+fn print_context(last: Option<TraceRecord>) {
+    match last {
+        Some(record) => println!("  common: {}", record),
+        None => println!("  common: (none)"),
+    }
+}
+
+/// Prints the record of one of the traces
+fn print_record(path: &str, record: Option<TraceRecord>) {
+    match record {
+        Some(record) => println!("  {}: {}", path, record),
+        None => println!("  {}: (end of trace)", path),
+    }
+}
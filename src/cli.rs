@@ -0,0 +1,215 @@
+//! Contains the command line interface
+
+use clap::{Args, Parser, Subcommand};
+use std::path::PathBuf;
+
+/// Command line arguments
+/// Without subcommand, the arguments of run are expected
+#[derive(Parser)]
+#[command(
+    version,
+    about = "Chip-8 emulator written in Rust",
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
+pub struct Cli {
+    /// Subcommand to execute
+    #[command(subcommand)]
+    pub action: Option<Action>,
+
+    /// Arguments of run, when no subcommand is given
+    #[command(flatten)]
+    pub run: RunArgs,
+}
+
+/// Subcommands
+#[derive(Subcommand)]
+pub enum Action {
+    /// Runs a ROM (the default)
+    Run(Box<RunArgs>),
+    /// Shows the size, hash, platform and instructions of a ROM
+    Info(InfoArgs),
+    /// Runs a ROM for a number of instructions, then prints its memory and
+    /// screen
+    Dump(DumpArgs),
+    /// Creates a BPS patch turning a ROM into a modified ROM
+    MakePatch(MakePatchArgs),
+}
+
+/// Arguments of run
+#[derive(Args)]
+pub struct RunArgs {
+    /// Path of the ROM to run
+    #[arg(required = true)]
+    pub rom: Option<PathBuf>,
+
+    /// IPS or BPS patch applied to the ROM, can be given several times to
+    /// apply patches in order
+    #[arg(long, value_name = "FILE")]
+    pub patch: Vec<PathBuf>,
+
+    /// Size of a pixel of the console, in pixels of the window (default 20)
+    #[arg(long, value_name = "N")]
+    pub scale: Option<u32>,
+
+    /// Instructions executed per second (default 720)
+    #[arg(long, value_name = "N")]
+    pub ips: Option<u64>,
+
+    /// Platform whose quirks are emulated: originalChip8, modernChip8,
+    /// superchip or xochip (default from the ROM database)
+    #[arg(long, value_name = "ID")]
+    pub platform: Option<String>,
+
+    /// Computer whose memory layout is used: chip8, vip2k (2 KiB of memory)
+    /// or eti660 (programs start at 0x600) (default chip8)
+    #[arg(long, value_name = "NAME")]
+    pub machine: Option<String>,
+
+    /// Font of the digits: default, vip, dream6800, eti660, fish, or the path
+    /// of a font file of 80 bytes
+    #[arg(long, value_name = "NAME|FILE")]
+    pub font: Option<String>,
+
+    /// Colors of the lit and unlit pixels, as hexadecimal RGB values
+    /// (default ffffff,000000)
+    #[arg(long, value_name = "FG,BG")]
+    pub palette: Option<String>,
+
+    /// Fills the screen with the window
+    #[arg(long)]
+    pub fullscreen: bool,
+
+    /// Starts paused, P pauses and resumes the emulation
+    #[arg(long)]
+    pub paused: bool,
+
+    /// Reloads the ROM whenever its file changes
+    #[arg(long, conflicts_with = "headless")]
+    pub watch: bool,
+
+    /// Prints the key events (-v) and every executed instruction (-vv)
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    pub verbose: u8,
+
+    /// Keymap preset: qwerty, qwertz, azerty or numpad
+    #[arg(long, value_name = "PRESET")]
+    pub keymap: Option<String>,
+
+    /// Identifies keys by their physical position (scancode) or by the
+    /// character they produce (keycode)
+    #[arg(long, value_name = "MODE")]
+    pub input_mode: Option<String>,
+
+    /// Shows a keypad next to the game, which can be used with the mouse
+    #[arg(long)]
+    pub keypad: bool,
+
+    /// Frequency of the beep, in Hz (default 240)
+    #[arg(long, value_name = "HZ")]
+    pub beep_frequency: Option<f32>,
+
+    /// Shape of the beep: square, triangle, sine or noise
+    #[arg(long)]
+    pub waveform: Option<String>,
+
+    /// Volume of the beep, from 0 to 1 (default 0.25)
+    #[arg(long)]
+    pub volume: Option<f32>,
+
+    /// Time for the beep to reach its volume, in milliseconds (default 5)
+    #[arg(long, value_name = "MS")]
+    pub attack: Option<f32>,
+
+    /// Time for the beep to fall to silence, in milliseconds (default 5)
+    #[arg(long, value_name = "MS")]
+    pub release: Option<f32>,
+
+    /// Writes an execution trace into the given file
+    #[arg(long, value_name = "FILE")]
+    pub trace: Option<PathBuf>,
+
+    /// Format of the execution trace
+    #[arg(long, value_parser = ["text", "binary"], default_value = "text")]
+    pub trace_format: String,
+
+    /// Runs without window nor audio device, for the given number of frames
+    #[arg(long)]
+    pub headless: bool,
+
+    /// Number of frames run in headless mode
+    #[arg(long, default_value_t = 600, requires = "headless")]
+    pub frames: u64,
+
+    /// Writes the audio of the headless run into a WAV file
+    #[arg(long, value_name = "FILE", requires = "headless")]
+    pub audio_out: Option<PathBuf>,
+
+    /// Path of the configuration file
+    #[arg(long, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+
+    /// Directory of the ROM database, in the format of the community CHIP-8
+    /// database
+    #[arg(long, value_name = "DIR")]
+    pub database: Option<PathBuf>,
+
+    /// Saves the speed, platform, machine, font, display and keymap options
+    /// given on the command line as the settings of the ROM in the
+    /// configuration file
+    #[arg(long)]
+    pub remember: bool,
+}
+
+/// Arguments of info
+#[derive(Args)]
+pub struct InfoArgs {
+    /// Path of the ROM
+    pub rom: PathBuf,
+
+    /// Computer whose memory layout is used: chip8, vip2k or eti660
+    #[arg(long, value_name = "NAME", default_value = "chip8")]
+    pub machine: String,
+
+    /// Directory of the ROM database, in the format of the community CHIP-8
+    /// database
+    #[arg(long, value_name = "DIR")]
+    pub database: Option<PathBuf>,
+}
+
+/// Arguments of dump
+#[derive(Args)]
+pub struct DumpArgs {
+    /// Path of the ROM
+    pub rom: PathBuf,
+
+    /// Computer whose memory layout is used: chip8, vip2k or eti660
+    #[arg(long, value_name = "NAME", default_value = "chip8")]
+    pub machine: String,
+
+    /// Number of instructions executed before the dump
+    #[arg(long, default_value_t = 0, value_name = "N")]
+    pub cycles: u64,
+
+    /// Only prints the memory
+    #[arg(long, conflicts_with = "screen")]
+    pub memory: bool,
+
+    /// Only prints the screen
+    #[arg(long)]
+    pub screen: bool,
+}
+
+/// Arguments of make-patch
+#[derive(Args)]
+pub struct MakePatchArgs {
+    /// Path of the original ROM
+    pub original: PathBuf,
+
+    /// Path of the modified ROM
+    pub modified: PathBuf,
+
+    /// Path of the patch to create
+    #[arg(short, long, value_name = "FILE")]
+    pub output: PathBuf,
+}
@@ -0,0 +1,568 @@
+//! Contains the configuration file of the emulator
+//!
+//! The file is `chip-8-rust/config.toml` in the configuration directory of
+//! the user (`$XDG_CONFIG_HOME` on Linux). Sections under `[roms.<sha1>]`
+//! override the global settings for the ROM with that SHA-1 hash, and the
+//! command line overrides both:
+//!
+//! ```toml
+//! [emulation]
+//! ips = 1000
+//! platform = "modernChip8"
+//!
+//! [display]
+//! scale = 15
+//! palette = "ffcc00,1a1a1a"
+//! fullscreen = false
+//! keypad = false
+//!
+//! [keymap]
+//! mode = "scancode"
+//! preset = "qwerty"
+//! keys = { "5" = ["W", "Up"] }
+//!
+//! [sound]
+//! frequency = 440
+//! waveform = "triangle"
+//! volume = 0.5
+//! attack = 5   # milliseconds
+//! release = 20
+//!
+//! [controller]
+//! deadzone = 10000
+//! buttons = { "a" = "6", "leftshoulder" = "4" }
+//!
+//! [roms.0123456789abcdef0123456789abcdef01234567.emulation]
+//! ips = 500
+//! machine = "eti660"
+//! font = "eti660"
+//!
+//! [roms.0123456789abcdef0123456789abcdef01234567.keymap]
+//! keys = { "4" = ["Left"], "6" = ["Right"] }
+//!
+//! [roms.0123456789abcdef0123456789abcdef01234567.controller]
+//! buttons = { "dpup" = "5", "a" = "none" }
+//! ```
+//!
+//! Settings hinted by the ROM database come between the section of the ROM
+//! and the global settings.
+//!
+//! Running a ROM with `--remember` saves the speed, display and keymap
+//! settings given on the command line into the section of the ROM, keeping
+//! the rest of the file as it is.
+
+use chip_8_rust::audio::ToneSettings;
+use crate::gui::controller::ControllerMapping;
+use crate::gui::keymap::{InputMode, Keymap, Preset};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use toml_edit::{value, DocumentMut, Item, Table, Value};
+
+/// Name of the directory of the emulator in the configuration directory
+const CONFIG_DIR: &str = "chip-8-rust";
+/// Name of the configuration file
+const CONFIG_FILE: &str = "config.toml";
+/// Name of the ROM database directory
+const DATABASE_DIR: &str = "database";
+
+/// Configuration of the emulator
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Global emulation settings
+    #[serde(default)]
+    pub emulation: EmulationConfig,
+    /// Global display settings
+    #[serde(default)]
+    pub display: DisplayConfig,
+    /// Global keymap
+    #[serde(default)]
+    pub keymap: KeymapConfig,
+    /// Global controller mapping
+    #[serde(default)]
+    pub controller: ControllerConfig,
+    /// Settings of the beep
+    #[serde(default)]
+    pub sound: SoundConfig,
+    /// Settings of specific ROMs, by SHA-1 hash
+    #[serde(default)]
+    pub roms: HashMap<String, RomConfig>,
+}
+
+/// Emulation settings
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EmulationConfig {
+    /// Instructions executed per second
+    pub ips: Option<u64>,
+    /// Identifier of the platform in the ROM database, which sets the quirks
+    pub platform: Option<String>,
+    /// Computer whose memory layout is used: chip8, vip2k or eti660
+    pub machine: Option<String>,
+    /// Built-in font (default, vip, dream6800, eti660 or fish), or path of
+    /// a font file of 80 bytes
+    pub font: Option<String>,
+}
+
+impl EmulationConfig {
+    /// Returns the settings of self, completed by those of other
+    pub fn or(&self, other: &EmulationConfig) -> EmulationConfig {
+        EmulationConfig {
+            ips: self.ips.or(other.ips),
+            platform: self.platform.clone().or_else(|| other.platform.clone()),
+            machine: self.machine.clone().or_else(|| other.machine.clone()),
+            font: self.font.clone().or_else(|| other.font.clone()),
+        }
+    }
+}
+
+/// Display settings
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DisplayConfig {
+    /// Size of a pixel of the console, in pixels of the window
+    pub scale: Option<u32>,
+    /// Colors of the lit and unlit pixels, as "RRGGBB,RRGGBB"
+    pub palette: Option<String>,
+    /// True to fill the screen
+    pub fullscreen: Option<bool>,
+    /// True to show the on-screen keypad
+    pub keypad: Option<bool>,
+}
+
+impl DisplayConfig {
+    /// Returns the settings of self, completed by those of other
+    pub fn or(&self, other: &DisplayConfig) -> DisplayConfig {
+        DisplayConfig {
+            scale: self.scale.or(other.scale),
+            palette: self.palette.clone().or_else(|| other.palette.clone()),
+            fullscreen: self.fullscreen.or(other.fullscreen),
+            keypad: self.keypad.or(other.keypad),
+        }
+    }
+}
+
+/// Keymap settings
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct KeymapConfig {
+    /// Input mode: keycode or scancode
+    pub mode: Option<String>,
+    /// Name of the preset the keymap starts from
+    pub preset: Option<String>,
+    /// Keyboard keys bound to console keys, replacing those of the preset
+    #[serde(default)]
+    pub keys: HashMap<String, Vec<String>>,
+}
+
+/// Controller settings
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ControllerConfig {
+    /// Value under which a stick is considered centered
+    pub deadzone: Option<i16>,
+    /// Console keys bound to controller inputs, replacing the default ones
+    #[serde(default)]
+    pub buttons: HashMap<String, String>,
+}
+
+/// Settings of the beep
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SoundConfig {
+    /// Frequency, in Hz
+    pub frequency: Option<f32>,
+    /// Shape of the wave: square, triangle, sine or noise
+    pub waveform: Option<String>,
+    /// Volume, from 0 to 1
+    pub volume: Option<f32>,
+    /// Time to reach the full volume, in milliseconds
+    pub attack: Option<f32>,
+    /// Time to fall to silence, in milliseconds
+    pub release: Option<f32>,
+}
+
+impl SoundConfig {
+    /// Returns the settings of self, completed by those of other
+    pub fn or(&self, other: &SoundConfig) -> SoundConfig {
+        SoundConfig {
+            frequency: self.frequency.or(other.frequency),
+            waveform: self.waveform.clone().or_else(|| other.waveform.clone()),
+            volume: self.volume.or(other.volume),
+            attack: self.attack.or(other.attack),
+            release: self.release.or(other.release),
+        }
+    }
+}
+
+/// Settings of a ROM, overriding the global ones
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RomConfig {
+    /// Emulation settings of the ROM
+    #[serde(default)]
+    pub emulation: EmulationConfig,
+    /// Display settings of the ROM
+    #[serde(default)]
+    pub display: DisplayConfig,
+    /// Settings of the beep of the ROM
+    #[serde(default)]
+    pub sound: SoundConfig,
+    /// Keymap of the ROM
+    pub keymap: Option<KeymapConfig>,
+    /// Controller mapping of the ROM
+    pub controller: Option<ControllerConfig>,
+}
+
+impl Config {
+    /// Returns the default path of the configuration file
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join(CONFIG_DIR).join(CONFIG_FILE))
+    }
+
+    /// Returns the default directory of the ROM database
+    pub fn database_dir() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join(CONFIG_DIR).join(DATABASE_DIR))
+    }
+
+    /// Loads the configuration file at the given path
+    /// Returns the default configuration if the file doesn't exist
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Config::default()),
+            Err(e) => return Err(format!("{}: {}", path.display(), e)),
+        };
+
+        toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    /// Returns the emulation settings of a ROM
+    /// The settings given on the command line replace the configured ones,
+    /// the hints of the database only replace the global ones
+    pub fn emulation(
+        &self,
+        rom_sha1: &str,
+        args: &EmulationConfig,
+        hints: &EmulationConfig,
+    ) -> EmulationConfig {
+        match self.roms.get(rom_sha1) {
+            Some(rom) => args.or(&rom.emulation).or(hints).or(&self.emulation),
+            None => args.or(hints).or(&self.emulation),
+        }
+    }
+
+    /// Returns the display settings of a ROM
+    /// The settings given on the command line replace the configured ones,
+    /// the hints of the database only replace the global ones
+    pub fn display(
+        &self,
+        rom_sha1: &str,
+        args: &DisplayConfig,
+        hints: &DisplayConfig,
+    ) -> DisplayConfig {
+        match self.roms.get(rom_sha1) {
+            Some(rom) => args.or(&rom.display).or(hints).or(&self.display),
+            None => args.or(hints).or(&self.display),
+        }
+    }
+
+    /// Builds the keymap of a ROM
+    /// The preset and mode given on the command line replace the configured
+    /// ones, the keys hinted by the database are added to the preset
+    pub fn keymap(
+        &self,
+        rom_sha1: &str,
+        preset: Option<&str>,
+        mode: Option<&str>,
+        hints: &HashMap<String, u8>,
+    ) -> Result<Keymap, String> {
+        let rom_keymap = self
+            .roms
+            .get(rom_sha1)
+            .and_then(|rom| rom.keymap.as_ref());
+
+        let preset = preset
+            .or_else(|| rom_keymap.and_then(|k| k.preset.as_deref()))
+            .or(self.keymap.preset.as_deref())
+            .unwrap_or("qwerty")
+            .parse::<Preset>()?;
+
+        let mode = mode
+            .or_else(|| rom_keymap.and_then(|k| k.mode.as_deref()))
+            .or(self.keymap.mode.as_deref())
+            .unwrap_or("keycode")
+            .parse::<InputMode>()?;
+
+        let mut keymap = Keymap::preset(preset, mode);
+        keymap.add_roles(hints);
+        keymap.bind_names(&self.keymap.keys)?;
+        if let Some(rom_keymap) = rom_keymap {
+            keymap.bind_names(&rom_keymap.keys)?;
+        }
+
+        Ok(keymap)
+    }
+
+    /// Builds the controller mapping of a ROM
+    /// The keys hinted by the database replace the default bindings
+    pub fn controller_mapping(
+        &self,
+        rom_sha1: &str,
+        hints: &HashMap<String, u8>,
+    ) -> Result<ControllerMapping, String> {
+        let rom_controller = self
+            .roms
+            .get(rom_sha1)
+            .and_then(|rom| rom.controller.as_ref());
+
+        let mut mapping = ControllerMapping::default();
+        mapping.bind_roles(hints);
+        mapping.bind_names(&self.controller.buttons)?;
+
+        let deadzone = rom_controller
+            .and_then(|c| c.deadzone)
+            .or(self.controller.deadzone);
+        if let Some(deadzone) = deadzone {
+            mapping.set_deadzone(deadzone);
+        }
+
+        if let Some(rom_controller) = rom_controller {
+            mapping.bind_names(&rom_controller.buttons)?;
+        }
+
+        Ok(mapping)
+    }
+
+    /// Builds the settings of the beep of a ROM
+    /// The settings given on the command line replace the configured ones
+    pub fn tone_settings(&self, rom_sha1: &str, args: &SoundConfig) -> Result<ToneSettings, String> {
+        let sound = match self.roms.get(rom_sha1) {
+            Some(rom) => args.or(&rom.sound).or(&self.sound),
+            None => args.or(&self.sound),
+        };
+        let default = ToneSettings::default();
+
+        let waveform = match &sound.waveform {
+            Some(waveform) => waveform.parse()?,
+            None => default.waveform,
+        };
+
+        let settings = ToneSettings {
+            frequency: sound.frequency.unwrap_or(default.frequency),
+            waveform,
+            volume: sound.volume.unwrap_or(default.volume),
+            attack: sound.attack.map_or(default.attack, |ms| ms / 1000.0),
+            release: sound.release.map_or(default.release, |ms| ms / 1000.0),
+        };
+
+        if settings.frequency <= 0.0 {
+            return Err(format!("invalid frequency {}", settings.frequency));
+        }
+        if !(0.0..=1.0).contains(&settings.volume) {
+            return Err(format!("invalid volume {} (expected 0 to 1)", settings.volume));
+        }
+        if settings.attack < 0.0 || settings.release < 0.0 {
+            return Err("attack and release can't be negative".to_string());
+        }
+
+        Ok(settings)
+    }
+}
+
+/// Settings of a ROM saved by remember
+#[derive(Debug, Default, Clone)]
+pub struct RememberedSettings {
+    /// Emulation settings
+    pub emulation: EmulationConfig,
+    /// Display settings
+    pub display: DisplayConfig,
+    /// Keymap preset
+    pub keymap_preset: Option<String>,
+    /// Keymap input mode
+    pub keymap_mode: Option<String>,
+}
+
+/// Saves settings into the section of a ROM in the configuration file
+/// Only the given settings are written, the rest of the file is kept as it
+/// is, comments included
+pub fn remember(path: &Path, rom_sha1: &str, settings: &RememberedSettings) -> Result<(), String> {
+    let error = |e: &dyn std::fmt::Display| format!("{}: {}", path.display(), e);
+
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(error(&e)),
+    };
+    let mut doc: DocumentMut = text.parse().map_err(|e| error(&e))?;
+
+    remember_into(&mut doc, rom_sha1, settings).map_err(|e| error(&e))?;
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| error(&e))?;
+    }
+    fs::write(path, doc.to_string()).map_err(|e| error(&e))
+}
+
+/// Writes the settings of a ROM into its section of a configuration
+fn remember_into(
+    doc: &mut DocumentMut,
+    rom_sha1: &str,
+    settings: &RememberedSettings,
+) -> Result<(), String> {
+    let rom = table(table(doc.as_table_mut(), "roms")?, rom_sha1)?;
+
+    if let Some(ips) = settings.emulation.ips {
+        table(rom, "emulation")?["ips"] = value(ips as i64);
+    }
+    if let Some(platform) = &settings.emulation.platform {
+        table(rom, "emulation")?["platform"] = value(platform.as_str());
+    }
+    if let Some(machine) = &settings.emulation.machine {
+        table(rom, "emulation")?["machine"] = value(machine.as_str());
+    }
+    if let Some(font) = &settings.emulation.font {
+        table(rom, "emulation")?["font"] = value(font.as_str());
+    }
+
+    let display = &settings.display;
+    if let Some(scale) = display.scale {
+        table(rom, "display")?["scale"] = value(scale as i64);
+    }
+    if let Some(palette) = &display.palette {
+        table(rom, "display")?["palette"] = value(palette.as_str());
+    }
+    if let Some(fullscreen) = display.fullscreen {
+        table(rom, "display")?["fullscreen"] = value(fullscreen);
+    }
+    if let Some(keypad) = display.keypad {
+        table(rom, "display")?["keypad"] = value(keypad);
+    }
+
+    if let Some(preset) = &settings.keymap_preset {
+        table(rom, "keymap")?["preset"] = value(preset.as_str());
+    }
+    if let Some(mode) = &settings.keymap_mode {
+        table(rom, "keymap")?["mode"] = value(mode.as_str());
+    }
+
+    Ok(())
+}
+
+/// Returns the sub-table of a table, creating it if needed
+/// Tables only containing tables are implicit, so that `[roms]` isn't
+/// written above `[roms.<sha1>.display]`
+fn table<'a>(parent: &'a mut Table, key: &str) -> Result<&'a mut Table, String> {
+    let item = parent.entry(key).or_insert_with(|| {
+        let mut table = Table::new();
+        table.set_implicit(true);
+        Item::Table(table)
+    });
+
+    // An inline table, such as `emulation = { ips = 500 }`, becomes a section
+    if let Item::Value(Value::InlineTable(inline)) = item {
+        *item = Item::Table(std::mem::take(inline).into_table());
+    }
+
+    item.as_table_mut().ok_or_else(|| format!("'{}' isn't a table", key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// SHA-1 used by the tests
+    const SHA1: &str = "0123456789abcdef0123456789abcdef01234567";
+
+    #[test]
+    fn rom_settings_override_global_ones() {
+        let config: Config = toml::from_str(&format!(
+            "[emulation]\nips = 1000\n[display]\nscale = 10\n\
+             [roms.{}.display]\nscale = 5\n",
+            SHA1
+        ))
+        .unwrap();
+
+        let args = DisplayConfig::default();
+        let hints = DisplayConfig::default();
+        assert_eq!(config.display(SHA1, &args, &hints).scale, Some(5));
+        assert_eq!(config.display("other", &args, &hints).scale, Some(10));
+        let none = EmulationConfig::default();
+        assert_eq!(config.emulation(SHA1, &none, &none).ips, Some(1000));
+
+        let args = DisplayConfig { scale: Some(3), ..DisplayConfig::default() };
+        assert_eq!(config.display(SHA1, &args, &hints).scale, Some(3));
+    }
+
+    #[test]
+    fn database_hints_only_override_global_settings() {
+        let config: Config = toml::from_str(&format!(
+            "[emulation]\nips = 1000\n[roms.{}.emulation]\nips = 500\n",
+            SHA1
+        ))
+        .unwrap();
+
+        let args = EmulationConfig::default();
+        let hints = EmulationConfig {
+            ips: Some(900),
+            platform: Some("superchip".to_string()),
+            ..EmulationConfig::default()
+        };
+        assert_eq!(config.emulation(SHA1, &args, &hints).ips, Some(500));
+        assert_eq!(config.emulation("other", &args, &hints).ips, Some(900));
+        assert_eq!(
+            config.emulation(SHA1, &args, &hints).platform.as_deref(),
+            Some("superchip")
+        );
+
+        let args = EmulationConfig { ips: Some(100), ..EmulationConfig::default() };
+        assert_eq!(config.emulation(SHA1, &args, &hints).ips, Some(100));
+    }
+
+    #[test]
+    fn remember_keeps_the_rest_of_the_file() {
+        let path = std::env::temp_dir().join(format!("chip-8-rust-{}.toml", std::process::id()));
+        fs::write(&path, "# my settings\n[display]\nscale = 10\n").unwrap();
+
+        let settings = RememberedSettings {
+            emulation: EmulationConfig { ips: Some(500), ..EmulationConfig::default() },
+            keymap_preset: Some("azerty".to_string()),
+            ..RememberedSettings::default()
+        };
+        remember(&path, SHA1, &settings).unwrap();
+
+        let text = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(text.starts_with("# my settings\n[display]\nscale = 10\n"));
+        assert!(!text.contains("[roms]\n"));
+
+        let config: Config = toml::from_str(&text).unwrap();
+        let rom = &config.roms[SHA1];
+        assert_eq!(rom.emulation.ips, Some(500));
+        assert_eq!(rom.keymap.as_ref().unwrap().preset.as_deref(), Some("azerty"));
+        assert_eq!(config.display.scale, Some(10));
+    }
+
+    #[test]
+    fn remember_into_inline_tables() {
+        let settings = RememberedSettings {
+            emulation: EmulationConfig { ips: Some(500), ..EmulationConfig::default() },
+            ..RememberedSettings::default()
+        };
+
+        let text = format!("[roms.{}]\nemulation = {{ ips = 300, font = \"vip\" }}\n", SHA1);
+        let mut doc: DocumentMut = text.parse().unwrap();
+        remember_into(&mut doc, SHA1, &settings).unwrap();
+        let config: Config = toml::from_str(&doc.to_string()).unwrap();
+        let emulation = &config.roms[SHA1].emulation;
+        assert_eq!(emulation.ips, Some(500));
+        assert_eq!(emulation.font.as_deref(), Some("vip"));
+
+        let mut doc: DocumentMut = format!("[roms.{}]\nemulation = 3\n", SHA1).parse().unwrap();
+        let error = remember_into(&mut doc, SHA1, &settings).unwrap_err();
+        assert_eq!(error, "'emulation' isn't a table");
+    }
+}
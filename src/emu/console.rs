@@ -1,8 +1,14 @@
 //! Contains the code pertaining to the console
 
 use crate::emu::instructions::*;
-use crate::emu::{box_border, font};
+use crate::emu::machine::Machine;
+use crate::emu::quirks::Quirks;
+use crate::emu::trace::{TraceRecord, TraceWriter};
+use crate::emu::font::{self, Font};
+use crate::emu::box_border;
+use crate::rom::container;
 use std::fs;
+use std::io;
 use rand::rngs::ThreadRng;
 use rand::Rng;
 
@@ -10,24 +16,37 @@ use rand::Rng;
 pub const BUFF_WIDTH: usize = 64;
 /// Height of the buffer
 pub const BUFF_HEIGHT: usize = 32;
-/// Total size of the buffer
-pub const BUFF_SIZE: usize = BUFF_HEIGHT * BUFF_WIDTH;
-/// Total size of the memory
-const MEM_SIZE: usize = 4096;
+/// Mask of the leftmost pixel of a buffer row
+const ROW_MSB: u64 = 1 << (BUFF_WIDTH - 1);
+/// Number of entries of the stack
+const STACK_SIZE: usize = 16;
+/// Default number of instructions executed per second
+pub const DEFAULT_IPS: u64 = 720;
 
 /// Struct containing the variables of a chip-8 console
 pub struct Console {
-    /// Array of the bytes of the memory
-    memory: [u8; MEM_SIZE],
+    /// Bytes of the memory
+    /// Its size is a power of 2, addresses wrap around its end
+    memory: Vec<u8>,
+    /// Memory layout
+    machine: Machine,
+    /// Font stored into the memory
+    font: Font,
+    /// ROM loaded into the memory, loaded again on reset
+    rom: Vec<u8>,
     /// Buffer (display bits) of the console
-    buffer: [bool; BUFF_SIZE],
+    /// Each row is a bitset, the leftmost pixel being the most significant bit
+    buffer: [u64; BUFF_HEIGHT],
 
     /// Program counter
     pc: u16,
     /// I register
     i: u16,
     /// Stack
-    stack: Vec<u16>,
+    /// It is circular: overflowing it overwrites the oldest entries
+    stack: [u16; STACK_SIZE],
+    /// Stack pointer, index of the next free entry of the stack
+    sp: usize,
     /// Delay timer
     delay_timer: u8,
     /// Sound timer
@@ -35,6 +54,9 @@ pub struct Console {
 
     /// Registers
     v: [u8; 16],
+    /// RPL user flags, where SUPER-CHIP games save registers between runs
+    /// The HP-48 had 8 of them, XO-CHIP has 16
+    flags: [u8; 16],
 
     /// Keys of the console
     /// True if they are down, false if they are up
@@ -42,22 +64,65 @@ pub struct Console {
 
     /// Random number generator
     rng: ThreadRng,
+
+    /// Decoded instructions indexed by address, along with their opcode
+    /// An entry is cleared whenever one of its two bytes is written
+    decoded: Vec<Option<(u16, Instr)>>,
+    /// True if the decoded instructions cache is used
+    cache_enabled: bool,
+
+    /// Behaviours of the interpreter
+    quirks: Quirks,
+    /// True once an instruction waits for the next frame
+    wait_frame: bool,
+
+    /// Number of instructions executed
+    cycles: u64,
+    /// Writer of the execution trace, if any
+    tracer: Option<TraceWriter>,
+}
+
+impl Default for Console {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Console {
-    /// Creates a new console
+    /// Creates a new console, with the memory layout of most interpreters
     pub fn new() -> Self {
+        Self::with_machine(Machine::default())
+    }
+
+    /// Creates a new console with the given memory layout
+    /// Panics if the layout is inconsistent, see Machine::validate
+    pub fn with_machine(machine: Machine) -> Self {
+        if let Err(e) = machine.validate() {
+            panic!("Invalid machine: {}", e);
+        }
+
         let mut cons = Console {
-            memory: [0; MEM_SIZE],
-            buffer: [false; BUFF_SIZE],
-            pc: 0x200,
+            memory: vec![0; machine.memory_size],
+            machine,
+            font: font::FONT,
+            rom: vec![],
+            buffer: [0; BUFF_HEIGHT],
+            pc: machine.start_addr,
             i: 0,
-            stack: vec![],
+            stack: [0; STACK_SIZE],
+            sp: 0,
             delay_timer: 0,
             sound_timer: 0,
             v: [0; 16],
+            flags: [0; 16],
             keys: [false; 16],
             rng: rand::thread_rng(),
+            decoded: vec![None; machine.memory_size],
+            cache_enabled: true,
+            quirks: Quirks::default(),
+            wait_frame: false,
+            cycles: 0,
+            tracer: None,
         };
 
         cons.put_font();
@@ -67,44 +132,160 @@ impl Console {
 
     /// Gets the value of the bit in the buffer at position (x, y)
     pub fn get_bit(&self, x: usize, y: usize) -> bool {
-        self.buffer[x + y * BUFF_WIDTH]
+        self.buffer[y] & (ROW_MSB >> x) != 0
+    }
+
+    /// Gets all the rows of the buffer, from top to bottom
+    /// In each row the leftmost pixel is the most significant bit
+    pub fn rows(&self) -> &[u64; BUFF_HEIGHT] {
+        &self.buffer
     }
 
     /// Loads a ROM into the console
-    pub fn load_rom(&mut self, filename: &str) {
-        let cont = fs::read(filename).expect("Error reading file");
-        for (i, bt) in cont.iter().enumerate() {
-            self.memory[i + 0x200] = *bt;
+    /// The ROM can be in a container, such as a zip archive
+    pub fn load_rom(&mut self, filename: &str) -> io::Result<()> {
+        let data = fs::read(filename)?;
+        self.load_rom_data(&data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Loads the content of a ROM file already in memory into the console
+    /// The format of the file is detected from its magic bytes, so the ROM can
+    /// be in a container, such as a zip archive or an Octo cartridge
+    pub fn load_rom_data(&mut self, data: &[u8]) -> Result<(), String> {
+        let rom = container::unpack(data)?;
+        self.load_rom_bytes(&rom);
+        Ok(())
+    }
+
+    /// Loads a raw ROM already in memory into the console, without looking
+    /// for a container
+    /// Bytes which don't fit into the memory are ignored
+    /// The program area left by a longer ROM is cleared
+    pub fn load_rom_bytes(&mut self, rom: &[u8]) {
+        self.rom = rom.iter().copied().take(self.machine.max_rom_size()).collect();
+        let start = self.machine.start_addr as usize;
+        self.memory[start..].iter_mut().for_each(|bt| *bt = 0);
+        self.put_rom();
+    }
+
+    /// Replaces the ROM and restarts the console with it
+    pub fn reload_rom(&mut self, rom: &[u8]) {
+        self.rom.clear();
+        self.reset();
+        self.load_rom_bytes(rom);
+    }
+
+    /// Restarts the console, as if it was turned off and on again
+    /// The registers, the memory and the buffer are cleared, then the font
+    /// and the ROM are loaded again. The RPL user flags, the quirks and the
+    /// held keys are kept.
+    pub fn reset(&mut self) {
+        self.memory.iter_mut().for_each(|bt| *bt = 0);
+        self.buffer = [0; BUFF_HEIGHT];
+        self.pc = self.machine.start_addr;
+        self.i = 0;
+        self.stack = [0; STACK_SIZE];
+        self.sp = 0;
+        self.delay_timer = 0;
+        self.sound_timer = 0;
+        self.v = [0; 16];
+        self.wait_frame = false;
+
+        self.put_font();
+        self.put_rom();
+    }
+
+    /// Puts the ROM into the memory
+    fn put_rom(&mut self) {
+        let start = self.machine.start_addr as usize;
+        self.memory[start..start + self.rom.len()].copy_from_slice(&self.rom);
+        self.clear_decoded();
+    }
+
+    /// Replaces the font of the console, such as the font of another
+    /// interpreter
+    pub fn set_font(&mut self, font: &Font) {
+        self.font = *font;
+        self.put_font();
+    }
+
+    /// Returns the RPL user flags
+    pub fn flags(&self) -> &[u8; 16] {
+        &self.flags
+    }
+
+    /// Sets the RPL user flags, such as those saved by a previous run
+    pub fn set_flags(&mut self, flags: &[u8; 16]) {
+        self.flags = *flags;
+    }
+
+    /// Returns the memory layout
+    pub fn machine(&self) -> &Machine {
+        &self.machine
+    }
+
+    /// Sets the behaviours of the interpreter
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    /// Enables or disables the decoded instructions cache
+    /// It is enabled by default: the `cycle` benchmarks run 30 to 45% faster
+    /// with it
+    pub fn set_decode_cache(&mut self, enabled: bool) {
+        self.cache_enabled = enabled;
+        self.clear_decoded();
+    }
+
+    /// Clears the decoded instructions cache
+    fn clear_decoded(&mut self) {
+        for entry in self.decoded.iter_mut() {
+            *entry = None;
         }
     }
 
+    /// Writes a byte into the memory
+    /// Invalidates the decoded instructions containing that byte
+    fn write_memory(&mut self, addr: usize, val: u8) {
+        self.memory[addr] = val;
+        self.decoded[addr] = None;
+        let previous = (addr + self.memory.len() - 1) & self.addr_mask();
+        self.decoded[previous] = None;
+    }
+
+    /// Mask keeping addresses inside the memory
+    fn addr_mask(&self) -> usize {
+        self.memory.len() - 1
+    }
+
     /// Puts the font into the memory
     fn put_font(&mut self) {
-        let mut mem_index = font::FONT_ADDR as usize;
+        let mut mem_index = self.machine.font_addr as usize;
 
-        for digit in font::FONT {
+        for digit in self.font {
             for bt in digit {
-                self.memory[mem_index] = bt;
+                self.write_memory(mem_index, bt);
                 mem_index += 1;
             }
         }
     }
 
-    /// XORs the bit at position (x,y) in the buffer with the given argument
-    fn xor_bit(&mut self, x: usize, y: usize, bit: bool) {
-        let bval: bool = self.buffer[x + y * BUFF_WIDTH];
-        if bit && !bval || !bit && bval {
-            self.buffer[x + y * BUFF_WIDTH] = true;
-        } else {
-            self.buffer[x + y * BUFF_WIDTH] = false;
-        }
+    /// XORs a sprite row into the row y of the buffer
+    /// Returns true if a pixel was turned off
+    fn xor_row(&mut self, y: usize, sprite: u64) -> bool {
+        let collision = self.buffer[y] & sprite != 0;
+        self.buffer[y] ^= sprite;
+        collision
     }
 
     /// Clears the buffer
     fn clear_buffer(&mut self) {
-        for i in 0..BUFF_SIZE {
-            self.buffer[i] = false;
-        }
+        self.buffer = [0; BUFF_HEIGHT];
+    }
+
+    /// Writes a byte at the given address of the memory
+    pub fn set_memory(&mut self, addr: usize, val: u8) {
+        self.write_memory(addr, val);
     }
 
     /// Sets a given key
@@ -112,48 +293,187 @@ impl Console {
         self.keys[index] = down;
     }
 
-    /// Goes through a cycle (fetch, decode, execute)
-    /// Prints the instruction if debug is true
+    /// Gets the state of the keys, true when down
+    pub fn keys(&self) -> &[bool; 16] {
+        &self.keys
+    }
+
+    /// Sets the writer of the execution trace
+    /// Every following instruction writes a record into it, until writing
+    /// fails
+    pub fn set_tracer(&mut self, tracer: Option<TraceWriter>) {
+        self.tracer = tracer;
+    }
+
+    /// Returns the state of the console before executing the given opcode
+    /// The opcode was fetched at pc
+    fn trace_record(&self, pc: u16, opcode: u16) -> TraceRecord {
+        TraceRecord {
+            cycle: self.cycles,
+            pc: pc & self.addr_mask() as u16,
+            opcode,
+            v: self.v,
+            i: self.i,
+            sp: self.sp as u8,
+            delay_timer: self.delay_timer,
+            sound_timer: self.sound_timer,
+        }
+    }
+
+    /// Executes one instruction (fetch, decode, execute)
+    /// The timers are left untouched, see tick_timers
+    /// Prints the trace record of the instruction if debug is true
     /// Draws the buffer into stdout if draw_term is true
     /// Returns true if we need to draw
-    pub fn cycle(&mut self, debug: bool, draw_term: bool) -> (bool, bool) {
-        let instr_enc = self.fetch();
-        let instr_dec = Instr::decode(instr_enc);
+    pub fn step(&mut self, debug: bool, draw_term: bool) -> bool {
+        let pc = self.pc;
+        let (instr_enc, instr_dec) = self.fetch_decode();
+
+        if debug || self.tracer.is_some() {
+            let record = self.trace_record(pc, instr_enc);
 
-        if debug {
-            println!("{:04x} - {:?}", instr_enc, instr_dec);
+            if debug {
+                println!("{}", record);
+            }
+            if let Some(tracer) = &mut self.tracer {
+                if let Err(e) = tracer.write(&record) {
+                    eprintln!("Error writing the trace, tracing stopped: {}", e);
+                    self.tracer = None;
+                }
+            }
         }
-        
+
+        self.cycles += 1;
         let draw = self.execute(instr_dec);
         if draw_term && draw {
             self.print_buffer();
         }
 
-        // Decrement counters
+        draw
+    }
+
+    /// Decrements the timers, which happens 60 times per second
+    pub fn tick_timers(&mut self) {
         if self.delay_timer > 0 {
             self.delay_timer -= 1;
         }
         if self.sound_timer > 0 {
             self.sound_timer -= 1;
         }
+    }
+
+    /// Returns true while the sound timer is running
+    pub fn sound_on(&self) -> bool {
+        self.sound_timer > 0
+    }
+
+    /// Runs a frame, a 60th of a second of emulated time: executes the given
+    /// number of instructions, then ticks the timers
+    /// The beep lasts for the whole frame if the sound timer is running
+    /// before the tick, so a sound timer of N beeps for exactly N frames
+    /// Prints the trace record of each instruction if debug is true
+    /// The frame ends early if an instruction waits for the next frame
+    pub fn run_frame(&mut self, instructions: usize, debug: bool) -> Frame {
+        self.wait_frame = false;
+
+        let mut draw = false;
+        for _ in 0..instructions {
+            draw |= self.step(debug, false);
+            if self.wait_frame {
+                break;
+            }
+        }
+
+        let sound = self.sound_on();
+        self.tick_timers();
 
-        (draw, self.sound_timer > 0)
+        Frame { draw, sound }
     }
 }
 
+/// Returns the number of instructions of a frame at the given speed
+/// When the speed isn't a multiple of 60, frames get one instruction more or
+/// less so that exactly ips instructions are executed every second
+pub fn frame_instructions(ips: u64, frame: u64) -> usize {
+    let start = frame * ips / 60;
+    let end = (frame + 1) * ips / 60;
+    (end - start) as usize
+}
+
+/// Outcome of a frame
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Frame {
+    /// True if the buffer changed during the frame
+    pub draw: bool,
+    /// True if the beep plays during the frame
+    pub sound: bool,
+}
+
 // INSTRUCTIONS
 
 impl Console {
     /// Fetches the next instruction (not decoded)
     fn fetch(&mut self) -> u16 {
-        let pcu = self.pc as usize;
+        let pcu = self.pc as usize & self.addr_mask();
         let b0 = self.memory[pcu] as u16;
-        let b1 = self.memory[pcu + 1] as u16;
-        self.pc += 2;
+        let b1 = self.memory[(pcu + 1) & self.addr_mask()] as u16;
+        self.pc = (pcu as u16).wrapping_add(2);
 
         (b0 << 8) | b1
     }
 
+    /// Fetches and decodes the next instruction
+    /// Uses the decoded instructions cache if it is enabled
+    fn fetch_decode(&mut self) -> (u16, Instr) {
+        let pcu = self.pc as usize & self.addr_mask();
+
+        if self.cache_enabled {
+            if let Some(entry) = self.decoded[pcu] {
+                self.pc = (pcu as u16).wrapping_add(2);
+                return entry;
+            }
+        }
+
+        let instr_enc = self.fetch();
+        let entry = (instr_enc, Instr::decode(instr_enc));
+
+        if self.cache_enabled {
+            self.decoded[pcu] = Some(entry);
+        }
+
+        entry
+    }
+
+    /// Skips the next instruction
+    /// Wraps around the end of the memory
+    fn skip(&mut self) {
+        self.pc = (self.pc.wrapping_add(2) as usize & self.addr_mask()) as u16;
+    }
+
+    /// Address in memory at the given offset from I
+    /// Wraps around the end of the memory
+    fn i_addr(&self, offset: usize) -> usize {
+        (self.i as usize + offset) & self.addr_mask()
+    }
+
+    /// Resets vf after a logic instruction if the quirk is enabled
+    fn logic_quirk(&mut self) {
+        if self.quirks.logic {
+            self.v[0xF] = 0;
+        }
+    }
+
+    /// Moves I after storing or loading x + 1 registers, depending on the
+    /// quirks
+    fn memory_quirk(&mut self, x: u8) {
+        if self.quirks.memory_leave_i_unchanged {
+            return;
+        }
+
+        let increment = if self.quirks.memory_increment_by_x { x } else { x + 1 };
+        self.i = self.i.wrapping_add(increment as u16);
+    }
+
     /// Executes the given instruction
     fn execute(&mut self, instr: Instr) -> bool {
         let mut draw = false;
@@ -161,30 +481,34 @@ impl Console {
         match instr {
             Instr::CLS => self.clear_buffer(),
 
-            Instr::RET => (self.pc = self.stack.pop().expect("Empty stack")),
+            Instr::RET => {
+                self.sp = (self.sp + STACK_SIZE - 1) % STACK_SIZE;
+                self.pc = self.stack[self.sp];
+            }
 
             Instr::JP(nnn) => self.pc = nnn,
 
             Instr::CALL(nnn) => {
-                self.stack.push(self.pc);
+                self.stack[self.sp] = self.pc;
+                self.sp = (self.sp + 1) % STACK_SIZE;
                 self.pc = nnn;
             }
 
             Instr::SE(x, nn) => {
                 if self.v[x as usize] == nn {
-                    self.pc += 2;
+                    self.skip();
                 }
             }
 
             Instr::SNE(x, nn) => {
                 if self.v[x as usize] != nn {
-                    self.pc += 2;
+                    self.skip();
                 }
             }
 
             Instr::SEV(x, y) => {
                 if self.v[x as usize] == self.v[y as usize] {
-                    self.pc += 2;
+                    self.skip();
                 }
             }
 
@@ -196,11 +520,20 @@ impl Console {
 
             Instr::LDV(x, y) => self.v[x as usize] = self.v[y as usize],
 
-            Instr::OR(x, y) => self.v[x as usize] |= self.v[y as usize],
+            Instr::OR(x, y) => {
+                self.v[x as usize] |= self.v[y as usize];
+                self.logic_quirk();
+            }
 
-            Instr::AND(x, y) => self.v[x as usize] &= self.v[y as usize],
+            Instr::AND(x, y) => {
+                self.v[x as usize] &= self.v[y as usize];
+                self.logic_quirk();
+            }
 
-            Instr::XOR(x, y) => self.v[x as usize] ^= self.v[y as usize],
+            Instr::XOR(x, y) => {
+                self.v[x as usize] ^= self.v[y as usize];
+                self.logic_quirk();
+            }
 
             Instr::ADDV(x, y) => {
                 let added = self.v[x as usize] as u16 + self.v[y as usize] as u16;
@@ -210,59 +543,54 @@ impl Console {
             }
 
             Instr::SUB(x, y) => {
-                self.v[0xF] = if self.v[x as usize] > self.v[y as usize] {
-                    1
-                } else {
-                    0
-                };
-
+                // VF is set to 1 if there is no borrow, after the result so
+                // that it wins when x is F
+                let no_borrow = self.v[x as usize] >= self.v[y as usize];
                 self.v[x as usize] = self.v[x as usize].wrapping_sub(self.v[y as usize]);
+
+                self.v[0xF] = if no_borrow { 1 } else { 0 };
             }
 
             Instr::SHR(x, y) => {
-                /*
-                TODO FOR COSMAC
-                self.v[x as usize] = self.v[y as usize]
-                 */
+                if !self.quirks.shift {
+                    self.v[x as usize] = self.v[y as usize];
+                }
 
-                self.v[0xF] = self.v[x as usize] & 1;
+                let shifted_out = self.v[x as usize] & 1;
                 self.v[x as usize] >>= 1;
+
+                self.v[0xF] = shifted_out;
             }
 
             Instr::SUBN(x, y) => {
-                self.v[0xF] = if self.v[y as usize] > self.v[x as usize] {
-                    1
-                } else {
-                    0
-                };
-
+                let no_borrow = self.v[y as usize] >= self.v[x as usize];
                 self.v[x as usize] = self.v[y as usize].wrapping_sub(self.v[x as usize]);
+
+                self.v[0xF] = if no_borrow { 1 } else { 0 };
             }
 
             Instr::SHL(x, y) => {
-                /*
-                TODO FOR COSMAC
-                self.v[x as usize] = self.v[y as usize]
-                 */
+                if !self.quirks.shift {
+                    self.v[x as usize] = self.v[y as usize];
+                }
 
-                self.v[0xF] = self.v[x as usize] & 1;
+                let shifted_out = self.v[x as usize] >> 7;
                 self.v[x as usize] <<= 1;
+
+                self.v[0xF] = shifted_out;
             }
 
             Instr::SNEV(x, y) => {
                 if self.v[x as usize] != self.v[y as usize] {
-                    self.pc += 2;
+                    self.skip();
                 }
             }
 
             Instr::LDI(nnn) => self.i = nnn,
 
             Instr::JPV(x, nnn) => {
-                /*
-                TODO NOT COSMAC
-                self.pc = nnn + self.v[x as usize] as u16;
-                 */
-                self.pc = nnn + self.v[0] as u16;
+                let offset = if self.quirks.jump { self.v[x as usize] } else { self.v[0] };
+                self.pc = nnn + offset as u16;
             }
 
             Instr::RND(x, nn) => {
@@ -277,51 +605,56 @@ impl Console {
 
                 self.v[15] = 0;
 
-                let mut i: usize = 0;
-                let mut j: usize;
-                let mut bt: u8;
-                let mut bit: u8;
-
-                while i < (n as usize) && y_coord + i < BUFF_HEIGHT {
-                    bt = self.memory[(self.i as usize) + i];
-                    j = 0;
-
-                    while j < 8 && x_coord + j < BUFF_WIDTH {
-                        bit = (bt >> (7 - j)) & 1;
+                // Sprite rows are clipped at the bottom of the screen, unless
+                // they wrap
+                let rows = if self.quirks.wrap {
+                    n as usize
+                } else {
+                    (n as usize).min(BUFF_HEIGHT - y_coord)
+                };
 
-                        if bit == 1 {
-                            self.xor_bit(x_coord + j, y_coord + i, true);
+                for i in 0..rows {
+                    let bt = self.memory[self.i_addr(i)];
 
-                            if self.get_bit(x_coord + j, y_coord + i) {
-                                self.v[15] = 1;
-                            }
-                        }
+                    // Place the byte at the left of the row, then move it to x
+                    // Bits moved past the right edge are clipped or wrap
+                    let left = (bt as u64) << (BUFF_WIDTH - 8);
+                    let sprite = if self.quirks.wrap {
+                        left.rotate_right(x_coord as u32)
+                    } else {
+                        left >> x_coord
+                    };
 
-                        j += 1;
+                    if self.xor_row((y_coord + i) % BUFF_HEIGHT, sprite) {
+                        self.v[15] = 1;
                     }
+                }
 
-                    i += 1;
+                if self.quirks.vblank {
+                    self.wait_frame = true;
                 }
             }
 
             Instr::SKP(x) => {
-                if self.keys[self.v[x as usize] as usize] {
-                    self.pc += 2;
+                if self.keys[(self.v[x as usize] & 0xF) as usize] {
+                    self.skip();
                 }
             }
 
             Instr::SKNP(x) => {
-                if !self.keys[self.v[x as usize] as usize] {
-                    self.pc += 2;
+                if !self.keys[(self.v[x as usize] & 0xF) as usize] {
+                    self.skip();
                 }
             }
 
             Instr::LDXT(x) => self.v[x as usize] = self.delay_timer,
 
             Instr::LDK(x) => {
-                //TODO implement cosmac behaviour
-                if !self.keys[self.v[x as usize] as usize] {
-                    self.pc -= 2;
+                //TODO implement cosmac behaviour (wait for the release)
+                // Waits by executing the instruction again until a key is down
+                match self.keys.iter().position(|&down| down) {
+                    Some(key) => self.v[x as usize] = key as u8,
+                    None => self.pc = (self.pc.wrapping_sub(2) as usize & self.addr_mask()) as u16,
                 }
             }
 
@@ -330,7 +663,7 @@ impl Console {
             Instr::LDS(x) => self.sound_timer = self.v[x as usize],
 
             Instr::ADDI(x) => {
-                self.i += self.v[x as usize] as u16;
+                self.i = self.i.wrapping_add(self.v[x as usize] as u16);
 
                 //FIXME don't do if cosmac
                 if self.i > 0x1000 {
@@ -338,7 +671,9 @@ impl Console {
                 }
             }
 
-            Instr::LDF(x) => self.i = font::digit_addr(self.v[x as usize]),
+            Instr::LDF(x) => {
+                self.i = font::digit_addr(self.machine.font_addr, self.v[x as usize] & 0xF)
+            },
 
             Instr::LDB(x) => {
                 let nbr = self.v[x as usize];
@@ -346,23 +681,33 @@ impl Console {
                 let tens = (nbr / 10) % 10;
                 let ones = nbr % 10;
 
-                self.memory[self.i as usize] = hundreds;
-                self.memory[self.i as usize + 1] = tens;
-                self.memory[self.i as usize + 2] = ones;
+                self.write_memory(self.i_addr(0), hundreds);
+                self.write_memory(self.i_addr(1), tens);
+                self.write_memory(self.i_addr(2), ones);
             }
 
             Instr::LDIX(x) => {
                 for i in 0..=(x as usize) {
-                    self.memory[self.i as usize + i] = self.v[i];
+                    self.write_memory(self.i_addr(i), self.v[i]);
                 }
-                //TODO add COSMAC behaviour
+                self.memory_quirk(x);
             }
 
             Instr::LDXI(x) => {
                 for i in 0..=(x as usize) {
-                    self.v[i] = self.memory[self.i as usize + i];
+                    self.v[i] = self.memory[self.i_addr(i)];
                 }
-                //TODO add COSMAC behaviour
+                self.memory_quirk(x);
+            }
+
+            Instr::LDRX(x) => {
+                let x = x as usize;
+                self.flags[..=x].copy_from_slice(&self.v[..=x]);
+            }
+
+            Instr::LDXR(x) => {
+                let x = x as usize;
+                self.v[..=x].copy_from_slice(&self.flags[..=x]);
             }
 
             _ => (), //println!("Not yet implemented"),
@@ -387,14 +732,7 @@ impl Console {
             print!("{}", box_border::VERTICAL);
 
             for x in 0..BUFF_WIDTH {
-                let bit = self.get_bit(x, y);
-
-                let s: &str;
-                if bit {
-                    s = "██";
-                } else {
-                    s = "  ";
-                }
+                let s = if self.get_bit(x, y) { "██" } else { "  " };
 
                 print!("{}", s);
             }
@@ -410,7 +748,7 @@ impl Console {
     pub fn print_memory(&self) {
         println!("MEMORY");
 
-        for i in 0..MEM_SIZE {
+        for i in 0..self.memory.len() {
             if i % 16 == 0 {
                 print!("{:#05x}: ", i);
             }
@@ -423,3 +761,760 @@ impl Console {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rom::cartridge::tests::cartridge_gif;
+
+    /// Address at which the tested instructions are placed
+    const START: u16 = 0x200;
+
+    /// Creates a console with the given registers set
+    fn console_with(regs: &[(usize, u8)]) -> Console {
+        let mut cons = Console::new();
+        for &(x, val) in regs {
+            cons.v[x] = val;
+        }
+        cons
+    }
+
+    /// Places the given opcode at pc, then fetches, decodes and executes it
+    /// Returns true if the instruction draws
+    fn step(cons: &mut Console, opcode: u16) -> bool {
+        let pc = cons.pc as usize;
+        cons.write_memory(pc, (opcode >> 8) as u8);
+        cons.write_memory(pc + 1, opcode as u8);
+
+        let (_, instr) = cons.fetch_decode();
+        cons.execute(instr)
+    }
+
+    /// Returns the lit pixels of the buffer
+    fn lit_pixels(cons: &Console) -> Vec<(usize, usize)> {
+        let mut lit = vec![];
+        for y in 0..BUFF_HEIGHT {
+            for x in 0..BUFF_WIDTH {
+                if cons.get_bit(x, y) {
+                    lit.push((x, y));
+                }
+            }
+        }
+        lit
+    }
+
+    #[test]
+    fn cls_clears_the_buffer() {
+        let mut cons = console_with(&[]);
+        cons.buffer = [u64::MAX; BUFF_HEIGHT];
+
+        step(&mut cons, 0x00E0);
+
+        assert!(lit_pixels(&cons).is_empty());
+        assert_eq!(cons.pc, START + 2);
+    }
+
+    #[test]
+    fn call_and_ret() {
+        let mut cons = console_with(&[]);
+
+        step(&mut cons, 0x2345);
+        assert_eq!(cons.pc, 0x345);
+        assert_eq!(cons.sp, 1);
+        assert_eq!(cons.stack[0], START + 2);
+
+        step(&mut cons, 0x00EE);
+        assert_eq!(cons.pc, START + 2);
+        assert_eq!(cons.sp, 0);
+    }
+
+    #[test]
+    fn jp() {
+        let mut cons = console_with(&[]);
+        step(&mut cons, 0x1ABC);
+        assert_eq!(cons.pc, 0xABC);
+    }
+
+    #[test]
+    fn jpv_adds_v0() {
+        let mut cons = console_with(&[(0, 0x10), (1, 0x20)]);
+        step(&mut cons, 0xB300);
+        assert_eq!(cons.pc, 0x310);
+    }
+
+    #[test]
+    fn se_and_sne() {
+        let mut cons = console_with(&[(3, 0x42)]);
+        step(&mut cons, 0x3342);
+        assert_eq!(cons.pc, START + 4);
+
+        let mut cons = console_with(&[(3, 0x42)]);
+        step(&mut cons, 0x3343);
+        assert_eq!(cons.pc, START + 2);
+
+        let mut cons = console_with(&[(3, 0x42)]);
+        step(&mut cons, 0x4343);
+        assert_eq!(cons.pc, START + 4);
+
+        let mut cons = console_with(&[(3, 0x42)]);
+        step(&mut cons, 0x4342);
+        assert_eq!(cons.pc, START + 2);
+    }
+
+    #[test]
+    fn sev_and_snev() {
+        let mut cons = console_with(&[(1, 7), (2, 7), (3, 8)]);
+        step(&mut cons, 0x5120);
+        assert_eq!(cons.pc, START + 4);
+
+        let mut cons = console_with(&[(1, 7), (2, 7), (3, 8)]);
+        step(&mut cons, 0x5130);
+        assert_eq!(cons.pc, START + 2);
+
+        let mut cons = console_with(&[(1, 7), (2, 7), (3, 8)]);
+        step(&mut cons, 0x9130);
+        assert_eq!(cons.pc, START + 4);
+
+        let mut cons = console_with(&[(1, 7), (2, 7), (3, 8)]);
+        step(&mut cons, 0x9120);
+        assert_eq!(cons.pc, START + 2);
+    }
+
+    #[test]
+    fn ld_and_ldv() {
+        let mut cons = console_with(&[(2, 0x99)]);
+
+        step(&mut cons, 0x6A5B);
+        assert_eq!(cons.v[0xA], 0x5B);
+
+        step(&mut cons, 0x8120);
+        assert_eq!(cons.v[1], 0x99);
+    }
+
+    #[test]
+    fn add_wraps_without_touching_vf() {
+        let mut cons = console_with(&[(1, 0xFF), (0xF, 0x12)]);
+        step(&mut cons, 0x7102);
+        assert_eq!(cons.v[1], 0x01);
+        assert_eq!(cons.v[0xF], 0x12);
+    }
+
+    #[test]
+    fn bitwise_operations() {
+        let mut cons = console_with(&[(1, 0b1100), (2, 0b1010)]);
+        step(&mut cons, 0x8121);
+        assert_eq!(cons.v[1], 0b1110);
+
+        let mut cons = console_with(&[(1, 0b1100), (2, 0b1010)]);
+        step(&mut cons, 0x8122);
+        assert_eq!(cons.v[1], 0b1000);
+
+        let mut cons = console_with(&[(1, 0b1100), (2, 0b1010)]);
+        step(&mut cons, 0x8123);
+        assert_eq!(cons.v[1], 0b0110);
+    }
+
+    #[test]
+    fn addv_carry() {
+        let mut cons = console_with(&[(1, 200), (2, 55)]);
+        step(&mut cons, 0x8124);
+        assert_eq!(cons.v[1], 255);
+        assert_eq!(cons.v[0xF], 0);
+
+        let mut cons = console_with(&[(1, 255), (2, 1)]);
+        step(&mut cons, 0x8124);
+        assert_eq!(cons.v[1], 0);
+        assert_eq!(cons.v[0xF], 1);
+
+        let mut cons = console_with(&[(1, 255), (2, 255)]);
+        step(&mut cons, 0x8124);
+        assert_eq!(cons.v[1], 254);
+        assert_eq!(cons.v[0xF], 1);
+    }
+
+    #[test]
+    fn sub_borrow() {
+        let mut cons = console_with(&[(1, 10), (2, 3)]);
+        step(&mut cons, 0x8125);
+        assert_eq!(cons.v[1], 7);
+        assert_eq!(cons.v[0xF], 1);
+
+        let mut cons = console_with(&[(1, 5), (2, 5)]);
+        step(&mut cons, 0x8125);
+        assert_eq!(cons.v[1], 0);
+        assert_eq!(cons.v[0xF], 1);
+
+        let mut cons = console_with(&[(1, 3), (2, 10)]);
+        step(&mut cons, 0x8125);
+        assert_eq!(cons.v[1], 249);
+        assert_eq!(cons.v[0xF], 0);
+    }
+
+    #[test]
+    fn subn_borrow() {
+        let mut cons = console_with(&[(1, 3), (2, 10)]);
+        step(&mut cons, 0x8127);
+        assert_eq!(cons.v[1], 7);
+        assert_eq!(cons.v[0xF], 1);
+
+        let mut cons = console_with(&[(1, 5), (2, 5)]);
+        step(&mut cons, 0x8127);
+        assert_eq!(cons.v[1], 0);
+        assert_eq!(cons.v[0xF], 1);
+
+        let mut cons = console_with(&[(1, 10), (2, 3)]);
+        step(&mut cons, 0x8127);
+        assert_eq!(cons.v[1], 249);
+        assert_eq!(cons.v[0xF], 0);
+    }
+
+    #[test]
+    fn shr_and_shl() {
+        let mut cons = console_with(&[(1, 0b1000_0011)]);
+        step(&mut cons, 0x8106);
+        assert_eq!(cons.v[1], 0b0100_0001);
+        assert_eq!(cons.v[0xF], 1);
+
+        let mut cons = console_with(&[(1, 0b1000_0010)]);
+        step(&mut cons, 0x8106);
+        assert_eq!(cons.v[1], 0b0100_0001);
+        assert_eq!(cons.v[0xF], 0);
+
+        let mut cons = console_with(&[(1, 0b1000_0010)]);
+        step(&mut cons, 0x810E);
+        assert_eq!(cons.v[1], 0b0000_0100);
+        assert_eq!(cons.v[0xF], 1);
+
+        let mut cons = console_with(&[(1, 0b0100_0001)]);
+        step(&mut cons, 0x810E);
+        assert_eq!(cons.v[1], 0b1000_0010);
+        assert_eq!(cons.v[0xF], 0);
+    }
+
+    #[test]
+    fn flag_wins_over_result_in_vf() {
+        let mut cons = console_with(&[(0xF, 200), (1, 100)]);
+        step(&mut cons, 0x8F14);
+        assert_eq!(cons.v[0xF], 1);
+
+        let mut cons = console_with(&[(0xF, 100), (1, 200)]);
+        step(&mut cons, 0x8F15);
+        assert_eq!(cons.v[0xF], 0);
+
+        let mut cons = console_with(&[(0xF, 0b10)]);
+        step(&mut cons, 0x8F06);
+        assert_eq!(cons.v[0xF], 0);
+    }
+
+    #[test]
+    fn ldi_and_addi() {
+        let mut cons = console_with(&[(4, 0x10)]);
+
+        step(&mut cons, 0xA123);
+        assert_eq!(cons.i, 0x123);
+
+        step(&mut cons, 0xF41E);
+        assert_eq!(cons.i, 0x133);
+        assert_eq!(cons.v[0xF], 0);
+    }
+
+    #[test]
+    fn rnd_is_masked() {
+        for _ in 0..64 {
+            let mut cons = console_with(&[(1, 0xFF)]);
+            step(&mut cons, 0xC10F);
+            assert_eq!(cons.v[1] & 0xF0, 0);
+        }
+
+        let mut cons = console_with(&[(1, 0xFF)]);
+        step(&mut cons, 0xC100);
+        assert_eq!(cons.v[1], 0);
+    }
+
+    #[test]
+    fn drw_draws_a_sprite() {
+        let mut cons = console_with(&[(1, 2), (2, 3)]);
+        cons.memory[0x300] = 0b1010_0000;
+        cons.memory[0x301] = 0b0100_0000;
+        cons.i = 0x300;
+
+        assert!(step(&mut cons, 0xD122));
+        assert_eq!(lit_pixels(&cons), vec![(2, 3), (4, 3), (3, 4)]);
+        assert_eq!(cons.v[0xF], 0);
+    }
+
+    #[test]
+    fn drw_collision() {
+        let mut cons = console_with(&[(1, 10), (2, 10)]);
+        cons.memory[0x300] = 0b1100_0000;
+        cons.i = 0x300;
+
+        step(&mut cons, 0xD121);
+        assert_eq!(cons.v[0xF], 0);
+
+        // Drawing the same sprite again erases it
+        step(&mut cons, 0xD121);
+        assert_eq!(cons.v[0xF], 1);
+        assert!(lit_pixels(&cons).is_empty());
+
+        // Drawing next to a sprite doesn't collide
+        step(&mut cons, 0xD121);
+        cons.v[1] = 12;
+        step(&mut cons, 0xD121);
+        assert_eq!(cons.v[0xF], 0);
+        assert_eq!(lit_pixels(&cons).len(), 4);
+    }
+
+    #[test]
+    fn drw_clips_at_the_right_edge() {
+        let mut cons = console_with(&[(1, 60), (2, 0)]);
+        cons.memory[0x300] = 0xFF;
+        cons.i = 0x300;
+
+        step(&mut cons, 0xD121);
+        assert_eq!(lit_pixels(&cons), vec![(60, 0), (61, 0), (62, 0), (63, 0)]);
+    }
+
+    #[test]
+    fn drw_clips_at_the_bottom_edge() {
+        let mut cons = console_with(&[(1, 0), (2, 30)]);
+        cons.memory[0x300..0x304].copy_from_slice(&[0x80; 4]);
+        cons.i = 0x300;
+
+        step(&mut cons, 0xD124);
+        assert_eq!(lit_pixels(&cons), vec![(0, 30), (0, 31)]);
+    }
+
+    #[test]
+    fn drw_wraps_the_starting_position() {
+        let mut cons = console_with(&[(1, 64 + 5), (2, 32 + 7)]);
+        cons.memory[0x300] = 0x80;
+        cons.i = 0x300;
+
+        step(&mut cons, 0xD121);
+        assert_eq!(lit_pixels(&cons), vec![(5, 7)]);
+    }
+
+    #[test]
+    fn drw_clipped_pixels_do_not_wrap() {
+        let mut cons = console_with(&[(1, 63), (2, 0)]);
+        cons.memory[0x300] = 0xFF;
+        cons.i = 0x300;
+        cons.buffer[0] = 1 << 63;
+
+        step(&mut cons, 0xD121);
+        assert_eq!(cons.v[0xF], 0);
+        assert_eq!(lit_pixels(&cons), vec![(0, 0), (63, 0)]);
+    }
+
+    #[test]
+    fn skp_and_sknp() {
+        let mut cons = console_with(&[(1, 0xA)]);
+        cons.set_key(0xA, true);
+        step(&mut cons, 0xE19E);
+        assert_eq!(cons.pc, START + 4);
+
+        let mut cons = console_with(&[(1, 0xA)]);
+        step(&mut cons, 0xE19E);
+        assert_eq!(cons.pc, START + 2);
+
+        let mut cons = console_with(&[(1, 0xA)]);
+        step(&mut cons, 0xE1A1);
+        assert_eq!(cons.pc, START + 4);
+
+        let mut cons = console_with(&[(1, 0xA)]);
+        cons.set_key(0xA, true);
+        step(&mut cons, 0xE1A1);
+        assert_eq!(cons.pc, START + 2);
+    }
+
+    #[test]
+    fn ldk_waits_for_a_key() {
+        let mut cons = console_with(&[]);
+
+        step(&mut cons, 0xF30A);
+        assert_eq!(cons.pc, START);
+
+        cons.set_key(0x7, true);
+        step(&mut cons, 0xF30A);
+        assert_eq!(cons.pc, START + 2);
+        assert_eq!(cons.v[3], 0x7);
+    }
+
+    #[test]
+    fn timers() {
+        let mut cons = console_with(&[(1, 30), (2, 40)]);
+
+        step(&mut cons, 0xF115);
+        assert_eq!(cons.delay_timer, 30);
+
+        step(&mut cons, 0xF218);
+        assert_eq!(cons.sound_timer, 40);
+
+        step(&mut cons, 0xF307);
+        assert_eq!(cons.v[3], 30);
+    }
+
+    #[test]
+    fn frame_ticks_timers_once() {
+        let mut cons = console_with(&[]);
+        cons.delay_timer = 2;
+        cons.sound_timer = 1;
+        cons.load_rom_bytes(&[0x12, 0x00]);
+
+        cons.step(false, false);
+        assert_eq!(cons.delay_timer, 2);
+
+        assert_eq!(cons.run_frame(10, false), Frame { draw: false, sound: true });
+        assert_eq!(cons.delay_timer, 1);
+        assert_eq!(cons.sound_timer, 0);
+
+        assert_eq!(cons.run_frame(10, false), Frame { draw: false, sound: false });
+        assert_eq!(cons.delay_timer, 0);
+    }
+
+    #[test]
+    fn sound_timer_beeps_for_its_number_of_frames() {
+        let mut cons = console_with(&[(0, 5)]);
+        cons.load_rom_bytes(&[
+            0xF0, 0x18, // 0x200: LD ST, V0
+            0x12, 0x02, // 0x202: JP 0x202
+        ]);
+
+        let frames: Vec<bool> = (0..8).map(|_| cons.run_frame(3, false).sound).collect();
+        assert_eq!(frames, [true, true, true, true, true, false, false, false]);
+    }
+
+    #[test]
+    fn frame_instructions_add_up_to_the_speed() {
+        assert_eq!(frame_instructions(720, 0), 12);
+        assert_eq!((0..60).map(|f| frame_instructions(700, f)).sum::<usize>(), 700);
+        assert_eq!((0..120).map(|f| frame_instructions(30, f)).sum::<usize>(), 60);
+    }
+
+    #[test]
+    fn ldf_points_to_the_digit() {
+        let mut cons = console_with(&[(1, 0xB)]);
+        step(&mut cons, 0xF129);
+        assert_eq!(cons.i, font::digit_addr(font::FONT_ADDR, 0xB));
+
+        let addr = cons.i as usize;
+        assert_eq!(cons.memory[addr..addr + 5], font::FONT[0xB]);
+    }
+
+    #[test]
+    fn set_font_replaces_the_digits() {
+        let mut cons = console_with(&[(1, 0x4)]);
+        cons.set_font(&font::VIP_FONT);
+        step(&mut cons, 0xF129);
+
+        let addr = cons.i as usize;
+        assert_eq!(cons.memory[addr..addr + 5], [0xA0, 0xA0, 0xF0, 0x20, 0x20]);
+    }
+
+    /// Output which can't be written to, like a full disk
+    struct FullDisk;
+
+    impl std::io::Write for FullDisk {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(io::Error::other("no space left"))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn trace_errors_stop_tracing() {
+        let mut cons = Console::new();
+        cons.load_rom_bytes(&[0x12, 0x00]);
+        let tracer = TraceWriter::new(Box::new(FullDisk), crate::emu::trace::TraceFormat::Text);
+        cons.set_tracer(Some(tracer.unwrap()));
+
+        cons.step(false, false);
+        assert!(cons.tracer.is_none());
+        cons.step(false, false);
+        assert_eq!(cons.cycles, 2);
+    }
+
+    #[test]
+    fn shorter_roms_clear_the_program_area() {
+        let mut cons = Console::new();
+        cons.load_rom_bytes(&[0x60, 0x07, 0x12, 0x00]);
+        cons.write_memory(0x300, 0xAA);
+        cons.load_rom_bytes(&[0x12, 0x00]);
+
+        assert_eq!(cons.memory[0x200..0x204], [0x12, 0x00, 0, 0]);
+        assert_eq!(cons.memory[0x300], 0);
+        assert_eq!(cons.memory[0x50..0x55], font::FONT[0]);
+    }
+
+    #[test]
+    fn reset_restarts_the_rom() {
+        let mut cons = console_with(&[(1, 0x4)]);
+        cons.set_font(&font::VIP_FONT);
+        cons.load_rom_bytes(&[0x60, 0x07, 0x12, 0x00]);
+        cons.flags[0] = 9;
+        step(&mut cons, 0xF129);
+        step(&mut cons, 0xD115);
+        cons.write_memory(0x300, 0xAA);
+
+        cons.reset();
+
+        assert_eq!(cons.pc, START);
+        assert_eq!(cons.i, 0);
+        assert_eq!(cons.v, [0; 16]);
+        assert!(lit_pixels(&cons).is_empty());
+        assert_eq!(cons.memory[0x200..0x206], [0x60, 0x07, 0x12, 0x00, 0, 0]);
+        assert_eq!(cons.memory[0x300], 0);
+        assert_eq!(cons.memory[0x50..0x55], font::VIP_FONT[0]);
+        assert_eq!(cons.flags[0], 9);
+
+        cons.reload_rom(&[0x61, 0x01]);
+        assert_eq!(cons.memory[0x200..0x204], [0x61, 0x01, 0, 0]);
+    }
+
+    #[test]
+    fn machine_sets_the_memory_layout() {
+        let machine = Machine {
+            start_addr: 0x600,
+            font_addr: 0x100,
+            reserved: 0x600,
+            memory_size: 0x800,
+        };
+        let mut cons = Console::with_machine(machine);
+        cons.load_rom_bytes(&[0xF1, 0x29]);
+
+        assert_eq!(cons.memory.len(), 0x800);
+        assert_eq!(cons.pc, 0x600);
+        assert_eq!(cons.memory[0x100..0x105], font::FONT[0]);
+
+        cons.v[1] = 2;
+        cons.step(false, false);
+        assert_eq!(cons.i, 0x10A);
+        assert_eq!(cons.i_addr(0x700), 0x00A);
+    }
+
+    #[test]
+    fn rpl_flags_store_and_load() {
+        let mut cons = console_with(&[(0, 1), (1, 2), (2, 3)]);
+        step(&mut cons, 0xF175);
+        assert_eq!(cons.flags()[..3], [1, 2, 0]);
+
+        cons.v = [0; 16];
+        cons.set_flags(&[9; 16]);
+        step(&mut cons, 0xFF85);
+        assert_eq!(cons.v, [9; 16]);
+    }
+
+    #[test]
+    fn ldb_bcd() {
+        for &(val, digits) in &[(0, [0, 0, 0]), (7, [0, 0, 7]), (42, [0, 4, 2]), (255, [2, 5, 5])] {
+            let mut cons = console_with(&[(1, val)]);
+            cons.i = 0x300;
+            step(&mut cons, 0xF133);
+
+            assert_eq!(cons.memory[0x300..0x303], digits);
+            assert_eq!(cons.i, 0x300);
+        }
+    }
+
+    #[test]
+    fn ldix_and_ldxi() {
+        let mut cons = console_with(&[(0, 1), (1, 2), (2, 3), (3, 4)]);
+        cons.i = 0x300;
+        step(&mut cons, 0xF255);
+        assert_eq!(cons.memory[0x300..0x304], [1, 2, 3, 0]);
+
+        let mut cons = console_with(&[]);
+        cons.memory[0x300..0x304].copy_from_slice(&[5, 6, 7, 8]);
+        cons.i = 0x300;
+        step(&mut cons, 0xF265);
+        assert_eq!(cons.v[..4], [5, 6, 7, 0]);
+    }
+
+    #[test]
+    fn stack_is_circular() {
+        let mut cons = console_with(&[]);
+        for _ in 0..STACK_SIZE + 1 {
+            step(&mut cons, 0x2200);
+        }
+        assert_eq!(cons.sp, 1);
+
+        let mut cons = console_with(&[]);
+        step(&mut cons, 0x00EE);
+        assert_eq!(cons.sp, STACK_SIZE - 1);
+    }
+
+    #[test]
+    fn addresses_wrap_around_the_memory() {
+        let mut cons = console_with(&[(1, 255)]);
+        cons.i = 0xFFF;
+        step(&mut cons, 0xF133);
+        assert_eq!(cons.memory[0xFFF], 2);
+        assert_eq!(cons.memory[0x000..0x002], [5, 5]);
+
+        let mut cons = console_with(&[]);
+        cons.memory[0xFFE] = 0x80;
+        cons.memory[0xFFF] = 0x40;
+        cons.memory[0x000] = 0x20;
+        cons.i = 0xFFE;
+        step(&mut cons, 0xD00F);
+        assert_eq!(cons.buffer[0..4], [0x80 << 56, 0x40 << 56, 0x20 << 56, 0]);
+
+        let values: Vec<u8> = (1..=16).collect();
+        cons.v.copy_from_slice(&values);
+        cons.i = 0xFFE;
+        step(&mut cons, 0xFF55);
+        assert_eq!(cons.memory[0xFFE..], values[0..2]);
+        assert_eq!(cons.memory[0x000..0x00E], values[2..]);
+
+        cons.v = [0; 16];
+        cons.i = 0xFFE;
+        step(&mut cons, 0xFF65);
+        assert_eq!(cons.v[..], values[..]);
+
+        let mut cons = console_with(&[]);
+        cons.memory[0xFFF] = 0x12;
+        cons.memory[0x000] = 0x34;
+        cons.pc = 0xFFF;
+        cons.step(false, false);
+        assert_eq!(cons.pc, 0x234);
+    }
+
+    #[test]
+    fn skips_and_waits_wrap_around_the_largest_memory() {
+        let machine = Machine { memory_size: 0x10000, ..Machine::default() };
+        let mut cons = Console::with_machine(machine);
+
+        // SE V0, 0 at 0xFFFC skips the instruction at 0xFFFE
+        cons.memory[0xFFFC] = 0x30;
+        cons.pc = 0xFFFC;
+        cons.step(false, false);
+        assert_eq!(cons.pc, 0x0000);
+
+        // LD V0, K at 0xFFFE waits without a key down
+        cons.memory[0xFFFE] = 0xF0;
+        cons.memory[0xFFFF] = 0x0A;
+        cons.pc = 0xFFFE;
+        cons.step(false, false);
+        assert_eq!(cons.pc, 0xFFFE);
+    }
+
+    #[test]
+    fn key_index_uses_the_low_nibble() {
+        let mut cons = console_with(&[(1, 0xFA)]);
+        cons.set_key(0xA, true);
+        step(&mut cons, 0xE19E);
+        assert_eq!(cons.pc, START + 4);
+    }
+
+    #[test]
+    fn self_modifying_code_invalidates_the_cache() {
+        let mut cons = console_with(&[(0, 0x12), (1, 0x34)]);
+        cons.load_rom_bytes(&[
+            0x00, 0xE0, // 0x200: CLS
+            0xA2, 0x00, // 0x202: LD I, 0x200
+            0xF1, 0x55, // 0x204: LD [I], V1
+            0x12, 0x00, // 0x206: JP 0x200
+        ]);
+
+        for _ in 0..4 {
+            cons.step(false, false);
+        }
+        assert_eq!(cons.pc, 0x200);
+
+        // 0x200 now contains JP 0x234
+        cons.step(false, false);
+        assert_eq!(cons.pc, 0x234);
+    }
+
+    #[test]
+    fn shift_quirk_off_shifts_vy() {
+        let mut cons = console_with(&[(1, 0x81)]);
+        cons.set_quirks(Quirks { shift: false, ..Quirks::default() });
+
+        step(&mut cons, 0x801E);
+        assert_eq!(cons.v[0], 0x02);
+        assert_eq!(cons.v[0xF], 1);
+    }
+
+    #[test]
+    fn jump_quirk_uses_vx() {
+        let mut cons = console_with(&[(0, 1), (2, 4)]);
+        cons.set_quirks(Quirks { jump: true, ..Quirks::default() });
+        step(&mut cons, 0xB234);
+        assert_eq!(cons.pc, 0x238);
+    }
+
+    #[test]
+    fn memory_quirks_move_i() {
+        let mut cons = console_with(&[]);
+        cons.i = 0x300;
+        cons.set_quirks(Quirks { memory_leave_i_unchanged: false, ..Quirks::default() });
+        step(&mut cons, 0xF255);
+        assert_eq!(cons.i, 0x303);
+
+        cons.set_quirks(Quirks {
+            memory_leave_i_unchanged: false,
+            memory_increment_by_x: true,
+            ..Quirks::default()
+        });
+        step(&mut cons, 0xF265);
+        assert_eq!(cons.i, 0x305);
+    }
+
+    #[test]
+    fn logic_quirk_resets_vf() {
+        let mut cons = console_with(&[(0xF, 1)]);
+        cons.set_quirks(Quirks { logic: true, ..Quirks::default() });
+        step(&mut cons, 0x8011);
+        assert_eq!(cons.v[0xF], 0);
+    }
+
+    #[test]
+    fn wrap_quirk_wraps_sprites() {
+        let mut cons = console_with(&[(0, 62), (1, 31)]);
+        cons.set_quirks(Quirks { wrap: true, ..Quirks::default() });
+        cons.memory[0x300] = 0xC0;
+        cons.memory[0x301] = 0x80;
+        cons.i = 0x300;
+
+        // Two rows, the second one wrapping to the top of the screen
+        step(&mut cons, 0xD012);
+        assert_eq!(lit_pixels(&cons), vec![(62, 0), (62, 31), (63, 31)]);
+
+        cons.v[0] = 63;
+        cons.v[1] = 5;
+        step(&mut cons, 0xD011);
+        assert_eq!(lit_pixels(&cons), vec![(62, 0), (0, 5), (63, 5), (62, 31), (63, 31)]);
+    }
+
+    #[test]
+    fn vblank_quirk_ends_the_frame() {
+        let mut cons = console_with(&[]);
+        cons.set_quirks(Quirks { vblank: true, ..Quirks::default() });
+        cons.load_rom_bytes(&[
+            0xD0, 0x01, // 0x200: DRW V0, V0, 1
+            0x12, 0x00, // 0x202: JP 0x200
+        ]);
+
+        cons.run_frame(10, false);
+        assert_eq!(cons.pc, 0x202);
+        cons.run_frame(10, false);
+        assert_eq!(cons.pc, 0x202);
+    }
+
+    #[test]
+    fn rom_data_is_unpacked() {
+        let mut cons = console_with(&[]);
+        let gif = cartridge_gif(r#"{"program": ": main v0 := 7"}"#);
+        cons.load_rom_data(&gif).unwrap();
+        assert_eq!(&cons.memory[0x200..0x204], [0x12, 0x02, 0x60, 0x07]);
+
+        cons.load_rom_data(&[0x00, 0xE0]).unwrap();
+        assert_eq!(&cons.memory[0x200..0x202], [0x00, 0xE0]);
+        assert!(cons.load_rom_data(b"GIF89a").is_err());
+    }
+}
@@ -1,15 +1,21 @@
 //! Contains the font of the console
 
-/// Base address in the memory for the font
+/// Default address in the memory for the font
 pub const FONT_ADDR: u16 = 0x50;
 
-/// Returns the memory address of a given digit
-pub fn digit_addr(digit: u8) -> u16 {
-    FONT_ADDR + digit as u16 * 5
+/// Returns the memory address of a given digit, for a font at base
+pub fn digit_addr(base: u16, digit: u8) -> u16 {
+    base + digit as u16 * 5
 }
 
-/// Font of the console (digits from 0 to F)
-pub const FONT: [[u8; 5]; 16] = [
+/// A font: the sprites of the digits from 0 to F, 5 rows each
+pub type Font = [[u8; 5]; 16];
+
+/// Size of a font in the memory
+pub const FONT_SIZE: usize = 16 * 5;
+
+/// Default font of the console (digits from 0 to F)
+pub const FONT: Font = [
     F0, F1, F2, F3, F4, F5, F6, F7, F8, F9,
     FA, FB, FC, FD, FE, FF,
 ];
@@ -156,4 +162,130 @@ const FF: [u8; 5] = [
     0xF0,
     0x80,
     0x80,
-];
\ No newline at end of file
+];
+/// Font of the COSMAC VIP interpreter
+pub const VIP_FONT: Font = [
+    [0xF0, 0x90, 0x90, 0x90, 0xF0], // 0
+    [0x60, 0x20, 0x20, 0x20, 0x70], // 1
+    [0xF0, 0x10, 0xF0, 0x80, 0xF0], // 2
+    [0xF0, 0x10, 0xF0, 0x10, 0xF0], // 3
+    [0xA0, 0xA0, 0xF0, 0x20, 0x20], // 4
+    [0xF0, 0x80, 0xF0, 0x10, 0xF0], // 5
+    [0xF0, 0x80, 0xF0, 0x90, 0xF0], // 6
+    [0xF0, 0x10, 0x10, 0x10, 0x10], // 7
+    [0xF0, 0x90, 0xF0, 0x90, 0xF0], // 8
+    [0xF0, 0x90, 0xF0, 0x10, 0xF0], // 9
+    [0xF0, 0x90, 0xF0, 0x90, 0x90], // A
+    [0xF0, 0x50, 0x70, 0x50, 0xF0], // B
+    [0xF0, 0x80, 0x80, 0x80, 0xF0], // C
+    [0xF0, 0x50, 0x50, 0x50, 0xF0], // D
+    [0xF0, 0x80, 0xF0, 0x80, 0xF0], // E
+    [0xF0, 0x80, 0xF0, 0x80, 0x80], // F
+];
+
+/// Font of the DREAM 6800 (CHIPOS), 3 pixels wide
+pub const DREAM_6800_FONT: Font = [
+    [0xE0, 0xA0, 0xA0, 0xA0, 0xE0], // 0
+    [0x40, 0x40, 0x40, 0x40, 0x40], // 1
+    [0xE0, 0x20, 0xE0, 0x80, 0xE0], // 2
+    [0xE0, 0x20, 0xE0, 0x20, 0xE0], // 3
+    [0x80, 0xA0, 0xA0, 0xE0, 0x20], // 4
+    [0xE0, 0x80, 0xE0, 0x20, 0xE0], // 5
+    [0xE0, 0x80, 0xE0, 0xA0, 0xE0], // 6
+    [0xE0, 0x20, 0x20, 0x20, 0x20], // 7
+    [0xE0, 0xA0, 0xE0, 0xA0, 0xE0], // 8
+    [0xE0, 0xA0, 0xE0, 0x20, 0xE0], // 9
+    [0xE0, 0xA0, 0xE0, 0xA0, 0xA0], // A
+    [0xC0, 0xA0, 0xE0, 0xA0, 0xC0], // B
+    [0xE0, 0x80, 0x80, 0x80, 0xE0], // C
+    [0xC0, 0xA0, 0xA0, 0xA0, 0xC0], // D
+    [0xE0, 0x80, 0xE0, 0x80, 0xE0], // E
+    [0xE0, 0x80, 0xC0, 0x80, 0x80], // F
+];
+
+/// Font of the ETI-660, 3 pixels wide
+pub const ETI_660_FONT: Font = [
+    [0xE0, 0xA0, 0xA0, 0xA0, 0xE0], // 0
+    [0x20, 0x20, 0x20, 0x20, 0x20], // 1
+    [0xE0, 0x20, 0xE0, 0x80, 0xE0], // 2
+    [0xE0, 0x20, 0xE0, 0x20, 0xE0], // 3
+    [0xA0, 0xA0, 0xE0, 0x20, 0x20], // 4
+    [0xE0, 0x80, 0xE0, 0x20, 0xE0], // 5
+    [0xE0, 0x80, 0xE0, 0xA0, 0xE0], // 6
+    [0xE0, 0x20, 0x20, 0x20, 0x20], // 7
+    [0xE0, 0xA0, 0xE0, 0xA0, 0xE0], // 8
+    [0xE0, 0xA0, 0xE0, 0x20, 0xE0], // 9
+    [0xE0, 0xA0, 0xE0, 0xA0, 0xA0], // A
+    [0x80, 0x80, 0xE0, 0xA0, 0xE0], // B
+    [0xE0, 0x80, 0x80, 0x80, 0xE0], // C
+    [0x20, 0x20, 0xE0, 0xA0, 0xE0], // D
+    [0xE0, 0x80, 0xE0, 0x80, 0xE0], // E
+    [0xE0, 0x80, 0xC0, 0x80, 0x80], // F
+];
+
+/// Font of the FISH-N-CHIPS interpreter, with rounded digits
+pub const FISH_N_CHIPS_FONT: Font = [
+    [0x60, 0xA0, 0xA0, 0xA0, 0xC0], // 0
+    [0x40, 0xC0, 0x40, 0x40, 0xE0], // 1
+    [0xC0, 0x20, 0x40, 0x80, 0xE0], // 2
+    [0xC0, 0x20, 0x40, 0x20, 0xC0], // 3
+    [0x20, 0xA0, 0xE0, 0x20, 0x20], // 4
+    [0xE0, 0x80, 0xC0, 0x20, 0xC0], // 5
+    [0x40, 0x80, 0xC0, 0xA0, 0x40], // 6
+    [0xE0, 0x20, 0x60, 0x40, 0x40], // 7
+    [0x40, 0xA0, 0x40, 0xA0, 0x40], // 8
+    [0x40, 0xA0, 0x60, 0x20, 0x40], // 9
+    [0x40, 0xA0, 0xE0, 0xA0, 0xA0], // A
+    [0xC0, 0xA0, 0xC0, 0xA0, 0xC0], // B
+    [0x60, 0x80, 0x80, 0x80, 0x60], // C
+    [0xC0, 0xA0, 0xA0, 0xA0, 0xC0], // D
+    [0xE0, 0x80, 0xC0, 0x80, 0xE0], // E
+    [0xE0, 0x80, 0xC0, 0x80, 0x80], // F
+];
+
+/// Returns the built-in font of the given name: default, vip, dream6800,
+/// eti660 or fish
+pub fn by_name(name: &str) -> Option<&'static Font> {
+    match name.to_lowercase().as_str() {
+        "default" => Some(&FONT),
+        "vip" => Some(&VIP_FONT),
+        "dream6800" => Some(&DREAM_6800_FONT),
+        "eti660" => Some(&ETI_660_FONT),
+        "fish" => Some(&FISH_N_CHIPS_FONT),
+        _ => None,
+    }
+}
+
+/// Reads a font from a file of 80 bytes, the 5 rows of each digit from 0
+/// to F
+pub fn from_bytes(bytes: &[u8]) -> Result<Font, String> {
+    if bytes.len() != FONT_SIZE {
+        return Err(format!(
+            "a font file must have {} bytes, not {}",
+            FONT_SIZE,
+            bytes.len()
+        ));
+    }
+
+    let mut font = [[0; 5]; 16];
+    for (digit, rows) in font.iter_mut().zip(bytes.chunks_exact(5)) {
+        digit.copy_from_slice(rows);
+    }
+
+    Ok(font)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fonts_by_name_and_from_files() {
+        assert_eq!(by_name("VIP"), Some(&VIP_FONT));
+        assert_eq!(by_name("octo"), None);
+
+        let bytes: Vec<u8> = FISH_N_CHIPS_FONT.iter().flatten().copied().collect();
+        assert_eq!(from_bytes(&bytes), Ok(FISH_N_CHIPS_FONT));
+        assert!(from_bytes(&bytes[..79]).is_err());
+    }
+}
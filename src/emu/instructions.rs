@@ -1,9 +1,10 @@
 //! Code used to decode the instructions
 
-#[allow(clippy::upper_case_acronyms)]
+use std::fmt;
 
 /// Enum used to represent CPU instructions
-#[derive(Debug)]
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Instr {
     /// Clear screen
     CLS,
@@ -73,6 +74,10 @@ pub enum Instr {
     LDIX(u8),
     /// Load from memory
     LDXI(u8),
+    /// Store into the RPL user flags (SUPER-CHIP)
+    LDRX(u8),
+    /// Load from the RPL user flags (SUPER-CHIP)
+    LDXR(u8),
 
     /// Every other instruction
     NIL,
@@ -100,7 +105,7 @@ impl Instr {
             0x2 => return CALL(nnn),
             0x3 => return SE(x, nn),
             0x4 => return SNE(x, nn),
-            0x5 => return SEV(x, y),
+            0x5 if n == 0 => return SEV(x, y),
             0x6 => return LD(x, nn),
             0x7 => return ADD(x, nn),
 
@@ -117,7 +122,7 @@ impl Instr {
                 _ => ()
             },
 
-            0x9 => return SNEV(x, y),
+            0x9 if n == 0 => return SNEV(x, y),
             0xA => return LDI(nnn),
             0xB => return JPV(x, nnn),
             0xC => return RND(x, nn),
@@ -139,6 +144,8 @@ impl Instr {
                 0x33 => return LDB(x),
                 0x55 => return LDIX(x),
                 0x65 => return LDXI(x),
+                0x75 => return LDRX(x),
+                0x85 => return LDXR(x),
                 _ => (),
             }
             _ => ()
@@ -147,3 +154,125 @@ impl Instr {
         Instr::NIL
     }
 }
+
+impl Instr {
+    /// Encodes an instruction into bits
+    /// Returns None for NIL, which stands for every unknown instruction
+    pub fn encode(self) -> Option<u16> {
+        use crate::emu::instructions::Instr::*;
+
+        /// Puts the arguments into the nibbles of an opcode
+        fn xy(op: u16, x: u8, y: u8, n: u8) -> u16 {
+            op | (x as u16) << 8 | (y as u16) << 4 | n as u16
+        }
+
+        /// Puts a register and a byte into an opcode
+        fn xnn(op: u16, x: u8, nn: u8) -> u16 {
+            op | (x as u16) << 8 | nn as u16
+        }
+
+        let enc = match self {
+            CLS => 0x00E0,
+            RET => 0x00EE,
+            JP(nnn) => 0x1000 | nnn,
+            CALL(nnn) => 0x2000 | nnn,
+            SE(x, nn) => xnn(0x3000, x, nn),
+            SNE(x, nn) => xnn(0x4000, x, nn),
+            SEV(x, y) => xy(0x5000, x, y, 0),
+            LD(x, nn) => xnn(0x6000, x, nn),
+            ADD(x, nn) => xnn(0x7000, x, nn),
+            LDV(x, y) => xy(0x8000, x, y, 0x0),
+            OR(x, y) => xy(0x8000, x, y, 0x1),
+            AND(x, y) => xy(0x8000, x, y, 0x2),
+            XOR(x, y) => xy(0x8000, x, y, 0x3),
+            ADDV(x, y) => xy(0x8000, x, y, 0x4),
+            SUB(x, y) => xy(0x8000, x, y, 0x5),
+            SHR(x, y) => xy(0x8000, x, y, 0x6),
+            SUBN(x, y) => xy(0x8000, x, y, 0x7),
+            SHL(x, y) => xy(0x8000, x, y, 0xE),
+            SNEV(x, y) => xy(0x9000, x, y, 0),
+            LDI(nnn) => 0xA000 | nnn,
+            JPV(_, nnn) => 0xB000 | nnn,
+            RND(x, nn) => xnn(0xC000, x, nn),
+            DRW(x, y, n) => xy(0xD000, x, y, n),
+            SKP(x) => xnn(0xE000, x, 0x9E),
+            SKNP(x) => xnn(0xE000, x, 0xA1),
+            LDXT(x) => xnn(0xF000, x, 0x07),
+            LDK(x) => xnn(0xF000, x, 0x0A),
+            LDTX(x) => xnn(0xF000, x, 0x15),
+            LDS(x) => xnn(0xF000, x, 0x18),
+            ADDI(x) => xnn(0xF000, x, 0x1E),
+            LDF(x) => xnn(0xF000, x, 0x29),
+            LDB(x) => xnn(0xF000, x, 0x33),
+            LDIX(x) => xnn(0xF000, x, 0x55),
+            LDXI(x) => xnn(0xF000, x, 0x65),
+            LDRX(x) => xnn(0xF000, x, 0x75),
+            LDXR(x) => xnn(0xF000, x, 0x85),
+            NIL => return None,
+        };
+
+        Some(enc)
+    }
+}
+
+impl fmt::Display for Instr {
+    /// Writes the mnemonic of the instruction
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use crate::emu::instructions::Instr::*;
+
+        match *self {
+            CLS => write!(f, "CLS"),
+            RET => write!(f, "RET"),
+            JP(nnn) => write!(f, "JP {:#05x}", nnn),
+            CALL(nnn) => write!(f, "CALL {:#05x}", nnn),
+            SE(x, nn) => write!(f, "SE V{:X}, {:#04x}", x, nn),
+            SNE(x, nn) => write!(f, "SNE V{:X}, {:#04x}", x, nn),
+            SEV(x, y) => write!(f, "SE V{:X}, V{:X}", x, y),
+            LD(x, nn) => write!(f, "LD V{:X}, {:#04x}", x, nn),
+            ADD(x, nn) => write!(f, "ADD V{:X}, {:#04x}", x, nn),
+            LDV(x, y) => write!(f, "LD V{:X}, V{:X}", x, y),
+            OR(x, y) => write!(f, "OR V{:X}, V{:X}", x, y),
+            AND(x, y) => write!(f, "AND V{:X}, V{:X}", x, y),
+            XOR(x, y) => write!(f, "XOR V{:X}, V{:X}", x, y),
+            ADDV(x, y) => write!(f, "ADD V{:X}, V{:X}", x, y),
+            SUB(x, y) => write!(f, "SUB V{:X}, V{:X}", x, y),
+            SHR(x, y) => write!(f, "SHR V{:X}, V{:X}", x, y),
+            SUBN(x, y) => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            SHL(x, y) => write!(f, "SHL V{:X}, V{:X}", x, y),
+            SNEV(x, y) => write!(f, "SNE V{:X}, V{:X}", x, y),
+            LDI(nnn) => write!(f, "LD I, {:#05x}", nnn),
+            JPV(_, nnn) => write!(f, "JP V0, {:#05x}", nnn),
+            RND(x, nn) => write!(f, "RND V{:X}, {:#04x}", x, nn),
+            DRW(x, y, n) => write!(f, "DRW V{:X}, V{:X}, {}", x, y, n),
+            SKP(x) => write!(f, "SKP V{:X}", x),
+            SKNP(x) => write!(f, "SKNP V{:X}", x),
+            LDXT(x) => write!(f, "LD V{:X}, DT", x),
+            LDK(x) => write!(f, "LD V{:X}, K", x),
+            LDTX(x) => write!(f, "LD DT, V{:X}", x),
+            LDS(x) => write!(f, "LD ST, V{:X}", x),
+            ADDI(x) => write!(f, "ADD I, V{:X}", x),
+            LDF(x) => write!(f, "LD F, V{:X}", x),
+            LDB(x) => write!(f, "LD B, V{:X}", x),
+            LDIX(x) => write!(f, "LD [I], V{:X}", x),
+            LDXI(x) => write!(f, "LD V{:X}, [I]", x),
+            LDRX(x) => write!(f, "LD R, V{:X}", x),
+            LDXR(x) => write!(f, "LD V{:X}, R", x),
+            NIL => write!(f, "???"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decoded_instructions_encode_back_to_their_opcode() {
+        for op in 0..=u16::MAX {
+            match Instr::decode(op) {
+                Instr::NIL => (),
+                instr => assert_eq!(instr.encode(), Some(op), "{:04x} - {:?}", op, instr),
+            }
+        }
+    }
+}
@@ -0,0 +1,106 @@
+//! Memory layouts of the computers which ran CHIP-8
+//!
+//! CHIP-8 programs were written for several computers, which loaded them
+//! at different addresses and had different amounts of memory.
+
+use crate::emu::font::{FONT_ADDR, FONT_SIZE};
+use std::str::FromStr;
+
+/// Memory layout of a computer
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Machine {
+    /// Address where ROMs are loaded and executed from
+    pub start_addr: u16,
+    /// Address of the font
+    pub font_addr: u16,
+    /// End of the area reserved to the interpreter, which starts at 0
+    /// The font is stored there
+    pub reserved: u16,
+    /// Size of the memory, a power of 2
+    pub memory_size: usize,
+}
+
+impl Default for Machine {
+    /// COSMAC VIP with 4 KiB of memory, the layout of most interpreters
+    fn default() -> Self {
+        Machine {
+            start_addr: 0x200,
+            font_addr: FONT_ADDR,
+            reserved: 0x200,
+            memory_size: 0x1000,
+        }
+    }
+}
+
+impl FromStr for Machine {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "chip8" => Ok(Machine::default()),
+            // COSMAC VIP with its base 2 KiB of memory
+            "vip2k" => Ok(Machine { memory_size: 0x800, ..Machine::default() }),
+            // The ETI-660 loads programs after its interpreter, at 0x600
+            "eti660" => Ok(Machine {
+                start_addr: 0x600,
+                reserved: 0x600,
+                ..Machine::default()
+            }),
+            _ => Err(format!("unknown machine '{}' (expected chip8, vip2k or eti660)", s)),
+        }
+    }
+}
+
+impl Machine {
+    /// Returns the size of the largest ROM fitting into the memory
+    pub fn max_rom_size(&self) -> usize {
+        self.memory_size - self.start_addr as usize
+    }
+
+    /// Checks that the layout is consistent
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.memory_size.is_power_of_two() || !(0x200..=0x10000).contains(&self.memory_size) {
+            return Err(format!(
+                "invalid memory size {:#x} (expected a power of 2 from 0x200 to 0x10000)",
+                self.memory_size
+            ));
+        }
+        if self.reserved > self.start_addr || self.start_addr as usize >= self.memory_size {
+            return Err(format!(
+                "the start address {:#x} must be after the reserved area and in the memory",
+                self.start_addr
+            ));
+        }
+        if self.font_addr as usize + FONT_SIZE > self.reserved as usize {
+            return Err(format!(
+                "the font at {:#x} must fit into the reserved area",
+                self.font_addr
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn named_machines_are_valid() {
+        for name in ["chip8", "vip2k", "ETI660"] {
+            let machine: Machine = name.parse().unwrap();
+            assert_eq!(machine.validate(), Ok(()));
+        }
+        assert_eq!("eti660".parse::<Machine>().unwrap().max_rom_size(), 0xA00);
+        assert!("hp48".parse::<Machine>().is_err());
+    }
+
+    #[test]
+    fn inconsistent_layouts_are_refused() {
+        let machine = Machine::default();
+        assert!(Machine { memory_size: 3000, ..machine }.validate().is_err());
+        assert!(Machine { start_addr: 0x100, ..machine }.validate().is_err());
+        assert!(Machine { font_addr: 0x1D0, ..machine }.validate().is_err());
+    }
+}
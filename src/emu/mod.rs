@@ -2,5 +2,8 @@
 
 pub mod console;
 pub mod instructions;
+pub mod machine;
 pub mod box_border;
-mod font;
\ No newline at end of file
+pub mod quirks;
+pub mod trace;
+pub mod font;
\ No newline at end of file
@@ -0,0 +1,57 @@
+//! Behaviours which differ between the interpreters of the various platforms
+//!
+//! The names follow the quirks of the community CHIP-8 database.
+
+use std::collections::HashMap;
+
+/// Quirks of the interpreter
+/// The default keeps the historical behaviour of this emulator
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quirks {
+    /// 8xy6 and 8xyE shift vx in place, instead of shifting vy into vx
+    pub shift: bool,
+    /// Fx55 and Fx65 increment I by x, instead of x + 1
+    pub memory_increment_by_x: bool,
+    /// Fx55 and Fx65 leave I unchanged
+    pub memory_leave_i_unchanged: bool,
+    /// Sprites wrap around the edges of the screen, instead of being clipped
+    pub wrap: bool,
+    /// Bxnn jumps to xnn + vx, instead of nnn + v0
+    pub jump: bool,
+    /// Drawing waits for the start of the next frame
+    pub vblank: bool,
+    /// 8xy1, 8xy2 and 8xy3 reset vf
+    pub logic: bool,
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Quirks {
+            shift: true,
+            memory_increment_by_x: false,
+            memory_leave_i_unchanged: true,
+            wrap: false,
+            jump: false,
+            vblank: false,
+            logic: false,
+        }
+    }
+}
+
+impl Quirks {
+    /// Creates quirks from their names in the community database
+    /// Missing quirks are disabled, unknown ones are ignored
+    pub fn from_names(quirks: &HashMap<String, bool>) -> Self {
+        let quirk = |name: &str| quirks.get(name).copied().unwrap_or(false);
+
+        Quirks {
+            shift: quirk("shift"),
+            memory_increment_by_x: quirk("memoryIncrementByX"),
+            memory_leave_i_unchanged: quirk("memoryLeaveIUnchanged"),
+            wrap: quirk("wrap"),
+            jump: quirk("jump"),
+            vblank: quirk("vblank"),
+            logic: quirk("logic"),
+        }
+    }
+}
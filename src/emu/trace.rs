@@ -0,0 +1,384 @@
+//! Execution traces, used to compare the emulator with other implementations
+//!
+//! A trace contains one record per executed instruction, holding the state
+//! of the console just before the instruction is executed.
+//!
+//! # Text format
+//!
+//! One line per record, with the following fields separated by spaces:
+//!
+//! ```text
+//! <cycle> <pc> <opcode> <v0..vf> <i> <sp> <dt> <st> ; <mnemonic>
+//! 42 0202 6a05 00010000000000000000000000000000 0000 0 00 00 ; LD VA, 0x05
+//! ```
+//!
+//! - `cycle`: number of instructions executed before this one, in decimal
+//! - `pc`: address of the instruction, 4 hex digits
+//! - `opcode`: the instruction, 4 hex digits
+//! - `v0..vf`: the 16 registers, 2 hex digits each, without separators
+//! - `i`: the I register, 4 hex digits
+//! - `sp`: the stack pointer (number of entries in the stack), 1 hex digit
+//! - `dt` and `st`: the delay and sound timers, 2 hex digits each
+//!
+//! Everything after `;` is ignored when reading, as are empty lines and
+//! lines starting with `#`. Hex digits may be upper or lower case.
+//!
+//! # Binary format
+//!
+//! The 8 bytes `C8TRACE1`, followed by records of 33 bytes, with the same
+//! fields in the same order, numbers being little endian: `cycle` (8 bytes),
+//! `pc` (2), `opcode` (2), `v0..vf` (16), `i` (2), `sp` (1), `dt` (1), `st` (1).
+
+use crate::emu::instructions::Instr;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::num::ParseIntError;
+use std::path::Path;
+
+/// Magic bytes starting a binary trace
+const MAGIC: &[u8; 8] = b"C8TRACE1";
+
+/// Size of a record in a binary trace
+const RECORD_SIZE: usize = 33;
+
+/// State of the console before the execution of an instruction
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TraceRecord {
+    /// Number of instructions executed before this one
+    pub cycle: u64,
+    /// Address of the instruction
+    pub pc: u16,
+    /// The instruction
+    pub opcode: u16,
+    /// Registers
+    pub v: [u8; 16],
+    /// I register
+    pub i: u16,
+    /// Stack pointer
+    pub sp: u8,
+    /// Delay timer
+    pub delay_timer: u8,
+    /// Sound timer
+    pub sound_timer: u8,
+}
+
+impl TraceRecord {
+    /// Returns the names of the fields which differ between two records
+    pub fn diff(&self, other: &TraceRecord) -> Vec<String> {
+        let mut fields = vec![];
+
+        if self.cycle != other.cycle {
+            fields.push("cycle".to_string());
+        }
+        if self.pc != other.pc {
+            fields.push("pc".to_string());
+        }
+        if self.opcode != other.opcode {
+            fields.push("opcode".to_string());
+        }
+        for (x, (a, b)) in self.v.iter().zip(other.v.iter()).enumerate() {
+            if a != b {
+                fields.push(format!("v{:x}", x));
+            }
+        }
+        if self.i != other.i {
+            fields.push("i".to_string());
+        }
+        if self.sp != other.sp {
+            fields.push("sp".to_string());
+        }
+        if self.delay_timer != other.delay_timer {
+            fields.push("dt".to_string());
+        }
+        if self.sound_timer != other.sound_timer {
+            fields.push("st".to_string());
+        }
+
+        fields
+    }
+
+    /// Parses a line of a text trace
+    /// Returns None for empty and comment lines
+    fn parse(line: &str) -> io::Result<Option<TraceRecord>> {
+        let line = line.split(';').next().unwrap_or("").trim();
+        if line.is_empty() || line.starts_with('#') {
+            return Ok(None);
+        }
+
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() != 8 || fields[3].len() != 32 || !fields[3].is_ascii() {
+            return Err(invalid(format!("malformed trace line: {}", line)));
+        }
+
+        let mut v = [0; 16];
+        for (x, reg) in v.iter_mut().enumerate() {
+            *reg = parse_hex(&fields[3][x * 2..x * 2 + 2], u8::from_str_radix)?;
+        }
+
+        Ok(Some(TraceRecord {
+            cycle: fields[0]
+                .parse()
+                .map_err(|_| invalid(format!("invalid cycle: {}", fields[0])))?,
+            pc: parse_hex(fields[1], u16::from_str_radix)?,
+            opcode: parse_hex(fields[2], u16::from_str_radix)?,
+            v,
+            i: parse_hex(fields[4], u16::from_str_radix)?,
+            sp: parse_hex(fields[5], u8::from_str_radix)?,
+            delay_timer: parse_hex(fields[6], u8::from_str_radix)?,
+            sound_timer: parse_hex(fields[7], u8::from_str_radix)?,
+        }))
+    }
+
+    /// Encodes the record for a binary trace
+    fn to_bytes(self) -> [u8; RECORD_SIZE] {
+        let mut bytes = [0; RECORD_SIZE];
+        bytes[0..8].copy_from_slice(&self.cycle.to_le_bytes());
+        bytes[8..10].copy_from_slice(&self.pc.to_le_bytes());
+        bytes[10..12].copy_from_slice(&self.opcode.to_le_bytes());
+        bytes[12..28].copy_from_slice(&self.v);
+        bytes[28..30].copy_from_slice(&self.i.to_le_bytes());
+        bytes[30] = self.sp;
+        bytes[31] = self.delay_timer;
+        bytes[32] = self.sound_timer;
+        bytes
+    }
+
+    /// Decodes a record of a binary trace
+    fn from_bytes(bytes: &[u8; RECORD_SIZE]) -> TraceRecord {
+        let mut v = [0; 16];
+        v.copy_from_slice(&bytes[12..28]);
+
+        TraceRecord {
+            cycle: u64::from_le_bytes([
+                bytes[0], bytes[1], bytes[2], bytes[3],
+                bytes[4], bytes[5], bytes[6], bytes[7],
+            ]),
+            pc: u16::from_le_bytes([bytes[8], bytes[9]]),
+            opcode: u16::from_le_bytes([bytes[10], bytes[11]]),
+            v,
+            i: u16::from_le_bytes([bytes[28], bytes[29]]),
+            sp: bytes[30],
+            delay_timer: bytes[31],
+            sound_timer: bytes[32],
+        }
+    }
+}
+
+impl fmt::Display for TraceRecord {
+    /// Writes the record as a line of a text trace, without the line break
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {:04x} {:04x} ", self.cycle, self.pc, self.opcode)?;
+        for reg in self.v.iter() {
+            write!(f, "{:02x}", reg)?;
+        }
+        write!(
+            f,
+            " {:04x} {:x} {:02x} {:02x} ; {}",
+            self.i,
+            self.sp,
+            self.delay_timer,
+            self.sound_timer,
+            Instr::decode(self.opcode)
+        )
+    }
+}
+
+/// Format of a trace
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TraceFormat {
+    /// One line of text per record
+    Text,
+    /// Fixed size binary records
+    Binary,
+}
+
+/// Writes the records of a trace
+pub struct TraceWriter {
+    /// Where the trace is written
+    out: Box<dyn Write>,
+    /// Format of the trace
+    format: TraceFormat,
+}
+
+impl TraceWriter {
+    /// Creates a trace writer
+    /// Writes the header of the trace if the format has one
+    pub fn new(mut out: Box<dyn Write>, format: TraceFormat) -> io::Result<Self> {
+        if format == TraceFormat::Binary {
+            out.write_all(MAGIC)?;
+        }
+
+        Ok(TraceWriter { out, format })
+    }
+
+    /// Creates a trace writer writing into a file
+    pub fn create<P: AsRef<Path>>(path: P, format: TraceFormat) -> io::Result<Self> {
+        let file = File::create(path)?;
+        Self::new(Box::new(io::BufWriter::new(file)), format)
+    }
+
+    /// Writes a record
+    pub fn write(&mut self, record: &TraceRecord) -> io::Result<()> {
+        match self.format {
+            TraceFormat::Text => writeln!(self.out, "{}", record),
+            TraceFormat::Binary => self.out.write_all(&record.to_bytes()),
+        }
+    }
+}
+
+/// Reads the records of a trace, in either format
+pub struct TraceReader<R: BufRead> {
+    /// Where the trace is read from
+    input: R,
+    /// Format of the trace, detected from its first bytes
+    format: TraceFormat,
+}
+
+impl TraceReader<BufReader<File>> {
+    /// Opens a trace file
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: BufRead> TraceReader<R> {
+    /// Creates a trace reader, detecting the format of the trace
+    pub fn new(mut input: R) -> io::Result<Self> {
+        let format = if input.fill_buf()?.starts_with(MAGIC) {
+            input.consume(MAGIC.len());
+            TraceFormat::Binary
+        } else {
+            TraceFormat::Text
+        };
+
+        Ok(TraceReader { input, format })
+    }
+
+    /// Format of the trace being read
+    pub fn format(&self) -> TraceFormat {
+        self.format
+    }
+
+    /// Reads the next record of a text trace
+    fn next_text(&mut self) -> io::Result<Option<TraceRecord>> {
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if self.input.read_line(&mut line)? == 0 {
+                return Ok(None);
+            }
+            if let Some(record) = TraceRecord::parse(&line)? {
+                return Ok(Some(record));
+            }
+        }
+    }
+
+    /// Reads the next record of a binary trace
+    fn next_binary(&mut self) -> io::Result<Option<TraceRecord>> {
+        let mut bytes = [0; RECORD_SIZE];
+        match self.input.read_exact(&mut bytes) {
+            Ok(()) => Ok(Some(TraceRecord::from_bytes(&bytes))),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for TraceReader<R> {
+    type Item = io::Result<TraceRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let next = match self.format {
+            TraceFormat::Text => self.next_text(),
+            TraceFormat::Binary => self.next_binary(),
+        };
+
+        next.transpose()
+    }
+}
+
+/// Parses a hexadecimal number of a text trace with the from_str_radix
+/// function of its type, which refuses the numbers out of its range
+fn parse_hex<T>(
+    s: &str,
+    from_str_radix: fn(&str, u32) -> Result<T, ParseIntError>,
+) -> io::Result<T> {
+    from_str_radix(s, 16).map_err(|_| invalid(format!("invalid hex number: {}", s)))
+}
+
+/// Creates an invalid data error
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Record used by the tests
+    const RECORD: TraceRecord = TraceRecord {
+        cycle: 42,
+        pc: 0x202,
+        opcode: 0x6A05,
+        v: [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0xA, 0xB, 0xC, 0xD, 0xE, 0xFF],
+        i: 0x300,
+        sp: 2,
+        delay_timer: 0x10,
+        sound_timer: 0,
+    };
+
+    #[test]
+    fn text_format() {
+        assert_eq!(
+            RECORD.to_string(),
+            "42 0202 6a05 000102030405060708090a0b0c0d0eff 0300 2 10 00 ; LD VA, 0x05"
+        );
+    }
+
+    #[test]
+    fn text_round_trip() {
+        let text = format!("# comment\n\n{}\n{}\n", RECORD, RECORD);
+        let records: Vec<_> = TraceReader::new(text.as_bytes())
+            .unwrap()
+            .collect::<io::Result<_>>()
+            .unwrap();
+
+        assert_eq!(records, vec![RECORD, RECORD]);
+    }
+
+    #[test]
+    fn malformed_text_is_refused() {
+        let text = RECORD.to_string();
+        let parse = |line: &str| TraceRecord::parse(line).map_err(|e| e.to_string());
+
+        let out_of_range = text.replacen("0202", "1FFFF", 1);
+        assert_eq!(parse(&out_of_range).unwrap_err(), "invalid hex number: 1FFFF");
+
+        let non_ascii = text.replacen("0001", "éé", 1);
+        assert!(parse(&non_ascii).unwrap_err().starts_with("malformed trace line"));
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&RECORD.to_bytes());
+        bytes.extend_from_slice(&RECORD.to_bytes());
+
+        let reader = TraceReader::new(&bytes[..]).unwrap();
+        assert_eq!(reader.format(), TraceFormat::Binary);
+
+        let records: Vec<_> = reader.collect::<io::Result<_>>().unwrap();
+        assert_eq!(records, vec![RECORD, RECORD]);
+    }
+
+    #[test]
+    fn diff_lists_fields() {
+        let mut other = RECORD;
+        other.v[3] = 0;
+        other.i = 0;
+
+        assert_eq!(RECORD.diff(&other), vec!["v3", "i"]);
+        assert!(RECORD.diff(&RECORD).is_empty());
+    }
+}
@@ -0,0 +1,115 @@
+//! Saves the RPL user flags of the ROMs between runs
+//!
+//! SUPER-CHIP games save high scores into the flags with Fx75, which the
+//! HP-48 calculators kept between runs. The flags of a ROM are stored in
+//! `chip-8-rust/flags/<sha1>` in the data directory of the user
+//! (`$XDG_DATA_HOME` on Linux).
+
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+/// Name of the directory of the emulator in the data directory
+const DATA_DIR: &str = "chip-8-rust";
+/// Name of the directory of the flags
+const FLAGS_DIR: &str = "flags";
+
+/// Flags of a ROM, as saved on the disk
+#[derive(Debug)]
+pub struct SavedFlags {
+    /// Path of the file of the flags
+    path: PathBuf,
+    /// Flags in the file
+    saved: [u8; 16],
+    /// True once saving failed, the flags aren't saved anymore
+    failed: bool,
+}
+
+impl SavedFlags {
+    /// Loads the flags of a ROM, cleared if they were never saved
+    /// Returns None if there is no data directory
+    pub fn load(rom_sha1: &str) -> Option<Self> {
+        let path = dirs::data_dir()?.join(DATA_DIR).join(FLAGS_DIR).join(rom_sha1);
+        Some(Self::load_from(path))
+    }
+
+    /// Loads the flags from the given file, cleared if it doesn't exist
+    fn load_from(path: PathBuf) -> Self {
+        let mut saved = [0; 16];
+
+        match fs::read(&path) {
+            Ok(bytes) => {
+                let len = bytes.len().min(saved.len());
+                saved[..len].copy_from_slice(&bytes[..len]);
+            },
+            Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+            Err(e) => eprintln!("Error reading the flags {}: {}", path.display(), e),
+        }
+
+        SavedFlags { path, saved, failed: false }
+    }
+
+    /// Returns the saved flags
+    pub fn flags(&self) -> &[u8; 16] {
+        &self.saved
+    }
+
+    /// Saves the flags if they changed
+    /// After an error, which is returned once, the flags aren't saved anymore
+    pub fn update(&mut self, flags: &[u8; 16]) -> io::Result<()> {
+        if self.failed || *flags == self.saved {
+            return Ok(());
+        }
+
+        if let Err(e) = self.write(flags) {
+            self.failed = true;
+            return Err(e);
+        }
+        self.saved = *flags;
+
+        Ok(())
+    }
+
+    /// Writes the flags into the file
+    fn write(&self, flags: &[u8; 16]) -> io::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(&self.path, flags)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flags_survive_restarts() {
+        let dir = std::env::temp_dir().join(format!("chip-8-rust-flags-{}", std::process::id()));
+        let path = dir.join("0123");
+
+        let mut flags = SavedFlags::load_from(path.clone());
+        assert_eq!(flags.flags(), &[0; 16]);
+
+        let mut high_score = [0; 16];
+        high_score[..3].copy_from_slice(&[1, 2, 3]);
+        flags.update(&high_score).unwrap();
+
+        let flags = SavedFlags::load_from(path);
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(flags.flags(), &high_score);
+    }
+
+    #[test]
+    fn failures_are_reported_once() {
+        let name = format!("chip-8-rust-not-a-dir-{}", std::process::id());
+        let file = std::env::temp_dir().join(name);
+        fs::write(&file, b"").unwrap();
+
+        // The parent of the flags is a file, they can't be saved
+        let mut flags = SavedFlags::load_from(file.join("0123"));
+        assert!(flags.update(&[1; 16]).is_err());
+        assert!(flags.update(&[2; 16]).is_ok());
+        fs::remove_file(&file).unwrap();
+    }
+}
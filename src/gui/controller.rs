@@ -0,0 +1,398 @@
+//! Contains functions used to interface with game controllers
+
+use sdl2::controller::{Axis, Button, GameController};
+use sdl2::GameControllerSubsystem;
+use std::collections::{HashMap, HashSet};
+
+/// Default value under which a stick is considered centered
+pub const DEFAULT_DEADZONE: i16 = 8000;
+
+/// An input of a controller which can be bound to a console key
+/// Each direction of an axis acts as a separate button
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ControllerInput {
+    /// A button
+    Button(Button),
+    /// An axis pushed towards its positive values (right or down)
+    AxisPositive(Axis),
+    /// An axis pushed towards its negative values (left or up)
+    AxisNegative(Axis),
+}
+
+impl ControllerInput {
+    /// Parses the name of an input
+    /// Buttons use the SDL names (a, dpup, leftshoulder, ...), axis
+    /// directions the SDL name of the axis followed by + or - (leftx-, ...)
+    pub fn from_name(name: &str) -> Option<ControllerInput> {
+        let name = name.to_lowercase();
+
+        if let Some(axis) = name.strip_suffix('+') {
+            return axis_from_name(axis).map(ControllerInput::AxisPositive);
+        }
+        if let Some(axis) = name.strip_suffix('-') {
+            return axis_from_name(axis).map(ControllerInput::AxisNegative);
+        }
+
+        let button = match name.as_str() {
+            "a" => Button::A,
+            "b" => Button::B,
+            "x" => Button::X,
+            "y" => Button::Y,
+            "back" => Button::Back,
+            "guide" => Button::Guide,
+            "start" => Button::Start,
+            "leftstick" => Button::LeftStick,
+            "rightstick" => Button::RightStick,
+            "leftshoulder" => Button::LeftShoulder,
+            "rightshoulder" => Button::RightShoulder,
+            "dpup" => Button::DPadUp,
+            "dpdown" => Button::DPadDown,
+            "dpleft" => Button::DPadLeft,
+            "dpright" => Button::DPadRight,
+            _ => return None,
+        };
+
+        Some(ControllerInput::Button(button))
+    }
+}
+
+/// Returns the axis of the given SDL name
+fn axis_from_name(name: &str) -> Option<Axis> {
+    match name {
+        "leftx" => Some(Axis::LeftX),
+        "lefty" => Some(Axis::LeftY),
+        "rightx" => Some(Axis::RightX),
+        "righty" => Some(Axis::RightY),
+        "lefttrigger" => Some(Axis::TriggerLeft),
+        "righttrigger" => Some(Axis::TriggerRight),
+        _ => None,
+    }
+}
+
+/// Maps the inputs of the controllers to the keys of the console
+/// Several inputs can map to the same console key
+#[derive(Debug, Clone)]
+pub struct ControllerMapping {
+    /// Console key of each bound input
+    bindings: HashMap<ControllerInput, usize>,
+    /// Value under which a stick is considered centered
+    deadzone: i16,
+}
+
+impl Default for ControllerMapping {
+    /// D-pad and left stick on 2 4 6 8, A on 5, B on 0, X on 7, Y on 9,
+    /// start on F and back on E
+    fn default() -> Self {
+        use self::ControllerInput::{AxisNegative, AxisPositive};
+
+        let bindings = [
+            (ControllerInput::Button(Button::DPadUp), 0x2),
+            (ControllerInput::Button(Button::DPadLeft), 0x4),
+            (ControllerInput::Button(Button::DPadRight), 0x6),
+            (ControllerInput::Button(Button::DPadDown), 0x8),
+            (AxisNegative(Axis::LeftY), 0x2),
+            (AxisNegative(Axis::LeftX), 0x4),
+            (AxisPositive(Axis::LeftX), 0x6),
+            (AxisPositive(Axis::LeftY), 0x8),
+            (ControllerInput::Button(Button::A), 0x5),
+            (ControllerInput::Button(Button::B), 0x0),
+            (ControllerInput::Button(Button::X), 0x7),
+            (ControllerInput::Button(Button::Y), 0x9),
+            (ControllerInput::Button(Button::Start), 0xF),
+            (ControllerInput::Button(Button::Back), 0xE),
+        ];
+
+        ControllerMapping {
+            bindings: bindings.iter().copied().collect(),
+            deadzone: DEFAULT_DEADZONE,
+        }
+    }
+}
+
+impl ControllerMapping {
+    /// Binds inputs given by their names to console keys
+    /// Console keys are hexadecimal digits, "none" unbinds the input
+    pub fn bind_names(&mut self, inputs: &HashMap<String, String>) -> Result<(), String> {
+        for (name, index) in inputs {
+            let input = ControllerInput::from_name(name)
+                .ok_or_else(|| format!("unknown controller input '{}'", name))?;
+
+            if index.eq_ignore_ascii_case("none") {
+                self.bindings.remove(&input);
+                continue;
+            }
+
+            let index = usize::from_str_radix(index, 16)
+                .ok()
+                .filter(|i| *i < 16)
+                .ok_or_else(|| format!("invalid console key '{}'", index))?;
+
+            self.bindings.insert(input, index);
+        }
+
+        Ok(())
+    }
+
+    /// Binds the d-pad, the left stick and the A and B buttons to the console
+    /// keys playing the given roles (up, down, left, right, a and b), as
+    /// hinted by the ROM database
+    pub fn bind_roles(&mut self, roles: &HashMap<String, u8>) {
+        use self::ControllerInput::{AxisNegative, AxisPositive};
+
+        for (role, index) in roles {
+            let inputs = match role.as_str() {
+                "up" => vec![ControllerInput::Button(Button::DPadUp), AxisNegative(Axis::LeftY)],
+                "down" => vec![ControllerInput::Button(Button::DPadDown), AxisPositive(Axis::LeftY)],
+                "left" => vec![ControllerInput::Button(Button::DPadLeft), AxisNegative(Axis::LeftX)],
+                "right" => vec![ControllerInput::Button(Button::DPadRight), AxisPositive(Axis::LeftX)],
+                "a" => vec![ControllerInput::Button(Button::A)],
+                "b" => vec![ControllerInput::Button(Button::B)],
+                _ => continue,
+            };
+
+            if *index < 16 {
+                for input in inputs {
+                    self.bindings.insert(input, *index as usize);
+                }
+            }
+        }
+    }
+
+    /// Sets the value under which a stick is considered centered
+    pub fn set_deadzone(&mut self, deadzone: i16) {
+        self.deadzone = deadzone;
+    }
+
+    /// Returns the console key bound to an input
+    pub fn key_index(&self, input: ControllerInput) -> Option<usize> {
+        self.bindings.get(&input).copied()
+    }
+}
+
+/// State of the inputs of the controllers
+/// Translates controller events into console key states
+#[derive(Debug)]
+pub struct PadState {
+    /// Mapping of the inputs
+    mapping: ControllerMapping,
+    /// Inputs currently held, along with the id of their controller
+    held: HashSet<(u32, ControllerInput)>,
+}
+
+impl PadState {
+    /// Creates the state of controllers using the given mapping
+    pub fn new(mapping: ControllerMapping) -> Self {
+        PadState { mapping, held: HashSet::new() }
+    }
+
+    /// Handles a button event of a controller
+    /// Returns the console key it affects, if any
+    pub fn button(&mut self, which: u32, button: Button, down: bool) -> Option<usize> {
+        self.set_input(which, ControllerInput::Button(button), down)
+    }
+
+    /// Handles an axis event of a controller
+    /// Returns the console keys it affects
+    pub fn axis(&mut self, which: u32, axis: Axis, value: i16) -> Vec<usize> {
+        let deadzone = self.mapping.deadzone;
+        let positive = value > deadzone;
+        let negative = value < deadzone.saturating_neg();
+
+        let mut keys = vec![];
+        keys.extend(self.set_input(which, ControllerInput::AxisPositive(axis), positive));
+        keys.extend(self.set_input(which, ControllerInput::AxisNegative(axis), negative));
+        keys
+    }
+
+    /// Releases every input of a controller
+    /// Returns the console keys it affects
+    pub fn release_all(&mut self, which: u32) -> Vec<usize> {
+        let inputs: Vec<_> = self
+            .held
+            .iter()
+            .filter(|(w, _)| *w == which)
+            .map(|(_, input)| *input)
+            .collect();
+
+        inputs
+            .into_iter()
+            .filter_map(|input| self.set_input(which, input, false))
+            .collect()
+    }
+
+    /// Returns true if any input bound to the console key is held
+    pub fn is_down(&self, index: usize) -> bool {
+        self.held
+            .iter()
+            .any(|(_, input)| self.mapping.key_index(*input) == Some(index))
+    }
+
+    /// Sets the state of an input
+    /// Returns the console key bound to it, if any
+    fn set_input(&mut self, which: u32, input: ControllerInput, down: bool) -> Option<usize> {
+        if down {
+            self.held.insert((which, input));
+        } else {
+            self.held.remove(&(which, input));
+        }
+
+        self.mapping.key_index(input)
+    }
+}
+
+/// Opened game controllers and the state of their inputs
+pub struct Controllers {
+    /// The game controller subsystem of SDL
+    subsystem: GameControllerSubsystem,
+    /// Opened controllers, kept open to receive their events
+    opened: Vec<GameController>,
+    /// State of the inputs
+    pub state: PadState,
+}
+
+impl Controllers {
+    /// Opens the game controller subsystem
+    /// Controllers are opened when SDL reports them as added
+    pub fn new(sdl_context: &sdl2::Sdl, mapping: ControllerMapping) -> Result<Self, String> {
+        let subsystem = sdl_context.game_controller()?;
+
+        Ok(Controllers {
+            subsystem,
+            opened: vec![],
+            state: PadState::new(mapping),
+        })
+    }
+
+    /// Opens a newly added controller, given its joystick index
+    pub fn add(&mut self, joystick_index: u32) {
+        match self.subsystem.open(joystick_index) {
+            Ok(controller) => {
+                println!("Controller connected: {}", controller.name());
+                self.opened.push(controller);
+            }
+            Err(e) => eprintln!("Can't open controller {}: {}", joystick_index, e),
+        }
+    }
+
+    /// Closes a removed controller, given its id
+    /// Returns the console keys which were held by it
+    pub fn remove(&mut self, which: u32) -> Vec<usize> {
+        self.opened.retain(|c| c.instance_id() != which);
+        self.state.release_all(which)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn input_names() {
+        assert_eq!(
+            ControllerInput::from_name("A"),
+            Some(ControllerInput::Button(Button::A))
+        );
+        assert_eq!(
+            ControllerInput::from_name("dpleft"),
+            Some(ControllerInput::Button(Button::DPadLeft))
+        );
+        assert_eq!(
+            ControllerInput::from_name("lefty-"),
+            Some(ControllerInput::AxisNegative(Axis::LeftY))
+        );
+        assert_eq!(
+            ControllerInput::from_name("righttrigger+"),
+            Some(ControllerInput::AxisPositive(Axis::TriggerRight))
+        );
+        assert_eq!(ControllerInput::from_name("z"), None);
+        assert_eq!(ControllerInput::from_name("leftz+"), None);
+    }
+
+    #[test]
+    fn buttons_press_their_keys() {
+        let mut state = PadState::new(ControllerMapping::default());
+
+        assert_eq!(state.button(0, Button::A, true), Some(0x5));
+        assert!(state.is_down(0x5));
+
+        assert_eq!(state.button(0, Button::A, false), Some(0x5));
+        assert!(!state.is_down(0x5));
+
+        assert_eq!(state.button(0, Button::Guide, true), None);
+    }
+
+    #[test]
+    fn axis_uses_the_deadzone() {
+        let mut state = PadState::new(ControllerMapping::default());
+
+        assert_eq!(state.axis(0, Axis::LeftX, DEFAULT_DEADZONE), vec![0x6, 0x4]);
+        assert!(!state.is_down(0x4) && !state.is_down(0x6));
+
+        state.axis(0, Axis::LeftX, i16::MAX);
+        assert!(state.is_down(0x6) && !state.is_down(0x4));
+
+        state.axis(0, Axis::LeftX, i16::MIN);
+        assert!(state.is_down(0x4) && !state.is_down(0x6));
+
+        state.axis(0, Axis::LeftX, 0);
+        assert!(!state.is_down(0x4) && !state.is_down(0x6));
+    }
+
+    #[test]
+    fn key_stays_down_while_any_input_holds_it() {
+        let mut state = PadState::new(ControllerMapping::default());
+
+        state.button(0, Button::DPadUp, true);
+        state.axis(0, Axis::LeftY, i16::MIN);
+        state.button(0, Button::DPadUp, false);
+        assert!(state.is_down(0x2));
+
+        state.axis(0, Axis::LeftY, 0);
+        assert!(!state.is_down(0x2));
+
+        state.button(0, Button::A, true);
+        state.button(1, Button::A, true);
+        assert_eq!(state.release_all(1), vec![0x5]);
+        assert!(state.is_down(0x5));
+    }
+
+    #[test]
+    fn profile_overrides_bindings() {
+        let mut mapping = ControllerMapping::default();
+        let profile: HashMap<String, String> = [
+            ("a".to_string(), "C".to_string()),
+            ("leftx-".to_string(), "none".to_string()),
+        ]
+        .iter()
+        .cloned()
+        .collect();
+        mapping.bind_names(&profile).unwrap();
+
+        assert_eq!(mapping.key_index(ControllerInput::Button(Button::A)), Some(0xC));
+        assert_eq!(mapping.key_index(ControllerInput::AxisNegative(Axis::LeftX)), None);
+        assert_eq!(mapping.key_index(ControllerInput::Button(Button::DPadLeft)), Some(0x4));
+
+        let bad: HashMap<String, String> =
+            [("a".to_string(), "10".to_string())].iter().cloned().collect();
+        assert!(mapping.bind_names(&bad).is_err());
+    }
+
+    #[test]
+    fn roles_bind_the_dpad_and_the_stick() {
+        let mut mapping = ControllerMapping::default();
+        let roles: HashMap<String, u8> = [
+            ("left".to_string(), 0x7),
+            ("a".to_string(), 0xA),
+            ("player2Up".to_string(), 0x1),
+        ]
+        .iter()
+        .cloned()
+        .collect();
+        mapping.bind_roles(&roles);
+
+        assert_eq!(mapping.key_index(ControllerInput::Button(Button::DPadLeft)), Some(0x7));
+        assert_eq!(mapping.key_index(ControllerInput::AxisNegative(Axis::LeftX)), Some(0x7));
+        assert_eq!(mapping.key_index(ControllerInput::Button(Button::A)), Some(0xA));
+        assert_eq!(mapping.key_index(ControllerInput::Button(Button::DPadUp)), Some(0x2));
+    }
+}
@@ -0,0 +1,145 @@
+//! Contains the controls of the emulation: pause, frame advance,
+//! fast-forward and slow motion
+
+use crate::gui::keyboard::Command;
+
+/// Title of the window at normal speed
+const TITLE: &str = "chip-8";
+
+/// Speeds of the slow motion, in percent, cycled through in order
+const SLOW_MOTION_SPEEDS: [u32; 3] = [100, 50, 25];
+
+/// Number of frames to emulate during a frame of the window
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Frames {
+    /// The given number of frames
+    Count(usize),
+    /// As many frames as the time allows
+    Unlimited,
+}
+
+/// State of the controls of the emulation
+#[derive(Debug)]
+pub struct Controls {
+    /// True if the emulation is paused
+    paused: bool,
+    /// True if a single frame must be emulated while paused
+    advance: bool,
+    /// True while fast-forwarding
+    fast_forward: bool,
+    /// Index of the speed in SLOW_MOTION_SPEEDS
+    slow_motion: usize,
+    /// Speed accumulated towards the next emulated frame, in percent
+    credit: u32,
+}
+
+impl Controls {
+    /// Creates the controls, paused or not
+    pub fn new(paused: bool) -> Self {
+        Controls { paused, advance: false, fast_forward: false, slow_motion: 0, credit: 0 }
+    }
+
+    /// Returns true if the emulation is paused
+    pub fn paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Applies a command, ignoring the ones which aren't controls
+    pub fn apply(&mut self, command: Command) {
+        match command {
+            Command::TogglePause => self.paused = !self.paused,
+            Command::AdvanceFrame => self.advance = self.paused,
+            Command::FastForward(on) => self.fast_forward = on,
+            Command::ToggleSlowMotion => {
+                self.slow_motion = (self.slow_motion + 1) % SLOW_MOTION_SPEEDS.len();
+                self.credit = 0;
+            },
+            _ => (),
+        }
+    }
+
+    /// Returns the number of frames to emulate during the next frame of the
+    /// window
+    pub fn next_frames(&mut self) -> Frames {
+        if self.paused {
+            let advance = self.advance;
+            self.advance = false;
+            return Frames::Count(advance as usize);
+        }
+        if self.fast_forward {
+            return Frames::Unlimited;
+        }
+
+        // In slow motion, the frames are spread over several frames of the
+        // window
+        self.credit += SLOW_MOTION_SPEEDS[self.slow_motion];
+        let frames = self.credit / 100;
+        self.credit %= 100;
+        Frames::Count(frames as usize)
+    }
+
+    /// Returns the title of the window, showing the state of the emulation
+    pub fn title(&self) -> String {
+        let speed = SLOW_MOTION_SPEEDS[self.slow_motion];
+
+        if self.paused {
+            format!("{} - Paused", TITLE)
+        } else if self.fast_forward {
+            format!("{} - Fast-forward", TITLE)
+        } else if speed != 100 {
+            format!("{} - Slow motion {}%", TITLE, speed)
+        } else {
+            TITLE.to_string()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the frames of the next window frames
+    fn frames(controls: &mut Controls, count: usize) -> Vec<Frames> {
+        (0..count).map(|_| controls.next_frames()).collect()
+    }
+
+    #[test]
+    fn pause_and_frame_advance() {
+        let mut controls = Controls::new(true);
+        assert_eq!(frames(&mut controls, 2), [Frames::Count(0); 2]);
+
+        controls.apply(Command::AdvanceFrame);
+        assert_eq!(frames(&mut controls, 2), [Frames::Count(1), Frames::Count(0)]);
+        assert_eq!(controls.title(), "chip-8 - Paused");
+
+        controls.apply(Command::TogglePause);
+        controls.apply(Command::AdvanceFrame);
+        assert_eq!(frames(&mut controls, 2), [Frames::Count(1); 2]);
+        assert_eq!(controls.title(), "chip-8");
+    }
+
+    #[test]
+    fn fast_forward_while_held() {
+        let mut controls = Controls::new(false);
+        controls.apply(Command::FastForward(true));
+        assert_eq!(controls.next_frames(), Frames::Unlimited);
+        assert_eq!(controls.title(), "chip-8 - Fast-forward");
+
+        controls.apply(Command::FastForward(false));
+        assert_eq!(controls.next_frames(), Frames::Count(1));
+    }
+
+    #[test]
+    fn slow_motion_spreads_frames() {
+        let mut controls = Controls::new(false);
+        controls.apply(Command::ToggleSlowMotion);
+        assert_eq!(controls.title(), "chip-8 - Slow motion 50%");
+        assert_eq!(frames(&mut controls, 4), [0, 1, 0, 1].map(Frames::Count));
+
+        controls.apply(Command::ToggleSlowMotion);
+        assert_eq!(frames(&mut controls, 4), [0, 0, 0, 1].map(Frames::Count));
+
+        controls.apply(Command::ToggleSlowMotion);
+        assert_eq!(controls.title(), "chip-8");
+    }
+}
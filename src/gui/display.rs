@@ -5,70 +5,217 @@ use sdl2::pixels;
 use sdl2::rect::Rect;
 use sdl2::render::Canvas;
 use sdl2::video::Window;
+use std::str::FromStr;
 
-use crate::emu::console::*;
+use chip_8_rust::emu::console::*;
+use chip_8_rust::emu::font::FONT;
+use crate::gui::keypad::{KeypadLayout, KEYPAD};
 
-/// Scale of the pixels
-const SCALE: usize = 20;
+/// Default scale of the pixels
+pub const DEFAULT_SCALE: u32 = 20;
 
-/// Width of the screen
-const SCREEN_WIDTH: usize = BUFF_WIDTH * SCALE;
-/// Height of the screen
-const SCREEN_HEIGHT: usize = BUFF_HEIGHT * SCALE;
+/// Color of the keys of the on-screen keypad
+const KEY_COLOR: pixels::Color = pixels::Color::RGB(0x40, 0x40, 0x40);
+
+/// Colors of the pixels
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Palette {
+    /// Color of the lit pixels
+    pub foreground: pixels::Color,
+    /// Color of the unlit pixels
+    pub background: pixels::Color,
+}
+
+impl Default for Palette {
+    /// White on black
+    fn default() -> Self {
+        Palette {
+            foreground: pixels::Color::WHITE,
+            background: pixels::Color::BLACK,
+        }
+    }
+}
+
+impl FromStr for Palette {
+    type Err = String;
+
+    /// Parses two hexadecimal RGB colors separated by a comma, such as
+    /// "ffcc00,1a1a1a" (a leading # is allowed)
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |color: &str| {
+            let hex = color.trim().trim_start_matches('#');
+            match u32::from_str_radix(hex, 16) {
+                Ok(rgb) if hex.len() == 6 => Ok(pixels::Color::RGB(
+                    (rgb >> 16) as u8,
+                    (rgb >> 8) as u8,
+                    rgb as u8,
+                )),
+                _ => Err(format!("invalid color '{}' (expected RRGGBB)", color)),
+            }
+        };
+
+        match s.split(',').collect::<Vec<_>>()[..] {
+            [foreground, background] => Ok(Palette {
+                foreground: parse(foreground)?,
+                background: parse(background)?,
+            }),
+            _ => Err(format!("invalid palette '{}' (expected FG,BG)", s)),
+        }
+    }
+}
+
+/// Settings of the display
+#[derive(Debug, Clone, Copy)]
+pub struct DisplayOptions {
+    /// Size of a pixel of the console, in pixels of the window
+    pub scale: u32,
+    /// Colors of the pixels
+    pub palette: Palette,
+    /// True to show the on-screen keypad on the right of the game
+    pub keypad: bool,
+    /// True to fill the screen
+    pub fullscreen: bool,
+}
 
 /// Display storing the canvas
 pub struct Display {
     /// Canvas of the window
     pub canvas: Canvas<Window>,
+    /// Size of a pixel of the console, in pixels of the window
+    scale: u32,
+    /// Colors of the pixels
+    palette: Palette,
+    /// Layout of the on-screen keypad, if shown
+    keypad: Option<KeypadLayout>,
+    /// State of the keys when the keypad was last drawn
+    drawn_keys: [bool; 16],
 }
 
 impl Display {
     /// Creates a new display
-    pub fn new(sdl_content: &sdl2::Sdl) -> Self {
+    /// In fullscreen, the game is scaled to the screen, keeping its ratio
+    pub fn new(sdl_content: &sdl2::Sdl, options: &DisplayOptions) -> Self {
+        let screen_width = BUFF_WIDTH as u32 * options.scale;
+        let screen_height = BUFF_HEIGHT as u32 * options.scale;
+
+        let keypad = if options.keypad {
+            Some(KeypadLayout::new(screen_width as i32, screen_height))
+        } else {
+            None
+        };
+        let width = screen_width + keypad.map_or(0, |k| k.width());
+
         let video = sdl_content.video().unwrap();
-        let window = video
-            .window(
-                "chip-8",
-                SCREEN_WIDTH as u32,
-                SCREEN_HEIGHT as u32
-            )
-            .position_centered()
-            .opengl()
-            .build()
-            .unwrap();
+        let mut builder = video.window("chip-8", width, screen_height);
+        builder.position_centered().opengl();
+        if options.fullscreen {
+            builder.fullscreen_desktop();
+        }
+        let window = builder.build().unwrap();
 
         let mut canvas = window.into_canvas().build().unwrap();
+        canvas
+            .set_logical_size(width, screen_height)
+            .expect("Can't scale the display");
 
-        canvas.set_draw_color(pixels::Color::BLACK);
+        canvas.set_draw_color(options.palette.background);
         canvas.clear();
         canvas.present();
 
-        Display{ canvas }
+        Display{
+            canvas,
+            scale: options.scale,
+            palette: options.palette,
+            keypad,
+            drawn_keys: [false; 16],
+        }
+    }
+
+    /// Sets the title of the window
+    pub fn set_title(&mut self, title: &str) {
+        self.canvas.window_mut().set_title(title).expect("Can't set the title");
+    }
+
+    /// Layout of the on-screen keypad, if shown
+    pub fn keypad(&self) -> Option<KeypadLayout> {
+        self.keypad
+    }
+
+    /// Returns true if the keys changed since the keypad was last drawn
+    pub fn keypad_changed(&self, console: &Console) -> bool {
+        self.keypad.is_some() && *console.keys() != self.drawn_keys
     }
 
     /// Draws the buffer into the display
     pub fn draw(&mut self, console: &Console) {
-        for y in 0..BUFF_HEIGHT {
-            for x in 0..BUFF_WIDTH {
-                let x_pos = x * SCALE;
-                let y_pos = y * SCALE;
+        let scale = self.scale as usize;
 
-                let color = if console.get_bit(x, y) {
-                    pixels::Color::WHITE
-                } else {
-                    pixels::Color::BLACK
-                };
+        self.canvas.set_draw_color(self.palette.background);
+        self.canvas.clear();
 
-                self.canvas.set_draw_color(color);
+        self.canvas.set_draw_color(self.palette.foreground);
+
+        for (y, &row) in console.rows().iter().enumerate() {
+            // Draw each horizontal run of lit pixels as a single rectangle
+            let mut x = 0;
+            while x < BUFF_WIDTH {
+                let rest = row << x;
+                if rest == 0 {
+                    break;
+                }
+
+                let start = x + rest.leading_zeros() as usize;
+                let len = (!(row << start)).leading_zeros() as usize;
 
                 let rect = Rect::new(
-                    x_pos as i32,
-                    y_pos as i32,
-                    SCALE as u32, SCALE as u32);
+                    (start * scale) as i32,
+                    (y * scale) as i32,
+                    (len * scale) as u32, scale as u32);
                 self.canvas.fill_rect(rect).expect("Can't draw pixel");
+
+                x = start + len;
             }
         }
 
+        if let Some(keypad) = self.keypad {
+            self.draw_keypad(keypad, console.keys());
+        }
+
         self.canvas.present();
     }
-}
\ No newline at end of file
+
+    /// Draws the on-screen keypad, highlighting the keys held down
+    fn draw_keypad(&mut self, keypad: KeypadLayout, keys: &[bool; 16]) {
+        for &key in KEYPAD.iter() {
+            let (x, y, size) = keypad.key_rect(key);
+            let (background, foreground) = if keys[key] {
+                (pixels::Color::WHITE, pixels::Color::BLACK)
+            } else {
+                (KEY_COLOR, pixels::Color::WHITE)
+            };
+
+            self.canvas.set_draw_color(background);
+            self.canvas.fill_rect(Rect::new(x, y, size, size)).expect("Can't draw key");
+
+            // Label of the key, using the font of the console (4x5 pixels)
+            let pixel = size / 10;
+            let glyph_x = x + (size - pixel * 4) as i32 / 2;
+            let glyph_y = y + (size - pixel * 5) as i32 / 2;
+
+            self.canvas.set_draw_color(foreground);
+            for (row, bits) in FONT[key].iter().enumerate() {
+                for col in 0..4 {
+                    if bits & (0x80 >> col) != 0 {
+                        let rect = Rect::new(
+                            glyph_x + (col * pixel) as i32,
+                            glyph_y + (row as u32 * pixel) as i32,
+                            pixel, pixel);
+                        self.canvas.fill_rect(rect).expect("Can't draw key");
+                    }
+                }
+            }
+        }
+
+        self.drawn_keys = *keys;
+    }
+}
@@ -1,82 +1,196 @@
 //! Contains functions used to interface with the keyboard
 
 use sdl2::EventPump;
-use crate::emu::console::Console;
+use chip_8_rust::emu::console::Console;
 use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
+use sdl2::keyboard::{Keycode, Scancode};
+use sdl2::mouse::MouseButton;
+use std::collections::HashSet;
+use crate::gui::controller::Controllers;
+use crate::gui::keymap::{HostKey, Keymap};
+use crate::gui::keypad::KeypadLayout;
+
+/// Actions of the emulator requested through the window or hotkeys
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Command {
+    /// Closes the emulator
+    Quit,
+    /// Pauses or resumes the emulation
+    TogglePause,
+    /// Mutes or unmutes the sound
+    ToggleMute,
+    /// Raises the volume
+    VolumeUp,
+    /// Lowers the volume
+    VolumeDown,
+    /// Starts or stops recording the audio
+    ToggleRecording,
+    /// Emulates a single frame while paused
+    AdvanceFrame,
+    /// Starts or stops running as fast as possible
+    FastForward(bool),
+    /// Cycles through the slow motion speeds
+    ToggleSlowMotion,
+    /// Restarts the console
+    Reset,
+    /// Reads the ROM again from its file and restarts the console with it
+    ReloadRom,
+}
 
 /// Represents the keyboard
 /// Contains the EventPump
 pub struct Keyboard {
     /// The event pump of the ui
     event_pump: EventPump,
+    /// Mapping from the keyboard keys to the console keys
+    keymap: Keymap,
+    /// Keyboard keys currently held down
+    held: HashSet<HostKey>,
+    /// Game controllers, if their subsystem could be opened
+    controllers: Option<Controllers>,
+    /// Layout of the on-screen keypad, if shown
+    keypad: Option<KeypadLayout>,
+    /// Key of the on-screen keypad held down with the mouse
+    clicked: Option<usize>,
 }
 
 impl Keyboard {
     /// Creates a new keyboard
-    pub fn new(sdl_context: &sdl2::Sdl) -> Self {
+    pub fn new(
+        sdl_context: &sdl2::Sdl,
+        keymap: Keymap,
+        controllers: Option<Controllers>,
+        keypad: Option<KeypadLayout>,
+    ) -> Self {
         let event_pump = sdl_context.event_pump().unwrap();
-        Keyboard{ event_pump }
+        Keyboard{ event_pump, keymap, held: HashSet::new(), controllers, keypad, clicked: None }
     }
 
-    /// Polls the keys from the keyboard and inputs them into console
-    /// Returns true if the program continues
-    pub fn poll_keys(&mut self, console: &mut Console, debug: bool) -> bool {
-        let mut cont = true;
-        for event in self.event_pump.poll_iter() {
-            let key_index: Option<usize> = match event {
+    /// Polls the keys from the keyboard, the controllers and the on-screen
+    /// keypad and inputs them into console
+    /// Returns the commands requested by the user
+    pub fn poll_keys(&mut self, console: &mut Console, debug: bool) -> Vec<Command> {
+        let mut commands = vec![];
+        let events: Vec<Event> = self.event_pump.poll_iter().collect();
+
+        for event in events {
+            let keys = match event {
                 Event::Quit { .. } |
                 Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
-                    cont = false;
+                    commands.push(Command::Quit);
                     break;
                 },
-                Event::KeyUp {keycode, ..} |
-                Event::KeyDown { keycode, .. } => {
-                    let key = keycode.unwrap();
-                    match key {
-                        Keycode::Num1 => Some(0x1),
-                        Keycode::Num2 => Some(0x2),
-                        Keycode::Num3 => Some(0x3),
-                        Keycode::Num4 => Some(0xC),
-
-                        Keycode::Q => Some(0x4),
-                        Keycode::W => Some(0x5),
-                        Keycode::E => Some(0x6),
-                        Keycode::R => Some(0xD),
-
-                        Keycode::A => Some(0x7),
-                        Keycode::S => Some(0x8),
-                        Keycode::D => Some(0x9),
-                        Keycode::F => Some(0xE),
-
-                        Keycode::Y => Some(0xA),
-                        Keycode::X => Some(0x0),
-                        Keycode::C => Some(0xB),
-                        Keycode::V => Some(0xF),
-
-                        _ => None,
+                Event::KeyDown { keycode, scancode, repeat, .. } => {
+                    let keys = self.host_key(keycode, scancode, true);
+                    if keys.is_empty() {
+                        commands.extend(keycode.and_then(|k| hotkey(k, repeat)));
                     }
-                }
-                _ => None,
-            };
-
-            let down = match event {
-                Event::KeyUp {..} => false,
-                Event::KeyDown {..} => true,
-                _ => false,
+                    keys
+                },
+                Event::KeyUp { keycode, scancode, .. } => {
+                    let keys = self.host_key(keycode, scancode, false);
+                    if keys.is_empty() {
+                        commands.extend(keycode.and_then(hotkey_release));
+                    }
+                    keys
+                },
+                Event::ControllerDeviceAdded { which, .. } => {
+                    if let Some(controllers) = &mut self.controllers {
+                        controllers.add(which);
+                    }
+                    continue;
+                },
+                Event::ControllerDeviceRemoved { which, .. } => match &mut self.controllers {
+                    Some(controllers) => controllers.remove(which),
+                    None => continue,
+                },
+                Event::ControllerButtonDown { which, button, .. } => match &mut self.controllers {
+                    Some(controllers) => controllers.state.button(which, button, true).into_iter().collect(),
+                    None => continue,
+                },
+                Event::ControllerButtonUp { which, button, .. } => match &mut self.controllers {
+                    Some(controllers) => controllers.state.button(which, button, false).into_iter().collect(),
+                    None => continue,
+                },
+                Event::ControllerAxisMotion { which, axis, value, .. } => match &mut self.controllers {
+                    Some(controllers) => controllers.state.axis(which, axis, value),
+                    None => continue,
+                },
+                Event::MouseButtonDown { mouse_btn: MouseButton::Left, x, y, .. } => {
+                    self.clicked = self.keypad.and_then(|k| k.key_at(x, y));
+                    self.clicked.into_iter().collect()
+                },
+                Event::MouseButtonUp { mouse_btn: MouseButton::Left, .. } => {
+                    self.clicked.take().into_iter().collect()
+                },
+                _ => continue,
             };
 
             // PRESS KEY
-            if let Some(i) = key_index {
+            for i in keys {
+                let down = self.is_down(i);
+
                 if debug {
                     println!("Key {:x} is {}", i,
                         if down { "down" } else { "up" }
                     );
                 }
                 console.set_key(i, down)
-            };
+            }
+        }
+
+        commands
+    }
+
+    /// Updates the state of a keyboard key
+    /// Returns the console key bound to it, if any
+    fn host_key(&mut self, keycode: Option<Keycode>, scancode: Option<Scancode>, down: bool) -> Vec<usize> {
+        let key = match self.keymap.host_key(keycode, scancode) {
+            Some(key) => key,
+            None => return vec![],
+        };
+
+        if down {
+            self.held.insert(key);
+        } else {
+            self.held.remove(&key);
         }
 
-        cont
+        self.keymap.key_index(key).into_iter().collect()
     }
-}
\ No newline at end of file
+
+    /// Returns true if any keyboard key, controller input or on-screen key
+    /// bound to the console key is held
+    fn is_down(&self, index: usize) -> bool {
+        let keymap = &self.keymap;
+        self.held.iter().any(|k| keymap.key_index(*k) == Some(index))
+            || self.controllers.as_ref().is_some_and(|c| c.state.is_down(index))
+            || self.clicked == Some(index)
+    }
+}
+
+/// Returns the command of a hotkey
+/// Hotkeys are only used when the key isn't bound to a console key
+fn hotkey(keycode: Keycode, repeat: bool) -> Option<Command> {
+    match keycode {
+        Keycode::P if !repeat => Some(Command::TogglePause),
+        Keycode::M if !repeat => Some(Command::ToggleMute),
+        Keycode::Equals | Keycode::Plus | Keycode::KpPlus => Some(Command::VolumeUp),
+        Keycode::Minus | Keycode::KpMinus => Some(Command::VolumeDown),
+        Keycode::F9 if !repeat => Some(Command::ToggleRecording),
+        Keycode::N => Some(Command::AdvanceFrame),
+        Keycode::Tab if !repeat => Some(Command::FastForward(true)),
+        Keycode::L if !repeat => Some(Command::ToggleSlowMotion),
+        Keycode::Backspace if !repeat => Some(Command::Reset),
+        Keycode::F5 if !repeat => Some(Command::ReloadRom),
+        _ => None,
+    }
+}
+
+/// Returns the command of the release of a hotkey held down
+fn hotkey_release(keycode: Keycode) -> Option<Command> {
+    match keycode {
+        Keycode::Tab => Some(Command::FastForward(false)),
+        _ => None,
+    }
+}
@@ -0,0 +1,448 @@
+//! Contains the mapping between the keys of the keyboard and of the console
+
+use crate::gui::keypad::KEYPAD;
+use sdl2::keyboard::{Keycode, Scancode};
+use std::collections::HashMap;
+use std::str::FromStr;
+
+/// Built-in keyboard layouts
+/// Each maps the 4x4 block under 1 to V (on QWERTY) to the console keypad
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Preset {
+    /// QWERTY layout: 1234 / QWER / ASDF / ZXCV
+    Qwerty,
+    /// QWERTZ layout: 1234 / QWER / ASDF / YXCV
+    Qwertz,
+    /// AZERTY layout: &é"' / AZER / QSDF / WXCV, with keypad 2 in place of é
+    Azerty,
+    /// Numeric keypad, each digit maps to itself and / * - + Enter . to A-F
+    Numpad,
+}
+
+impl FromStr for Preset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "qwerty" => Ok(Preset::Qwerty),
+            "qwertz" => Ok(Preset::Qwertz),
+            "azerty" => Ok(Preset::Azerty),
+            "numpad" => Ok(Preset::Numpad),
+            _ => Err(format!(
+                "unknown keymap preset '{}' (expected qwerty, qwertz, azerty or numpad)",
+                s
+            )),
+        }
+    }
+}
+
+/// How keyboard keys are identified
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InputMode {
+    /// By the character they produce, which depends on the keyboard layout
+    Keycode,
+    /// By their physical position, whatever the keyboard layout
+    /// The letter presets all map the block under 1 to V of a QWERTY keyboard
+    Scancode,
+}
+
+impl FromStr for InputMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "keycode" => Ok(InputMode::Keycode),
+            "scancode" => Ok(InputMode::Scancode),
+            _ => Err(format!(
+                "unknown input mode '{}' (expected keycode or scancode)",
+                s
+            )),
+        }
+    }
+}
+
+/// A key of the keyboard, as identified by the input mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HostKey {
+    /// Key identified by its keycode
+    Keycode(Keycode),
+    /// Key identified by its scancode
+    Scancode(Scancode),
+}
+
+/// Maps the keys of the keyboard to the keys of the console
+/// Several keyboard keys can map to the same console key
+#[derive(Debug, Clone)]
+pub struct Keymap {
+    /// How keyboard keys are identified
+    mode: InputMode,
+    /// Console key of each bound keyboard key
+    bindings: HashMap<HostKey, usize>,
+}
+
+impl Keymap {
+    /// Creates the keymap of a preset
+    pub fn preset(preset: Preset, mode: InputMode) -> Self {
+        let mut keymap = Keymap { mode, bindings: HashMap::new() };
+
+        let keys = match mode {
+            InputMode::Keycode => keycode_preset(preset),
+            InputMode::Scancode => scancode_preset(preset),
+        };
+
+        for (key, index) in keys {
+            keymap.bindings.insert(key, index);
+        }
+
+        keymap
+    }
+
+    /// Binds the given keyboard keys to a console key
+    /// The keys previously bound to that console key are unbound
+    pub fn bind(&mut self, index: usize, keys: &[HostKey]) {
+        self.bindings.retain(|_, i| *i != index);
+        for key in keys {
+            self.bindings.insert(*key, index);
+        }
+    }
+
+    /// Binds console keys to keyboard keys given by their names
+    /// Console keys are hexadecimal digits, keyboard keys use the SDL names
+    /// of keycodes or scancodes, depending on the input mode
+    /// A keyboard key given for several console keys is refused
+    pub fn bind_names(&mut self, keys: &HashMap<String, Vec<String>>) -> Result<(), String> {
+        // In the order of the console keys, so that errors don't depend on
+        // the order of the map
+        let mut entries = keys
+            .iter()
+            .map(|(index, names)| {
+                usize::from_str_radix(index, 16)
+                    .ok()
+                    .filter(|i| *i < 16)
+                    .map(|index| (index, names))
+                    .ok_or_else(|| format!("invalid console key '{}'", index))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        entries.sort_by_key(|(index, _)| *index);
+
+        let mut named: HashMap<HostKey, usize> = HashMap::new();
+        for (index, names) in entries {
+
+            let host_keys = names
+                .iter()
+                .map(|name| {
+                    let key = match self.mode {
+                        InputMode::Keycode => Keycode::from_name(name).map(HostKey::Keycode),
+                        InputMode::Scancode => Scancode::from_name(name).map(HostKey::Scancode),
+                    };
+                    key.ok_or_else(|| format!("unknown key name '{}'", name))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+
+            for (key, name) in host_keys.iter().zip(names) {
+                match named.insert(*key, index) {
+                    Some(other) if other != index => {
+                        return Err(format!(
+                            "key '{}' is bound to both console keys {:X} and {:X}",
+                            name, other, index
+                        ))
+                    },
+                    _ => (),
+                }
+            }
+            self.bind(index, &host_keys);
+        }
+
+        Ok(())
+    }
+
+    /// Adds the arrows, space and shift to the console keys playing the given
+    /// roles (up, down, left, right, a and b), as hinted by the ROM database
+    /// The keys of the preset stay bound
+    pub fn add_roles(&mut self, roles: &HashMap<String, u8>) {
+        for (role, index) in roles {
+            let key = match (role.as_str(), self.mode) {
+                ("up", InputMode::Keycode) => HostKey::Keycode(Keycode::Up),
+                ("down", InputMode::Keycode) => HostKey::Keycode(Keycode::Down),
+                ("left", InputMode::Keycode) => HostKey::Keycode(Keycode::Left),
+                ("right", InputMode::Keycode) => HostKey::Keycode(Keycode::Right),
+                ("a", InputMode::Keycode) => HostKey::Keycode(Keycode::Space),
+                ("b", InputMode::Keycode) => HostKey::Keycode(Keycode::LShift),
+                ("up", InputMode::Scancode) => HostKey::Scancode(Scancode::Up),
+                ("down", InputMode::Scancode) => HostKey::Scancode(Scancode::Down),
+                ("left", InputMode::Scancode) => HostKey::Scancode(Scancode::Left),
+                ("right", InputMode::Scancode) => HostKey::Scancode(Scancode::Right),
+                ("a", InputMode::Scancode) => HostKey::Scancode(Scancode::Space),
+                ("b", InputMode::Scancode) => HostKey::Scancode(Scancode::LShift),
+                _ => continue,
+            };
+
+            if *index < 16 {
+                self.bindings.insert(key, *index as usize);
+            }
+        }
+    }
+
+    /// Returns the keyboard key of an event, as identified by the input mode
+    pub fn host_key(&self, keycode: Option<Keycode>, scancode: Option<Scancode>) -> Option<HostKey> {
+        match self.mode {
+            InputMode::Keycode => keycode.map(HostKey::Keycode),
+            InputMode::Scancode => scancode.map(HostKey::Scancode),
+        }
+    }
+
+    /// Returns the console key bound to a keyboard key
+    pub fn key_index(&self, key: HostKey) -> Option<usize> {
+        self.bindings.get(&key).copied()
+    }
+}
+
+/// Returns the bindings of a preset in the keycode mode
+fn keycode_preset(preset: Preset) -> Vec<(HostKey, usize)> {
+    use sdl2::keyboard::Keycode::*;
+
+    // Keyboard keys in the order of KEYPAD
+    let keys: [Keycode; 16] = match preset {
+        Preset::Qwerty => [
+            Num1, Num2, Num3, Num4,
+            Q, W, E, R,
+            A, S, D, F,
+            Z, X, C, V,
+        ],
+        Preset::Qwertz => [
+            Num1, Num2, Num3, Num4,
+            Q, W, E, R,
+            A, S, D, F,
+            Y, X, C, V,
+        ],
+        // The é key has no keycode in the SDL bindings, key 2 is bound to
+        // the 2 of the numeric keypad instead (or use the scancode mode)
+        Preset::Azerty => [
+            Ampersand, Kp2, Quotedbl, Quote,
+            A, Z, E, R,
+            Q, S, D, F,
+            W, X, C, V,
+        ],
+        Preset::Numpad => {
+            let digits = [Kp0, Kp1, Kp2, Kp3, Kp4, Kp5, Kp6, Kp7, Kp8, Kp9];
+            let letters = [KpDivide, KpMultiply, KpMinus, KpPlus, KpEnter, KpPeriod];
+
+            return digits
+                .iter()
+                .chain(letters.iter())
+                .enumerate()
+                .map(|(index, key)| (HostKey::Keycode(*key), index))
+                .collect();
+        }
+    };
+
+    keys.iter()
+        .zip(KEYPAD.iter())
+        .map(|(key, index)| (HostKey::Keycode(*key), *index))
+        .collect()
+}
+
+/// Returns the bindings of a preset in the scancode mode
+/// The letter presets are the same physical keys
+fn scancode_preset(preset: Preset) -> Vec<(HostKey, usize)> {
+    use sdl2::keyboard::Scancode::*;
+
+    if preset == Preset::Numpad {
+        let digits = [Kp0, Kp1, Kp2, Kp3, Kp4, Kp5, Kp6, Kp7, Kp8, Kp9];
+        let letters = [KpDivide, KpMultiply, KpMinus, KpPlus, KpEnter, KpPeriod];
+
+        return digits
+            .iter()
+            .chain(letters.iter())
+            .enumerate()
+            .map(|(index, key)| (HostKey::Scancode(*key), index))
+            .collect();
+    }
+
+    // Named after their position on a QWERTY keyboard
+    let keys: [Scancode; 16] = [
+        Num1, Num2, Num3, Num4,
+        Q, W, E, R,
+        A, S, D, F,
+        Z, X, C, V,
+    ];
+
+    keys.iter()
+        .zip(KEYPAD.iter())
+        .map(|(key, index)| (HostKey::Scancode(*key), *index))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the console key bound to a keycode
+    fn keycode_index(keymap: &Keymap, keycode: Keycode) -> Option<usize> {
+        keymap.key_index(HostKey::Keycode(keycode))
+    }
+
+    /// Returns the SDL name of a keyboard key
+    fn key_name(key: HostKey) -> String {
+        match key {
+            HostKey::Keycode(keycode) => keycode.name(),
+            HostKey::Scancode(scancode) => scancode.name().to_string(),
+        }
+    }
+
+    /// Creates the keys of a configuration, from console keys and key names
+    fn names(keys: &[(&str, &[&str])]) -> HashMap<String, Vec<String>> {
+        keys.iter()
+            .map(|(index, names)| (index.to_string(), names.iter().map(|n| n.to_string()).collect()))
+            .collect()
+    }
+
+    #[test]
+    fn preset_and_mode_names() {
+        assert_eq!("QWERTZ".parse(), Ok(Preset::Qwertz));
+        assert_eq!("numpad".parse(), Ok(Preset::Numpad));
+        assert!("dvorak".parse::<Preset>().unwrap_err().contains("dvorak"));
+        assert_eq!("Scancode".parse(), Ok(InputMode::Scancode));
+        assert!("keysym".parse::<InputMode>().is_err());
+    }
+
+    #[test]
+    fn keycode_presets() {
+        let qwerty = Keymap::preset(Preset::Qwerty, InputMode::Keycode);
+        let expected = [
+            (Keycode::Num1, 0x1), (Keycode::Num4, 0xC), (Keycode::Q, 0x4), (Keycode::R, 0xD),
+            (Keycode::A, 0x7), (Keycode::F, 0xE), (Keycode::Z, 0xA), (Keycode::X, 0x0),
+            (Keycode::V, 0xF),
+        ];
+        for (keycode, index) in expected {
+            assert_eq!(keycode_index(&qwerty, keycode), Some(index), "{:?}", keycode);
+        }
+        assert_eq!(qwerty.bindings.len(), 16);
+
+        let qwertz = Keymap::preset(Preset::Qwertz, InputMode::Keycode);
+        assert_eq!(keycode_index(&qwertz, Keycode::Y), Some(0xA));
+        assert_eq!(keycode_index(&qwertz, Keycode::Z), None);
+
+        let azerty = Keymap::preset(Preset::Azerty, InputMode::Keycode);
+        assert_eq!(keycode_index(&azerty, Keycode::Ampersand), Some(0x1));
+        assert_eq!(keycode_index(&azerty, Keycode::Kp2), Some(0x2));
+        assert_eq!(keycode_index(&azerty, Keycode::A), Some(0x4));
+        assert_eq!(keycode_index(&azerty, Keycode::Q), Some(0x7));
+        assert_eq!(keycode_index(&azerty, Keycode::W), Some(0xA));
+        assert_eq!(azerty.bindings.len(), 16);
+
+        let numpad = Keymap::preset(Preset::Numpad, InputMode::Keycode);
+        assert_eq!(keycode_index(&numpad, Keycode::Kp0), Some(0x0));
+        assert_eq!(keycode_index(&numpad, Keycode::Kp9), Some(0x9));
+        assert_eq!(keycode_index(&numpad, Keycode::KpDivide), Some(0xA));
+        assert_eq!(keycode_index(&numpad, Keycode::KpPeriod), Some(0xF));
+        assert_eq!(numpad.bindings.len(), 16);
+    }
+
+    #[test]
+    fn key_names_round_trip() {
+        let preset = Keymap::preset(Preset::Azerty, InputMode::Keycode);
+
+        for (key, index) in &preset.bindings {
+            let name = key_name(*key);
+            let mut keymap = Keymap { mode: InputMode::Keycode, bindings: HashMap::new() };
+            keymap.bind_names(&names(&[(&format!("{:x}", index), &[&name])])).unwrap();
+            assert_eq!(keymap.key_index(*key), Some(*index), "{}", name);
+        }
+    }
+
+    #[test]
+    fn bind_names_replaces_the_keys() {
+        let mut keymap = Keymap::preset(Preset::Qwerty, InputMode::Keycode);
+        keymap.bind_names(&names(&[("5", &["Up", "k"]), ("a", &["Space"])])).unwrap();
+
+        assert_eq!(keycode_index(&keymap, Keycode::Up), Some(0x5));
+        assert_eq!(keycode_index(&keymap, Keycode::K), Some(0x5));
+        assert_eq!(keycode_index(&keymap, Keycode::Space), Some(0xA));
+        assert_eq!(keycode_index(&keymap, Keycode::W), None);
+        assert_eq!(keycode_index(&keymap, Keycode::Z), None);
+        assert_eq!(keycode_index(&keymap, Keycode::Q), Some(0x4));
+    }
+
+    #[test]
+    fn bind_names_refuses_invalid_keys() {
+        let mut keymap = Keymap::preset(Preset::Qwerty, InputMode::Keycode);
+
+        assert_eq!(
+            keymap.bind_names(&names(&[("10", &["Up"])])),
+            Err("invalid console key '10'".to_string())
+        );
+        assert_eq!(
+            keymap.bind_names(&names(&[("g", &["Up"])])),
+            Err("invalid console key 'g'".to_string())
+        );
+        assert_eq!(
+            keymap.bind_names(&names(&[("1", &["NoSuchKey"])])),
+            Err("unknown key name 'NoSuchKey'".to_string())
+        );
+        assert_eq!(
+            keymap.bind_names(&names(&[("1", &["Up"]), ("2", &["Down", "up"])])),
+            Err("key 'up' is bound to both console keys 1 and 2".to_string())
+        );
+        assert!(keymap.bind_names(&names(&[("1", &["Up", "Up"])])).is_ok());
+    }
+
+    #[test]
+    fn roles_add_the_arrows() {
+        let mut keymap = Keymap::preset(Preset::Qwerty, InputMode::Keycode);
+        let roles: HashMap<String, u8> = [("up", 5), ("a", 6), ("jump", 7), ("left", 16)]
+            .iter()
+            .map(|(role, index)| (role.to_string(), *index))
+            .collect();
+        keymap.add_roles(&roles);
+
+        assert_eq!(keycode_index(&keymap, Keycode::Up), Some(0x5));
+        assert_eq!(keycode_index(&keymap, Keycode::Space), Some(0x6));
+        assert_eq!(keycode_index(&keymap, Keycode::W), Some(0x5));
+        assert_eq!(keycode_index(&keymap, Keycode::Left), None);
+        assert_eq!(keymap.bindings.len(), 18);
+    }
+
+    #[test]
+    fn scancode_presets_are_physical_positions() {
+        let qwerty = Keymap::preset(Preset::Qwerty, InputMode::Scancode);
+        for preset in [Preset::Qwertz, Preset::Azerty] {
+            let keymap = Keymap::preset(preset, InputMode::Scancode);
+            assert_eq!(keymap.bindings, qwerty.bindings, "{:?}", preset);
+        }
+
+        assert_eq!(qwerty.key_index(HostKey::Scancode(Scancode::Z)), Some(0xA));
+        assert_eq!(qwerty.key_index(HostKey::Scancode(Scancode::Num4)), Some(0xC));
+        assert_eq!(qwerty.key_index(HostKey::Keycode(Keycode::Z)), None);
+
+        let numpad = Keymap::preset(Preset::Numpad, InputMode::Scancode);
+        assert_eq!(numpad.key_index(HostKey::Scancode(Scancode::KpEnter)), Some(0xE));
+        assert_eq!(numpad.bindings.len(), 16);
+    }
+
+    #[test]
+    fn scancode_mode_uses_scancodes() {
+        let mut keymap = Keymap::preset(Preset::Azerty, InputMode::Scancode);
+        assert_eq!(
+            keymap.host_key(Some(Keycode::A), Some(Scancode::Q)),
+            Some(HostKey::Scancode(Scancode::Q))
+        );
+        assert_eq!(keymap.host_key(Some(Keycode::A), None), None);
+
+        for (key, index) in keymap.bindings.clone() {
+            let mut names_only = Keymap { mode: InputMode::Scancode, bindings: HashMap::new() };
+            names_only.bind_names(&names(&[(&format!("{:x}", index), &[&key_name(key)])])).unwrap();
+            assert_eq!(names_only.key_index(key), Some(index), "{}", key_name(key));
+        }
+
+        keymap.bind_names(&names(&[("5", &["Keypad 5"])])).unwrap();
+        assert_eq!(keymap.key_index(HostKey::Scancode(Scancode::Kp5)), Some(0x5));
+        assert_eq!(keymap.key_index(HostKey::Scancode(Scancode::W)), None);
+
+        let mut roles = HashMap::new();
+        roles.insert("b".to_string(), 0xF);
+        keymap.add_roles(&roles);
+        assert_eq!(keymap.key_index(HostKey::Scancode(Scancode::LShift)), Some(0xF));
+        assert_eq!(keymap.key_index(HostKey::Keycode(Keycode::LShift)), None);
+    }
+}
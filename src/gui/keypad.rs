@@ -0,0 +1,100 @@
+//! Contains the layout of the on-screen keypad
+//!
+//! The keypad is drawn on the right of the game, with the keys in the same
+//! order as on the original hardware (1 2 3 C / 4 5 6 D / 7 8 9 E / A 0 B F).
+
+/// Console keys, in the order of the keypad
+pub const KEYPAD: [usize; 16] = [
+    0x1, 0x2, 0x3, 0xC,
+    0x4, 0x5, 0x6, 0xD,
+    0x7, 0x8, 0x9, 0xE,
+    0xA, 0x0, 0xB, 0xF,
+];
+
+/// Space between two keys, in pixels
+const KEY_GAP: u32 = 8;
+
+/// Position and size of the on-screen keypad in the window
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KeypadLayout {
+    /// Horizontal position of the left side of the keypad
+    pub x: i32,
+    /// Size of the side of a key, including the gap around it
+    pub key_size: u32,
+}
+
+impl KeypadLayout {
+    /// Creates the layout of a keypad placed at x, as high as the window
+    pub fn new(x: i32, height: u32) -> Self {
+        KeypadLayout { x, key_size: height / 4 }
+    }
+
+    /// Width of the keypad
+    pub fn width(&self) -> u32 {
+        self.key_size * 4
+    }
+
+    /// Returns the console key under the given position of the window
+    /// The gaps between the keys belong to no key
+    pub fn key_at(&self, x: i32, y: i32) -> Option<usize> {
+        let x = x - self.x;
+        if x < 0 || y < 0 {
+            return None;
+        }
+
+        let (x, y) = (x as u32, y as u32);
+        let (col, row) = (x / self.key_size, y / self.key_size);
+        if col >= 4 || row >= 4 {
+            return None;
+        }
+
+        let (dx, dy) = (x % self.key_size, y % self.key_size);
+        let inside = |d: u32| d >= KEY_GAP / 2 && d < self.key_size - KEY_GAP / 2;
+        if !inside(dx) || !inside(dy) {
+            return None;
+        }
+
+        Some(KEYPAD[(row * 4 + col) as usize])
+    }
+
+    /// Returns the position and size of a key, without the gap around it
+    pub fn key_rect(&self, index: usize) -> (i32, i32, u32) {
+        let pos = KEYPAD.iter().position(|&k| k == index).expect("Invalid key");
+        let (col, row) = ((pos % 4) as u32, (pos / 4) as u32);
+
+        (
+            self.x + (col * self.key_size + KEY_GAP / 2) as i32,
+            (row * self.key_size + KEY_GAP / 2) as i32,
+            self.key_size - KEY_GAP,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_at_finds_keys() {
+        let layout = KeypadLayout::new(640, 400);
+
+        assert_eq!(layout.key_at(640 + 50, 50), Some(0x1));
+        assert_eq!(layout.key_at(640 + 350, 50), Some(0xC));
+        assert_eq!(layout.key_at(640 + 150, 350), Some(0x0));
+        assert_eq!(layout.key_at(640 + 399, 399), None);
+        assert_eq!(layout.key_at(640 + 100, 50), None);
+        assert_eq!(layout.key_at(50, 50), None);
+        assert_eq!(layout.key_at(640 + 50, 450), None);
+    }
+
+    #[test]
+    fn key_rect_is_under_its_key() {
+        let layout = KeypadLayout::new(640, 400);
+
+        for &key in KEYPAD.iter() {
+            let (x, y, size) = layout.key_rect(key);
+            assert_eq!(layout.key_at(x, y), Some(key));
+            assert_eq!(layout.key_at(x + size as i32 - 1, y + size as i32 - 1), Some(key));
+        }
+    }
+}
@@ -1,5 +1,9 @@
 //! Contains code used for the GUI
 
+pub mod controller;
+pub mod controls;
 pub mod display;
 pub mod keyboard;
-pub mod sound;
\ No newline at end of file
+pub mod keypad;
+pub mod keymap;
+pub mod sound;
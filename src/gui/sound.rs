@@ -1,17 +1,37 @@
 //! Contains code used to produce sound
 
 use sdl2;
-use sdl2::audio::{AudioDevice, AudioCallback, AudioSpecDesired};
+use sdl2::audio::{AudioQueue, AudioSpecDesired};
+use chip_8_rust::audio::wav::WavWriter;
+use chip_8_rust::audio::{FrameAudio, ToneSettings};
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::Path;
+
+/// Change of the volume for each press of a volume hotkey
+const VOLUME_STEP: f32 = 0.05;
+
+/// Number of frames of silence queued before the first frame, so that the
+/// queue doesn't run dry when a frame comes a bit late
+const PREFILL_FRAMES: usize = 2;
+
+/// Number of frames queued above which frames are dropped, to bound the
+/// latency when the emulation runs faster than the audio device
+const MAX_QUEUED_FRAMES: usize = 6;
 
 /// Struct containing the audio device
 pub struct Sound {
-    /// The audio device
-    device: AudioDevice<SquareWave>,
+    /// The audio queue of the device
+    queue: AudioQueue<f32>,
+    /// Renderer of the beep
+    audio: FrameAudio,
+    /// WAV file the rendered audio is recorded into, if recording
+    recorder: Option<WavWriter<BufWriter<File>>>,
 }
 
 impl Sound {
     /// Creates a new Sound instance
-    pub fn new(sdl_context: &sdl2::Sdl) -> Self {
+    pub fn new(sdl_context: &sdl2::Sdl, settings: ToneSettings) -> Self {
         let audio = sdl_context.audio().unwrap();
 
         let des_spec = AudioSpecDesired {
@@ -20,47 +40,74 @@ impl Sound {
             samples: None,
         };
 
-        let device = audio
-            .open_playback(None, &des_spec, |spec| {
-                SquareWave {
-                    phase_inc: 240.0 / spec.freq as f32,
-                    phase: 0.0,
-                    volume: 0.25,
-                }
-            })
+        let queue = audio
+            .open_queue::<f32, _>(None, &des_spec)
             .unwrap();
+        let mut audio = FrameAudio::new(settings, queue.spec().freq as u32);
+
+        for _ in 0..PREFILL_FRAMES {
+            queue.queue(&audio.render(false));
+        }
+        queue.resume();
+
+        Sound { queue, audio, recorder: None }
+    }
+
+    /// Queues the samples of a frame, with or without the beep
+    pub fn queue_frame(&mut self, beep: bool) {
+        let samples = self.audio.render(beep);
 
-        Sound { device }
+        if let Some(recorder) = &mut self.recorder {
+            if let Err(e) = recorder.write(&samples) {
+                eprintln!("Error recording audio: {}", e);
+                // Keeps what was recorded playable
+                if let Some(Err(e)) = self.recorder.take().map(WavWriter::finish) {
+                    eprintln!("Error recording audio: {}", e);
+                }
+            }
+        }
+
+        let queued = self.queue.size() as usize / std::mem::size_of::<f32>();
+        if queued < samples.len() * MAX_QUEUED_FRAMES {
+            self.queue.queue(&samples);
+        }
     }
 
-    /// Starts the beep
-    pub fn start_beep(&self) {
-        self.device.resume();
+    /// Mutes or unmutes the beep
+    pub fn toggle_mute(&mut self) {
+        let tone = self.audio.tone_mut();
+        let muted = !tone.muted();
+        tone.set_muted(muted);
+        println!("Sound {}", if muted { "muted" } else { "unmuted" });
     }
 
-    /// Ends the beep
-    pub fn stop_beep(&self) {
-        self.device.pause();
+    /// Raises or lowers the volume by a step
+    pub fn change_volume(&mut self, up: bool) {
+        let tone = self.audio.tone_mut();
+        let step = if up { VOLUME_STEP } else { -VOLUME_STEP };
+        let volume = tone.volume() + step;
+        tone.set_volume(volume);
+        println!("Volume: {:.0}%", tone.volume() * 100.0);
     }
-}
 
-/// AudioCallback instance for a square wave
-struct SquareWave {
-    /// Phase_inc of the wave
-    phase_inc: f32,
-    /// Phase of the wave
-    phase: f32,
-    /// Volume of the wave
-    volume: f32,
-}
+    /// Returns true while the audio is recorded
+    pub fn is_recording(&self) -> bool {
+        self.recorder.is_some()
+    }
 
-impl AudioCallback for SquareWave {
-    type Channel = f32;
+    /// Starts recording the audio into a WAV file
+    /// The samples are recorded as rendered, even those dropped to bound
+    /// the latency
+    pub fn start_recording(&mut self, path: &Path) -> io::Result<()> {
+        self.recorder = Some(WavWriter::create(path, self.audio.sample_rate())?);
+        Ok(())
+    }
 
-    fn callback(&mut self, out: &mut [f32]) {
-        for x in out.iter_mut() {
-            *x = self.volume * if self.phase < 0.5 { 1.0 } else { -1.0 };
-            self.phase = (self.phase + self.phase_inc) % 1.0;
+    /// Stops recording the audio, completing the WAV file
+    pub fn stop_recording(&mut self) -> io::Result<()> {
+        match self.recorder.take() {
+            Some(recorder) => recorder.finish().map(|_| ()),
+            None => Ok(()),
         }
     }
-}
\ No newline at end of file
+}
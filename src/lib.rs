@@ -0,0 +1,9 @@
+#![warn(clippy::missing_docs_in_private_items)]
+#![allow(clippy::many_single_char_names)]
+#![allow(clippy::collapsible_match)]
+
+//! Core of the Chip-8 emulator, without any user interface
+
+pub mod audio;
+pub mod emu;
+pub mod rom;
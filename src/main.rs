@@ -1,54 +1,656 @@
 #![warn(clippy::missing_docs_in_private_items)]
-#![allow(clippy::many_single_char_names)]
 
 //! Chip-8 emulator written in rust
 
 extern crate sdl2;
 
-use crate::emu::console::*;
+use chip_8_rust::audio::wav::WavWriter;
+use chip_8_rust::audio::{FrameAudio, ToneSettings, FRAME_RATE};
+use chip_8_rust::emu::console::*;
+use chip_8_rust::emu::font::{self, Font};
+use chip_8_rust::emu::machine::Machine;
+use chip_8_rust::emu::trace::{TraceFormat, TraceWriter};
+use chip_8_rust::rom;
+use chip_8_rust::rom::cartridge::Options;
+use chip_8_rust::rom::container::{self, Format, Unpacked};
+use chip_8_rust::rom::database::{Database, RomInfo};
+use crate::config::{Config, DisplayConfig, EmulationConfig, RememberedSettings, SoundConfig};
 use crate::gui::display::*;
+use crate::cli::{Action, Cli, DumpArgs, InfoArgs, MakePatchArgs, RunArgs};
+use clap::Parser;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
 use std::thread;
-use std::time::Duration;
-use crate::gui::keyboard::Keyboard;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use crate::gui::controller::Controllers;
+use crate::gui::controls::{Controls, Frames};
+use crate::gui::keyboard::{Command, Keyboard};
 use crate::gui::sound::Sound;
+use crate::flags::SavedFlags;
 
-mod emu;
+mod cli;
+mod config;
+mod flags;
 mod gui;
 
+/// Sample rate of the audio rendered in headless mode
+const HEADLESS_SAMPLE_RATE: u32 = 44100;
+/// Time between two checks of the ROM file with --watch
+const WATCH_INTERVAL: Duration = Duration::from_millis(250);
+
 fn main() {
+    let cli = Cli::parse();
+
+    match cli.action {
+        None => run(cli.run),
+        Some(Action::Run(args)) => run(*args),
+        Some(Action::Info(args)) => info(&args),
+        Some(Action::Dump(args)) => dump(&args),
+        Some(Action::MakePatch(args)) => make_patch(&args),
+    }
+}
+
+/// Runs a ROM, in a window or headless
+fn run(args: RunArgs) {
+    let rom_path = args.rom.clone().expect("The ROM is a required argument");
+
     println!("Chip-8 emulator");
 
-    //TODO ability to change FPS
-    /// "Speed" of the emulation
-    const FPS: u64 = 60;
+    let config_path = args.config.clone().or_else(Config::default_path);
+    let config = match &config_path {
+        Some(path) => Config::load(path).unwrap_or_else(|e| exit_with(&e)),
+        None => Config::default(),
+    };
+
+    let Unpacked { rom: original, options } = read_rom(&rom_path);
+    let rom = apply_patches(&original, &args.patch);
+    let rom_sha1 = rom::sha1_hex(&rom);
+
+    let emulation_args = EmulationConfig {
+        ips: args.ips,
+        platform: args.platform.clone(),
+        machine: args.machine.clone(),
+        font: args.font.clone(),
+    };
+
+    // The machine isn't hinted by the database, the code is analysed where
+    // the machine loads it
+    let none = EmulationConfig::default();
+    let machine = parse_machine(
+        config
+            .emulation(&rom_sha1, &emulation_args, &none)
+            .machine
+            .as_deref()
+            .unwrap_or("chip8"),
+    );
+
+    // Settings hinted by the options of Octo cartridges, then by the
+    // database, or the platform guessed from the instructions of unknown ROMs
+    // A patched ROM gets the settings of the original if it's unknown
+    // Cartridges hold their own quirks, so no platform is hinted for them
+    let database = load_database(args.database.as_deref());
+    let original_sha1 = rom::sha1_hex(&original);
+    let rom_info = database
+        .lookup(&rom_sha1)
+        .or_else(|| database.lookup(&original_sha1));
+    if let Some(info) = rom_info {
+        println!("{}", describe(&info));
+    }
+    let emulation_hints = EmulationConfig {
+        ips: options
+            .as_ref()
+            .and_then(|options| options.ips())
+            .or_else(|| rom_info.and_then(|info| info.ips())),
+        platform: match (&options, rom_info) {
+            (Some(_), _) => None,
+            (None, Some(info)) => info.platform.map(|p| p.id.clone()),
+            (None, None) => rom::guess_platform(&rom, machine.start_addr).map(String::from),
+        },
+        font: options.as_ref().and_then(|options| options.font()).map(String::from),
+        ..EmulationConfig::default()
+    };
+    let display_hints = DisplayConfig {
+        palette: options
+            .as_ref()
+            .and_then(|options| options.palette())
+            .or_else(|| rom_info.and_then(|info| info.palette())),
+        ..DisplayConfig::default()
+    };
+    let key_hints = rom_info.map(|info| info.rom.keys.clone()).unwrap_or_default();
+
+    let display_args = DisplayConfig {
+        scale: args.scale,
+        palette: args.palette.clone(),
+        fullscreen: args.fullscreen.then_some(true),
+        keypad: args.keypad.then_some(true),
+    };
+
+    if args.remember {
+        let path = config_path
+            .as_ref()
+            .unwrap_or_else(|| exit_with("no configuration directory"));
+        let settings = RememberedSettings {
+            emulation: emulation_args.clone(),
+            display: display_args.clone(),
+            keymap_preset: args.keymap.clone(),
+            keymap_mode: args.input_mode.clone(),
+        };
+        config::remember(path, &rom_sha1, &settings).unwrap_or_else(|e| exit_with(&e));
+        println!("Settings saved into {}", path.display());
+    }
+
+    let keymap = config
+        .keymap(&rom_sha1, args.keymap.as_deref(), args.input_mode.as_deref(), &key_hints)
+        .unwrap_or_else(|e| exit_with(&e));
+    let controller_mapping = config
+        .controller_mapping(&rom_sha1, &key_hints)
+        .unwrap_or_else(|e| exit_with(&e));
+    let tone_settings = config
+        .tone_settings(&rom_sha1, &SoundConfig {
+            frequency: args.beep_frequency,
+            waveform: args.waveform.clone(),
+            volume: args.volume,
+            attack: args.attack,
+            release: args.release,
+        })
+        .unwrap_or_else(|e| exit_with(&e));
+
+    let emulation = config.emulation(&rom_sha1, &emulation_args, &emulation_hints);
+    let display_config = config.display(&rom_sha1, &display_args, &display_hints);
+    let platform = emulation.platform.as_deref().map(|id| {
+        database
+            .platform(id)
+            .unwrap_or_else(|| exit_with(&format!("unknown platform '{}'", id)))
+    });
+    let palette = match &display_config.palette {
+        Some(palette) => palette.parse().unwrap_or_else(|e: String| exit_with(&e)),
+        None => Palette::default(),
+    };
+    let platform_ips = platform
+        .and_then(|p| p.default_tickrate)
+        .and_then(|tickrate| tickrate.checked_mul(60));
+    let ips = emulation.ips.or(platform_ips).unwrap_or(DEFAULT_IPS);
+    let scale = display_config.scale.unwrap_or(DEFAULT_SCALE);
+    if ips == 0 || scale == 0 {
+        exit_with("the speed and the scale must be positive");
+    }
+    let debug = args.verbose >= 2;
+
+    let mut cons: Console = Console::with_machine(machine);
+    if let Some(name) = &emulation.font {
+        cons.set_font(&load_font(name));
+    }
+    cons.load_rom_bytes(&rom);
+
+    if let Some(platform) = platform {
+        println!("Platform: {}", platform.name);
+        if platform.extends_chip8() {
+            eprintln!(
+                "Warning: most instructions added by {} aren't supported, the ROM may not run correctly",
+                platform.name
+            );
+        }
+        cons.set_quirks(platform.quirks());
+    } else if let Some(options) = &options {
+        cons.set_quirks(options.quirks());
+    }
+
+    if let Some(path) = &args.trace {
+        let format = match args.trace_format.as_str() {
+            "binary" => TraceFormat::Binary,
+            _ => TraceFormat::Text,
+        };
+        let tracer = TraceWriter::create(path, format)
+            .unwrap_or_else(|e| exit_with(&format!("can't create {}: {}", path.display(), e)));
+        cons.set_tracer(Some(tracer));
+    }
 
-    let filename = std::env::args()
-        .nth(1)
-        .expect("no filename given");
+    // Headless runs start with cleared flags and don't save them, so that
+    // they can be reproduced
+    if args.headless {
+        run_headless(&mut cons, args.frames, ips, tone_settings, args.audio_out.as_deref())
+            .unwrap_or_else(|e| exit_with(&format!("Error writing audio: {}", e)));
+        return;
+    }
 
-    let mut cons: Console = Console::new();
-    cons.load_rom(filename.as_str());
+    let mut saved_flags = SavedFlags::load(&rom_sha1);
+    if let Some(saved_flags) = &saved_flags {
+        cons.set_flags(saved_flags.flags());
+    }
 
     let sdl_context = sdl2::init().unwrap();
 
-    let mut display = Display::new(&sdl_context);
-    let mut keyboard = Keyboard::new(&sdl_context);
-    let sound = Sound::new(&sdl_context);
+    let mut display = Display::new(&sdl_context, &DisplayOptions {
+        scale,
+        palette,
+        keypad: display_config.keypad.unwrap_or(false),
+        fullscreen: display_config.fullscreen.unwrap_or(false),
+    });
+    let controllers = Controllers::new(&sdl_context, controller_mapping)
+        .map_err(|e| eprintln!("Controllers are unavailable: {}", e))
+        .ok();
+    let mut keyboard = Keyboard::new(&sdl_context, keymap, controllers, display.keypad());
+    let mut sound = Sound::new(&sdl_context, tone_settings);
 
+    let frame_duration = Duration::from_secs(1) / FRAME_RATE as u32;
+    let mut next_frame = Instant::now();
+    let mut frame_count = 0;
+    let mut controls = Controls::new(args.paused);
     let mut running = true;
+    let mut rom_modified = modified_time(&rom_path);
+    let mut next_watch = Instant::now() + WATCH_INTERVAL;
+
+    if controls.paused() {
+        println!("Paused");
+    }
+    display.set_title(&controls.title());
 
     while running {
-        running = keyboard.poll_keys(&mut cons, true);
+        for command in keyboard.poll_keys(&mut cons, args.verbose >= 1) {
+            match command {
+                Command::Quit => running = false,
+                Command::ToggleMute => sound.toggle_mute(),
+                Command::VolumeUp => sound.change_volume(true),
+                Command::VolumeDown => sound.change_volume(false),
+                Command::ToggleRecording => toggle_recording(&mut sound, &rom_path),
+                Command::Reset => {
+                    cons.reset();
+                    display.draw(&cons);
+                    println!("Reset");
+                },
+                Command::ReloadRom => reload_rom(&mut cons, &mut display, &rom_path, &args.patch),
+                _ => {
+                    let was_paused = controls.paused();
+                    controls.apply(command);
+                    if controls.paused() != was_paused {
+                        println!("{}", if controls.paused() { "Paused" } else { "Resumed" });
+                    }
+                    display.set_title(&controls.title());
+                },
+            }
+        }
+
+        // The file is checked a few times per second, a build may write it
+        // in several steps
+        if args.watch && Instant::now() >= next_watch {
+            next_watch += WATCH_INTERVAL;
+            let modified = modified_time(&rom_path);
+            if modified != rom_modified {
+                rom_modified = modified;
+                reload_rom(&mut cons, &mut display, &rom_path, &args.patch);
+            }
+        }
+
+        // Fast-forward runs frames until the time of the next frame of the
+        // window
+        let deadline = next_frame + frame_duration;
+        let mut draw = false;
+        let mut last_sound = None;
+        let mut run_frame = |cons: &mut Console| {
+            let frame = cons.run_frame(frame_instructions(ips, frame_count), debug);
+            frame_count += 1;
+            draw |= frame.draw;
+            last_sound = Some(frame.sound);
+        };
+        match controls.next_frames() {
+            Frames::Count(count) => (0..count).for_each(|_| run_frame(&mut cons)),
+            Frames::Unlimited => loop {
+                run_frame(&mut cons);
+                if Instant::now() >= deadline {
+                    break;
+                }
+            },
+        }
+
+        if draw || display.keypad_changed(&cons) { display.draw(&cons) };
+        // In slow motion, the sound goes on between the emulated frames
+        sound.queue_frame(last_sound.unwrap_or(!controls.paused() && cons.sound_on()));
 
-        let (draw, play_sound) = cons.cycle(false, false);
-        if draw { display.draw(&cons) };
+        if let (Some(saved_flags), Some(_)) = (&mut saved_flags, last_sound) {
+            saved_flags
+                .update(cons.flags())
+                .unwrap_or_else(|e| eprintln!("Error saving the flags, they won't be saved: {}", e));
+        }
 
-        if play_sound {
-            sound.start_beep();
+        // Wait for the next frame, catching up if late
+        next_frame += frame_duration;
+        let now = Instant::now();
+        if next_frame > now {
+            thread::sleep(next_frame - now);
         } else {
-            sound.stop_beep();
+            next_frame = now;
         }
+    }
+
+    sound.stop_recording().expect("Error writing audio");
+}
+
+/// Reads the ROM again from its file and restarts the console with it
+/// The current ROM is kept if the file can't be loaded
+fn reload_rom(cons: &mut Console, display: &mut Display, path: &Path, patches: &[PathBuf]) {
+    match try_read_rom(path).and_then(|unpacked| try_apply_patches(&unpacked.rom, patches)) {
+        Ok(rom) => {
+            cons.reload_rom(&rom);
+            display.draw(cons);
+            println!("ROM reloaded");
+        },
+        Err(e) => eprintln!("Error reloading the ROM: {}", e),
+    }
+}
+
+/// Shows the size, hash, platform and instructions of a ROM
+fn info(args: &InfoArgs) {
+    let data = read_file(&args.rom).unwrap_or_else(|e| exit_with(&e));
+    let Unpacked { rom, options } = unpack_rom(&args.rom, &data).unwrap_or_else(|e| exit_with(&e));
+    let rom_sha1 = rom::sha1_hex(&rom);
+    let database = load_database(args.database.as_deref());
+    let machine = parse_machine(&args.machine);
+
+    println!("File:     {}", args.rom.display());
+    match Format::detect(&data) {
+        Format::Zip => println!("Format:   zip archive"),
+        Format::OctoCartridge => println!("Format:   Octo cartridge"),
+        Format::Raw => (),
+    }
+    println!("Size:     {} bytes", rom.len());
+    println!("SHA-1:    {}", rom_sha1);
+    match database.lookup(&rom_sha1) {
+        Some(info) => {
+            println!("Program:  {}", describe(&info));
+            if let Some(platform) = info.platform {
+                println!("Platform: {}", platform.name);
+            }
+        },
+        None if options.is_some() => println!("Platform: quirks of the cartridge"),
+        None => match rom::guess_platform(&rom, machine.start_addr).and_then(|id| database.platform(id)) {
+            Some(platform) => println!("Platform: {} (guessed from the code)", platform.name),
+            None => println!("Platform: CHIP-8 (guessed from the code)"),
+        },
+    }
+    if let Some(options) = &options {
+        print_options(options);
+    }
+    if rom.len() > machine.max_rom_size() {
+        println!("Warning: only the first {} bytes fit into the memory", machine.max_rom_size());
+    }
+
+    let analysis = rom::analysis::analyse(&rom, machine.start_addr);
+    println!();
+    println!(
+        "Code reachable from {:#05X}: {} instructions",
+        machine.start_addr, analysis.instructions
+    );
+    print_addresses("SUPER-CHIP instructions", &analysis.super_chip);
+    print_addresses("XO-CHIP instructions", &analysis.xo_chip);
+    print_addresses("Machine code calls", &analysis.machine_code);
+    print_addresses("Computed jumps (not followed)", &analysis.computed_jumps);
+    print_addresses("Invalid opcodes", &analysis.invalid);
+    print_addresses("Shifts depending on the shift quirk", &analysis.shift_sensitive);
+    print_addresses("Loads and stores depending on the memory quirks", &analysis.memory_sensitive);
+    match analysis.suggested_platform().and_then(|id| database.platform(id)) {
+        Some(platform) => println!("  Suggested platform: {}", platform.name),
+        None => println!("  Suggested platform: any, the code doesn't depend on quirks"),
+    }
+
+    println!();
+    println!("Instructions (every pair of bytes, data included):");
+    for (name, count) in rom::opcode_stats(&rom) {
+        println!("  {:<8} {:>5}", name, count);
+    }
+}
+
+/// Prints the options of an Octo cartridge which are set
+fn print_options(options: &Options) {
+    if let Some(ips) = options.ips() {
+        println!("Speed:    {} instructions per second", ips);
+    }
+    if let Some(palette) = options.palette() {
+        println!("Palette:  {}", palette);
+    }
+    if let Some(font) = options.font() {
+        println!("Font:     {}", font);
+    }
+    let quirks: Vec<&str> = [
+        ("shift", options.shift_quirks),
+        ("load/store", options.load_store_quirks),
+        ("clip", options.clip_quirks),
+        ("jump", options.jump_quirks),
+        ("vblank", options.v_blank_quirks),
+        ("logic", options.logic_quirks),
+    ]
+    .iter()
+    .filter(|(_, enabled)| *enabled)
+    .map(|(name, _)| *name)
+    .collect();
+    if quirks.is_empty() {
+        println!("Quirks:   none");
+    } else {
+        println!("Quirks:   {}", quirks.join(", "));
+    }
+}
+
+/// Prints the addresses found by the analysis of a ROM, if any
+fn print_addresses(label: &str, addresses: &[u16]) {
+    if addresses.is_empty() {
+        return;
+    }
+
+    let shown: Vec<String> = addresses.iter().take(8).map(|a| format!("{:#05X}", a)).collect();
+    let more = if addresses.len() > shown.len() { ", ..." } else { "" };
+    println!("  {}: {} ({}{})", label, addresses.len(), shown.join(", "), more);
+}
+
+/// Runs a ROM for a number of instructions, then prints its memory and
+/// screen
+fn dump(args: &DumpArgs) {
+    let rom = read_rom(&args.rom).rom;
+
+    let mut cons = Console::with_machine(parse_machine(&args.machine));
+    cons.load_rom_bytes(&rom);
 
-        thread::sleep(Duration::from_millis(1000 / FPS));
+    // Whole frames at the default speed, so that the timers tick
+    let per_frame = frame_instructions(DEFAULT_IPS, 0) as u64;
+    for _ in 0..args.cycles / per_frame {
+        cons.run_frame(per_frame as usize, false);
     }
+    for _ in 0..args.cycles % per_frame {
+        cons.step(false, false);
+    }
+
+    if !args.screen {
+        cons.print_memory();
+    }
+    if !args.memory {
+        cons.print_buffer();
+    }
+}
+
+/// Reads a ROM file, exiting with an error if it can't be read or is empty
+/// The ROM is extracted from its container, such as a zip archive or an Octo
+/// cartridge
+fn read_rom(path: &Path) -> Unpacked {
+    try_read_rom(path).unwrap_or_else(|e| exit_with(&e))
+}
+
+/// Reads a ROM file, extracting it from its container
+fn try_read_rom(path: &Path) -> Result<Unpacked, String> {
+    unpack_rom(path, &read_file(path)?)
+}
+
+/// Reads the content of a ROM file
+fn read_file(path: &Path) -> Result<Vec<u8>, String> {
+    fs::read(path).map_err(|e| format!("can't read ROM {}: {}", path.display(), e))
+}
+
+/// Extracts the ROM from the content of a ROM file
+fn unpack_rom(path: &Path, data: &[u8]) -> Result<Unpacked, String> {
+    let unpacked = container::load(data)
+        .map_err(|e| format!("can't load ROM {}: {}", path.display(), e))?;
+
+    if unpacked.rom.is_empty() {
+        return Err(format!("ROM {} is empty", path.display()));
+    }
+
+    Ok(unpacked)
+}
+
+/// Parses the name of a machine, exiting with an error if it's unknown
+fn parse_machine(name: &str) -> Machine {
+    name.parse().unwrap_or_else(|e: String| exit_with(&e))
+}
+
+/// Returns the built-in font of the given name, or reads a font file,
+/// exiting with an error if it can't be read
+fn load_font(name: &str) -> Font {
+    if let Some(font) = font::by_name(name) {
+        return *font;
+    }
+
+    fs::read(name)
+        .map_err(|e| e.to_string())
+        .and_then(|bytes| font::from_bytes(&bytes))
+        .unwrap_or_else(|e| exit_with(&format!("can't load font {}: {}", name, e)))
+}
+
+/// Applies patches to a ROM in order, exiting with an error if one of them
+/// can't be read or doesn't apply
+fn apply_patches(rom: &[u8], patches: &[PathBuf]) -> Vec<u8> {
+    let rom = try_apply_patches(rom, patches).unwrap_or_else(|e| exit_with(&e));
+    for path in patches {
+        println!("Patch {} applied", path.display());
+    }
+
+    rom
+}
+
+/// Applies patches to a ROM in order
+fn try_apply_patches(rom: &[u8], patches: &[PathBuf]) -> Result<Vec<u8>, String> {
+    let mut rom = rom.to_vec();
+
+    for path in patches {
+        let patch = fs::read(path)
+            .map_err(|e| format!("can't read patch {}: {}", path.display(), e))?;
+        rom = rom::patch::apply(&rom, &patch)
+            .map_err(|e| format!("can't apply patch {}: {}", path.display(), e))?;
+    }
+
+    Ok(rom)
+}
+
+/// Returns the time the file was last modified, if available
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+/// Creates a BPS patch turning a ROM into a modified ROM
+fn make_patch(args: &MakePatchArgs) {
+    let original = read_rom(&args.original).rom;
+    let modified = read_rom(&args.modified).rom;
+
+    let patch = rom::patch::create_bps(&original, &modified);
+    fs::write(&args.output, &patch)
+        .unwrap_or_else(|e| exit_with(&format!("can't write {}: {}", args.output.display(), e)));
+    println!("Patch of {} bytes written into {}", patch.len(), args.output.display());
+}
+
+/// Loads the ROM database: the given directory, else the one in the
+/// configuration directory if it exists, else the embedded one
+fn load_database(dir: Option<&Path>) -> Database {
+    if let Some(dir) = dir {
+        return Database::load(dir).unwrap_or_else(|e| exit_with(&e));
+    }
+
+    match Config::database_dir().filter(|dir| dir.is_dir()) {
+        Some(dir) => Database::load(&dir).unwrap_or_else(|e| {
+            eprintln!("Error loading the ROM database: {}", e);
+            Database::embedded()
+        }),
+        None => Database::embedded(),
+    }
+}
+
+/// Returns the title and the authors of a ROM of the database
+fn describe(info: &RomInfo) -> String {
+    match &info.program.authors[..] {
+        [] => info.program.title.clone(),
+        authors => format!("{} by {}", info.program.title, authors.join(", ")),
+    }
+}
+
+/// Starts or stops recording the audio
+/// Recordings are named after the ROM and the time they start
+fn toggle_recording(sound: &mut Sound, rom_path: &Path) {
+    if sound.is_recording() {
+        match sound.stop_recording() {
+            Ok(()) => println!("Recording stopped"),
+            Err(e) => eprintln!("Error writing audio: {}", e),
+        }
+        return;
+    }
+
+    let stem = rom_path
+        .file_stem()
+        .map_or("chip-8".into(), |s| s.to_string_lossy());
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let path = PathBuf::from(format!("{}-{}.wav", stem, time));
+
+    match sound.start_recording(&path) {
+        Ok(()) => println!("Recording audio into {}", path.display()),
+        Err(e) => eprintln!("Error creating {}: {}", path.display(), e),
+    }
+}
+
+/// Runs the given number of frames without any window nor audio device,
+/// as fast as possible
+/// The audio is rendered from the emulated time and written into audio_out
+/// if given, the screen is printed at the end
+fn run_headless(
+    cons: &mut Console,
+    frames: u64,
+    ips: u64,
+    tone_settings: ToneSettings,
+    audio_out: Option<&Path>,
+) -> io::Result<()> {
+    let mut audio = FrameAudio::new(tone_settings, HEADLESS_SAMPLE_RATE);
+    let mut wav = match audio_out {
+        Some(path) => Some(WavWriter::create(path, HEADLESS_SAMPLE_RATE)?),
+        None => None,
+    };
+    let mut beep_frames = 0;
+
+    for frame_count in 0..frames {
+        let frame = cons.run_frame(frame_instructions(ips, frame_count), false);
+        if frame.sound {
+            beep_frames += 1;
+        }
+
+        let samples = audio.render(frame.sound);
+        if let Some(wav) = &mut wav {
+            wav.write(&samples)?;
+        }
+    }
+
+    if let Some(wav) = wav {
+        wav.finish()?;
+    }
+
+    cons.print_buffer();
+    println!(
+        "{} frames, beep during {} frames ({:.3} s)",
+        frames,
+        beep_frames,
+        beep_frames as f64 / FRAME_RATE as f64
+    );
+
+    Ok(())
+}
+
+/// Prints an error and exits
+fn exit_with(msg: &str) -> ! {
+    eprintln!("error: {}", msg);
+    process::exit(1);
 }
@@ -0,0 +1,264 @@
+//! Static analysis of the code of a ROM
+//!
+//! The code is followed from the address where the ROM is loaded through
+//! jumps, calls and skips, so that the data of the ROM isn't mistaken for
+//! instructions. The instructions added by the extensions of CHIP-8 decode
+//! as NIL, they are recognized from their opcodes.
+
+use crate::emu::instructions::Instr;
+use std::collections::HashSet;
+
+/// Number of instructions searched after a load or a store for a use of I
+const MEMORY_LOOKAHEAD: u16 = 8;
+
+/// Instruction set extension of an opcode
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Extension {
+    /// SUPER-CHIP (scrolling, high resolution, big sprites, flags)
+    SuperChip,
+    /// XO-CHIP (planes, long I, audio pattern, register ranges)
+    XoChip,
+}
+
+/// What the analysis found in the reachable code of a ROM
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Analysis {
+    /// Number of reachable instructions
+    pub instructions: usize,
+    /// Addresses of the SUPER-CHIP instructions
+    pub super_chip: Vec<u16>,
+    /// Addresses of the XO-CHIP instructions
+    pub xo_chip: Vec<u16>,
+    /// Addresses of the calls to machine code (0nnn)
+    pub machine_code: Vec<u16>,
+    /// Addresses of the invalid opcodes reached, where the analysis stopped
+    pub invalid: Vec<u16>,
+    /// Addresses of the jumps to a computed address (Bnnn), which aren't
+    /// followed
+    pub computed_jumps: Vec<u16>,
+    /// Addresses of the shifts of a register into another one (8xy6 and
+    /// 8xyE with x != y), whose result depends on the shift quirk
+    pub shift_sensitive: Vec<u16>,
+    /// Addresses of the loads and stores (Fx55 and Fx65) soon followed by
+    /// another use of I, which depends on the memory quirks
+    pub memory_sensitive: Vec<u16>,
+}
+
+impl Analysis {
+    /// Returns the identifier of the platform in the database the code was
+    /// most likely written for, or None if the quirks don't matter to it
+    pub fn suggested_platform(&self) -> Option<&'static str> {
+        if !self.xo_chip.is_empty() {
+            Some("xochip")
+        } else if !self.super_chip.is_empty() {
+            Some("superchip")
+        } else if !self.machine_code.is_empty() {
+            // Only the COSMAC VIP ran machine code
+            Some("originalChip8")
+        } else if !self.shift_sensitive.is_empty() || !self.memory_sensitive.is_empty() {
+            Some("modernChip8")
+        } else {
+            None
+        }
+    }
+}
+
+/// Analyses the code of a ROM loaded at start_addr, following it from there
+pub fn analyse(rom: &[u8], start_addr: u16) -> Analysis {
+    let opcode = |addr: u16| -> Option<u16> {
+        let offset = addr.checked_sub(start_addr)? as usize;
+        let word = rom.get(offset..offset + 2)?;
+        Some(u16::from_be_bytes([word[0], word[1]]))
+    };
+
+    let mut analysis = Analysis::default();
+    let mut visited = HashSet::new();
+    let mut pending = vec![start_addr];
+
+    while let Some(addr) = pending.pop() {
+        if !visited.insert(addr) {
+            continue;
+        }
+        let op = match opcode(addr) {
+            Some(op) => op,
+            None => continue,
+        };
+        analysis.instructions += 1;
+
+        // Address of the next instruction, XO-CHIP long loads are 4 bytes
+        let next = |addr: u16| match opcode(addr) {
+            Some(0xF000) => addr.wrapping_add(4),
+            _ => addr.wrapping_add(2),
+        };
+
+        match extension(op) {
+            Some(Extension::SuperChip) => analysis.super_chip.push(addr),
+            Some(Extension::XoChip) => analysis.xo_chip.push(addr),
+            None => (),
+        }
+
+        match Instr::decode(op) {
+            Instr::JP(target) => pending.push(target),
+            Instr::CALL(target) => pending.extend([target, next(addr)]),
+            Instr::RET => (),
+            Instr::JPV(_, _) => analysis.computed_jumps.push(addr),
+            Instr::SE(_, _)
+            | Instr::SNE(_, _)
+            | Instr::SEV(_, _)
+            | Instr::SNEV(_, _)
+            | Instr::SKP(_)
+            | Instr::SKNP(_) => {
+                let skipped = next(addr);
+                pending.extend([skipped, next(skipped)]);
+            },
+            Instr::SHR(x, y) | Instr::SHL(x, y) => {
+                if x != y {
+                    analysis.shift_sensitive.push(addr);
+                }
+                pending.push(next(addr));
+            },
+            Instr::LDIX(_) | Instr::LDXI(_) => {
+                if uses_i_again(&opcode, addr) {
+                    analysis.memory_sensitive.push(addr);
+                }
+                pending.push(next(addr));
+            },
+            // Exits the interpreter
+            Instr::NIL if op == 0x00FD => (),
+            Instr::NIL if extension(op).is_some() => pending.push(next(addr)),
+            Instr::NIL if op & 0xF000 == 0 => {
+                analysis.machine_code.push(addr);
+                pending.push(next(addr));
+            },
+            Instr::NIL => analysis.invalid.push(addr),
+            _ => pending.push(next(addr)),
+        }
+    }
+
+    for addresses in [
+        &mut analysis.super_chip,
+        &mut analysis.xo_chip,
+        &mut analysis.machine_code,
+        &mut analysis.invalid,
+        &mut analysis.computed_jumps,
+        &mut analysis.shift_sensitive,
+        &mut analysis.memory_sensitive,
+    ] {
+        addresses.sort_unstable();
+    }
+
+    analysis
+}
+
+/// Returns the extension which added an opcode, or None for the opcodes of
+/// CHIP-8 and the invalid ones
+fn extension(op: u16) -> Option<Extension> {
+    match op {
+        0x00FB..=0x00FF => Some(Extension::SuperChip), // Scroll, exit, resolution
+        _ if op & 0xFFF0 == 0x00C0 => Some(Extension::SuperChip), // Scroll down
+        _ if op & 0xF00F == 0xD000 => Some(Extension::SuperChip), // 16x16 sprite
+        _ if matches!(op & 0xF0FF, 0xF030 | 0xF075 | 0xF085) => Some(Extension::SuperChip), // Big font, flags
+        _ if op & 0xFFF0 == 0x00D0 => Some(Extension::XoChip), // Scroll up
+        _ if op & 0xF00E == 0x5002 => Some(Extension::XoChip), // Save and load ranges
+        0xF000 | 0xF002 => Some(Extension::XoChip),            // Long I, audio pattern
+        _ if matches!(op & 0xF0FF, 0xF001 | 0xF03A) => Some(Extension::XoChip), // Plane, pitch
+        _ => None,
+    }
+}
+
+/// Returns true if I is used again soon after the load or store at addr,
+/// without being set in between, in the straight-line code which follows
+fn uses_i_again(opcode: &impl Fn(u16) -> Option<u16>, addr: u16) -> bool {
+    for i in 1..=MEMORY_LOOKAHEAD {
+        let op = match opcode(addr.wrapping_add(2 * i)) {
+            Some(op) => op,
+            None => return false,
+        };
+
+        match Instr::decode(op) {
+            Instr::DRW(_, _, _)
+            | Instr::LDB(_)
+            | Instr::ADDI(_)
+            | Instr::LDIX(_)
+            | Instr::LDXI(_) => return true,
+            Instr::LDI(_)
+            | Instr::LDF(_)
+            | Instr::JP(_)
+            | Instr::JPV(_, _)
+            | Instr::CALL(_)
+            | Instr::RET
+            | Instr::NIL => return false,
+            _ => (),
+        }
+    }
+
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn data_after_a_jump_is_skipped() {
+        // JP 0x206, then data made of SUPER-CHIP opcodes, then a loop
+        let rom = [0x12, 0x06, 0x00, 0xFF, 0xF3, 0x85, 0x12, 0x06];
+        let analysis = analyse(&rom, 0x200);
+
+        assert_eq!(analysis.instructions, 2);
+        assert!(analysis.super_chip.is_empty());
+        assert_eq!(analysis.suggested_platform(), None);
+    }
+
+    #[test]
+    fn calls_and_skips_are_followed() {
+        let rom = [
+            0x22, 0x06, // CALL 0x206
+            0x12, 0x02, // JP 0x202
+            0x00, 0x00, // Never reached
+            0x30, 0x00, // SE v0, 0
+            0x00, 0xFF, // High resolution
+            0x00, 0xEE, // RET
+        ];
+        let analysis = analyse(&rom, 0x200);
+
+        assert_eq!(analysis.instructions, 5);
+        assert_eq!(analysis.super_chip, [0x208]);
+        assert!(analysis.machine_code.is_empty());
+        assert_eq!(analysis.suggested_platform(), Some("superchip"));
+    }
+
+    #[test]
+    fn extensions_and_machine_code() {
+        let rom = [0xF0, 0x00, 0x12, 0x34, 0xF1, 0x01, 0x01, 0x23, 0x00, 0xFD];
+        let analysis = analyse(&rom, 0x200);
+
+        assert_eq!(analysis.xo_chip, [0x200, 0x204]);
+        assert_eq!(analysis.super_chip, [0x208]);
+        assert_eq!(analysis.machine_code, [0x206]);
+        assert_eq!(analysis.suggested_platform(), Some("xochip"));
+
+        let analysis = analyse(&[0x01, 0x23, 0x12, 0x00], 0x200);
+        assert_eq!(analysis.suggested_platform(), Some("originalChip8"));
+    }
+
+    #[test]
+    fn quirk_sensitive_sequences() {
+        let rom = [
+            0x81, 0x26, // SHR v1, v2
+            0x83, 0x3E, // SHL v3, v3
+            0xF1, 0x55, // LD [I], v1
+            0xD0, 0x15, // DRW v0, v1, 5
+            0xF1, 0x65, // LD v1, [I]
+            0xA3, 0x00, // LD I, 0x300
+            0xD0, 0x15, // DRW v0, v1, 5
+            0xFF, 0xFF, // Invalid
+        ];
+        let analysis = analyse(&rom, 0x200);
+
+        assert_eq!(analysis.shift_sensitive, [0x200]);
+        assert_eq!(analysis.memory_sensitive, [0x204]);
+        assert_eq!(analysis.invalid, [0x20E]);
+        assert_eq!(analysis.suggested_platform(), Some("modernChip8"));
+    }
+}
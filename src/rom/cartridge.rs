@@ -0,0 +1,410 @@
+//! Octo cartridges: GIF images holding an Octo game in their pixels
+//!
+//! The 2 low bits of the color index of every pixel, frame after frame,
+//! hold the data of the cartridge, most significant bits first: 4 pixels
+//! per byte. The data starts with its size, on 4 big endian bytes, followed
+//! by a JSON object holding the source code of the game (`program`) and the
+//! options of Octo (`options`): speed, colors, quirks and font.
+//!
+//! The source code is assembled into the ROM by `rom::octo`.
+
+use crate::emu::quirks::Quirks;
+use crate::rom::octo;
+use serde::Deserialize;
+
+/// Largest code size of the LZW compression of GIF images
+const MAX_CODE_SIZE: u32 = 12;
+/// Largest number of pixels decoded from an image, 4 pixels holding a byte
+/// of the cartridge
+const MAX_PIXELS: usize = 16 << 20;
+
+/// Content of a cartridge
+#[derive(Debug, Deserialize)]
+pub struct Cartridge {
+    /// Octo source code of the game
+    pub program: String,
+    /// Options of the game in Octo
+    #[serde(default)]
+    pub options: Options,
+}
+
+impl Cartridge {
+    /// Assembles the ROM of the game
+    pub fn assemble(&self) -> Result<Vec<u8>, String> {
+        octo::assemble(&self.program).map_err(|e| format!("can't assemble the cartridge: {}", e))
+    }
+}
+
+/// Options of a game in Octo
+/// Missing quirks are disabled, as in Octo
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Options {
+    /// Instructions executed per frame
+    pub tickrate: Option<u64>,
+    /// Color of the lit pixels, as "#RRGGBB"
+    pub fill_color: Option<String>,
+    /// Color of the unlit pixels, as "#RRGGBB"
+    pub background_color: Option<String>,
+    /// 8xy6 and 8xyE shift vx in place
+    #[serde(default)]
+    pub shift_quirks: bool,
+    /// Fx55 and Fx65 leave I unchanged
+    #[serde(default)]
+    pub load_store_quirks: bool,
+    /// Sprites are clipped at the edges of the screen instead of wrapping
+    #[serde(default)]
+    pub clip_quirks: bool,
+    /// Bxnn jumps to xnn + vx
+    #[serde(default)]
+    pub jump_quirks: bool,
+    /// Drawing waits for the start of the next frame
+    #[serde(default)]
+    pub v_blank_quirks: bool,
+    /// 8xy1, 8xy2 and 8xy3 reset vf
+    #[serde(default)]
+    pub logic_quirks: bool,
+    /// Font of the hexadecimal digits
+    pub font_style: Option<String>,
+}
+
+impl Options {
+    /// Returns the quirks of the interpreter
+    pub fn quirks(&self) -> Quirks {
+        Quirks {
+            shift: self.shift_quirks,
+            memory_increment_by_x: false,
+            memory_leave_i_unchanged: self.load_store_quirks,
+            wrap: !self.clip_quirks,
+            jump: self.jump_quirks,
+            vblank: self.v_blank_quirks,
+            logic: self.logic_quirks,
+        }
+    }
+
+    /// Returns the instructions executed per second
+    pub fn ips(&self) -> Option<u64> {
+        self.tickrate.and_then(|tickrate| tickrate.checked_mul(60))
+    }
+
+    /// Returns the lit and unlit colors, as "RRGGBB,RRGGBB"
+    pub fn palette(&self) -> Option<String> {
+        let foreground = self.fill_color.as_ref()?.trim_start_matches('#');
+        let background = self.background_color.as_ref()?.trim_start_matches('#');
+        Some(format!("{},{}", foreground, background))
+    }
+
+    /// Returns the name of the font, as given to `--font`
+    /// Octo's own font is the default one
+    pub fn font(&self) -> Option<&'static str> {
+        match self.font_style.as_deref()? {
+            "vip" => Some("vip"),
+            "dream6800" => Some("dream6800"),
+            "eti660" => Some("eti660"),
+            "fish" => Some("fish"),
+            _ => None,
+        }
+    }
+}
+
+/// Decodes the cartridge held by a GIF image
+pub fn decode(gif: &[u8]) -> Result<Cartridge, String> {
+    let pixels = gif_pixels(gif)?;
+    let bytes: Vec<u8> = pixels
+        .chunks_exact(4)
+        .map(|p| (p[0] & 3) << 6 | (p[1] & 3) << 4 | (p[2] & 3) << 2 | (p[3] & 3))
+        .collect();
+
+    let size = match bytes.get(..4) {
+        Some(size) => u32::from_be_bytes([size[0], size[1], size[2], size[3]]) as usize,
+        None => return Err("the image holds no cartridge".to_string()),
+    };
+    let payload = bytes
+        .get(4..4usize.saturating_add(size))
+        .ok_or("the image holds no cartridge, or a truncated one")?;
+
+    let cartridge: Cartridge =
+        serde_json::from_slice(payload).map_err(|e| format!("invalid cartridge: {}", e))?;
+    if cartridge.options.tickrate.is_some() && cartridge.options.ips().is_none() {
+        return Err("invalid cartridge: the tickrate is too large".to_string());
+    }
+
+    Ok(cartridge)
+}
+
+/// Reads bytes of a GIF image
+struct Reader<'a> {
+    /// The image
+    data: &'a [u8],
+    /// Offset of the next byte
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    /// Reads a number of bytes
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos + len)
+            .ok_or("the GIF image is truncated")?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    /// Reads a byte
+    fn byte(&mut self) -> Result<u8, String> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    /// Reads a little endian 16 bits number
+    fn word(&mut self) -> Result<usize, String> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]) as usize)
+    }
+
+    /// Reads data sub-blocks, up to the empty one
+    fn sub_blocks(&mut self) -> Result<Vec<u8>, String> {
+        let mut data = vec![];
+        loop {
+            let len = self.byte()? as usize;
+            if len == 0 {
+                return Ok(data);
+            }
+            data.extend_from_slice(self.bytes(len)?);
+        }
+    }
+}
+
+/// Returns the color indexes of the pixels of every frame of a GIF image,
+/// one frame after the other
+/// Images of more than MAX_PIXELS pixels are refused
+fn gif_pixels(gif: &[u8]) -> Result<Vec<u8>, String> {
+    let mut reader = Reader { data: gif, pos: 0 };
+    let signature = reader.bytes(6)?;
+    if signature != b"GIF87a" && signature != b"GIF89a" {
+        return Err("not a GIF image".to_string());
+    }
+
+    // Logical screen descriptor and global color table
+    reader.bytes(4)?;
+    let flags = reader.byte()?;
+    reader.bytes(2)?;
+    if flags & 0x80 != 0 {
+        reader.bytes(3 << ((flags & 7) + 1))?;
+    }
+
+    let mut pixels = vec![];
+    loop {
+        match reader.byte()? {
+            // Extension
+            0x21 => {
+                reader.byte()?;
+                reader.sub_blocks()?;
+            },
+            // Image
+            0x2C => {
+                reader.bytes(4)?;
+                let width = reader.word()?;
+                let height = reader.word()?;
+                let flags = reader.byte()?;
+                if flags & 0x80 != 0 {
+                    reader.bytes(3 << ((flags & 7) + 1))?;
+                }
+                if flags & 0x40 != 0 {
+                    return Err("interlaced GIF images aren't supported".to_string());
+                }
+
+                if pixels.len() + width * height > MAX_PIXELS {
+                    return Err("the GIF image is too large".to_string());
+                }
+
+                let min_code_size = reader.byte()?;
+                let data = reader.sub_blocks()?;
+                let frame = lzw_decode(min_code_size, &data, width * height)?;
+                if frame.len() < width * height {
+                    return Err("a frame of the GIF image is truncated".to_string());
+                }
+                pixels.extend_from_slice(&frame);
+            },
+            // Trailer
+            0x3B => return Ok(pixels),
+            block => return Err(format!("invalid block {:#04x} in the GIF image", block)),
+        }
+    }
+}
+
+/// Decompresses the LZW data of a GIF frame into color indexes
+/// The decompression stops once the given number of indexes is reached
+fn lzw_decode(min_code_size: u8, data: &[u8], limit: usize) -> Result<Vec<u8>, String> {
+    let min_code_size = min_code_size as u32;
+    if !(1..MAX_CODE_SIZE).contains(&min_code_size) {
+        return Err("invalid LZW code size in the GIF image".to_string());
+    }
+    let clear = 1usize << min_code_size;
+    let end = clear + 1;
+    let roots: Vec<Vec<u8>> = (0..clear).map(|i| vec![i as u8]).chain([vec![], vec![]]).collect();
+
+    let mut table = roots.clone();
+    let mut code_size = min_code_size + 1;
+    let mut previous: Option<Vec<u8>> = None;
+    let mut out = vec![];
+    let (mut bits, mut bit_count) = (0u32, 0u32);
+    let mut bytes = data.iter();
+
+    loop {
+        while bit_count < code_size {
+            match bytes.next() {
+                Some(bt) => {
+                    bits |= (*bt as u32) << bit_count;
+                    bit_count += 8;
+                },
+                None => return Ok(out),
+            }
+        }
+        let code = (bits & ((1 << code_size) - 1)) as usize;
+        bits >>= code_size;
+        bit_count -= code_size;
+
+        if code == clear {
+            table = roots.clone();
+            code_size = min_code_size + 1;
+            previous = None;
+            continue;
+        }
+        if code == end {
+            return Ok(out);
+        }
+
+        let entry = match (table.get(code), &previous) {
+            (Some(entry), _) if code < clear || code > end => entry.clone(),
+            (None, Some(previous)) if code == table.len() => {
+                let mut entry = previous.clone();
+                entry.push(previous[0]);
+                entry
+            },
+            _ => return Err("invalid LZW data in the GIF image".to_string()),
+        };
+        out.extend_from_slice(&entry);
+        if out.len() >= limit {
+            out.truncate(limit);
+            return Ok(out);
+        }
+
+        if let Some(mut new) = previous.take() {
+            if table.len() < 1 << MAX_CODE_SIZE {
+                new.push(entry[0]);
+                table.push(new);
+            }
+        }
+        if table.len() == 1 << code_size && code_size < MAX_CODE_SIZE {
+            code_size += 1;
+        }
+        previous = Some(entry);
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Compresses color indexes with LZW, without ever growing the code
+    /// size: the table is cleared every 2 codes
+    fn lzw_encode(min_code_size: u8, pixels: &[u8]) -> Vec<u8> {
+        let code_size = min_code_size as u32 + 1;
+        let clear = 1u32 << min_code_size;
+        let mut out = vec![];
+        let (mut bits, mut bit_count) = (0u32, 0u32);
+        let mut write = |code: u32, out: &mut Vec<u8>| {
+            bits |= code << bit_count;
+            bit_count += code_size;
+            while bit_count >= 8 {
+                out.push(bits as u8);
+                bits >>= 8;
+                bit_count -= 8;
+            }
+        };
+
+        for pair in pixels.chunks(2) {
+            write(clear, &mut out);
+            for pixel in pair {
+                write(*pixel as u32, &mut out);
+            }
+        }
+        write(clear + 1, &mut out);
+        write(0, &mut out);
+        out
+    }
+
+    /// Creates a GIF image of 2 frames holding the given cartridge
+    pub(crate) fn cartridge_gif(json: &str) -> Vec<u8> {
+        let mut data = (json.len() as u32).to_be_bytes().to_vec();
+        data.extend_from_slice(json.as_bytes());
+        let mut pixels: Vec<u8> = data
+            .iter()
+            .flat_map(|bt| [bt >> 6, bt >> 4, bt >> 2, *bt].map(|p| p & 3 | 4))
+            .collect();
+
+        // 2 frames of 32 pixels per row
+        let height = pixels.len().div_ceil(64);
+        pixels.resize(height * 64, 4);
+
+        let mut gif = b"GIF89a".to_vec();
+        gif.extend_from_slice(&[32, 0, height as u8, 0, 0x82, 0, 0]);
+        gif.extend_from_slice(&[0; 24]);
+        gif.extend_from_slice(&[0x21, 0xF9, 4, 0, 0, 0, 0, 0]);
+        for frame in pixels.chunks(height * 32) {
+            gif.extend_from_slice(&[0x2C, 0, 0, 0, 0, 32, 0, height as u8, 0, 0, 3]);
+            for block in lzw_encode(3, frame).chunks(255) {
+                gif.push(block.len() as u8);
+                gif.extend_from_slice(block);
+            }
+            gif.push(0);
+        }
+        gif.push(0x3B);
+        gif
+    }
+
+    #[test]
+    fn lzw_round_trip() {
+        let pixels: Vec<u8> = (0..200).map(|i| (i * 7 % 8) as u8).collect();
+        assert_eq!(lzw_decode(3, &lzw_encode(3, &pixels), usize::MAX).unwrap(), pixels);
+        assert_eq!(lzw_decode(3, &lzw_encode(3, &pixels), 10).unwrap(), pixels[..10]);
+
+        // Clear, 0, then the code of "00" which is being defined, end
+        assert_eq!(lzw_decode(2, &[0x84, 0x0B], usize::MAX).unwrap(), [0, 0, 0]);
+    }
+
+    #[test]
+    fn cartridge_program_and_options() {
+        let json = r##"{"program": ": main\n  v0 := 1\n", "options": {
+            "tickrate": 20, "fillColor": "#FFCC00", "backgroundColor": "#996600",
+            "shiftQuirks": true, "clipQuirks": true, "fontStyle": "vip", "maxSize": 3215
+        }}"##;
+        let cartridge = decode(&cartridge_gif(json)).unwrap();
+
+        assert_eq!(cartridge.assemble().unwrap(), [0x12, 0x02, 0x60, 0x01]);
+        let options = &cartridge.options;
+        assert_eq!(options.ips(), Some(1200));
+        assert_eq!(options.palette().as_deref(), Some("FFCC00,996600"));
+        assert_eq!(options.font(), Some("vip"));
+        let quirks = options.quirks();
+        assert!(quirks.shift && !quirks.wrap && !quirks.memory_leave_i_unchanged);
+    }
+
+    #[test]
+    fn images_without_cartridge_are_refused() {
+        let gif = cartridge_gif("not json");
+        assert!(decode(&gif).unwrap_err().starts_with("invalid cartridge"));
+        assert!(decode(b"GIF89a\x00\x00").unwrap_err().contains("truncated"));
+    }
+
+    #[test]
+    fn crafted_sizes_are_refused() {
+        let gif = cartridge_gif(r#"{"program": "", "options": {"tickrate": 18446744073709551615}}"#);
+        assert_eq!(decode(&gif).unwrap_err(), "invalid cartridge: the tickrate is too large");
+
+        let mut gif = b"GIF89a\x00\x00\x00\x00\x00\x00\x00".to_vec();
+        gif.extend_from_slice(&[0x2C, 0, 0, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF, 0, 2]);
+        assert_eq!(decode(&gif).unwrap_err(), "the GIF image is too large");
+    }
+}
@@ -0,0 +1,173 @@
+//! Files holding a ROM, recognized by their first bytes
+//!
+//! Besides raw binaries, ROMs are often distributed in zip archives, and
+//! Octo games as cartridges: GIF images holding the Octo source code of the
+//! game and its options in their pixels, see `rom::cartridge`.
+
+use crate::rom::cartridge::{self, Options};
+use std::io::{Cursor, Read};
+use zip::ZipArchive;
+
+/// Extensions of the ROM files, to find the ROM in an archive with other
+/// files
+const ROM_EXTENSIONS: [&str; 5] = ["ch8", "c8", "sc8", "xo8", "rom"];
+/// Largest ROM extracted from an archive, the size of the largest memory
+const MAX_ROM_SIZE: usize = 0x10000;
+
+/// Format of a file holding a ROM
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    /// The ROM itself
+    Raw,
+    /// Zip archive
+    Zip,
+    /// Octo cartridge, a GIF image
+    OctoCartridge,
+}
+
+impl Format {
+    /// Recognizes the format of a file from its first bytes
+    pub fn detect(data: &[u8]) -> Format {
+        if data.starts_with(b"PK\x03\x04") || data.starts_with(b"PK\x05\x06") {
+            Format::Zip
+        } else if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+            Format::OctoCartridge
+        } else {
+            Format::Raw
+        }
+    }
+}
+
+/// ROM held by a file, along with the settings the file holds
+#[derive(Debug, Clone, PartialEq)]
+pub struct Unpacked {
+    /// The ROM
+    pub rom: Vec<u8>,
+    /// Options of the game, for Octo cartridges
+    pub options: Option<Options>,
+}
+
+/// Returns the ROM held by a file, whatever its format
+pub fn unpack(data: &[u8]) -> Result<Vec<u8>, String> {
+    load(data).map(|unpacked| unpacked.rom)
+}
+
+/// Returns the ROM held by a file, whatever its format, along with the
+/// settings the file holds
+/// The source code of Octo cartridges is assembled
+pub fn load(data: &[u8]) -> Result<Unpacked, String> {
+    match Format::detect(data) {
+        Format::Raw => Ok(Unpacked { rom: data.to_vec(), options: None }),
+        Format::Zip => Ok(Unpacked { rom: unzip(data)?, options: None }),
+        Format::OctoCartridge => {
+            let cartridge = cartridge::decode(data)?;
+            Ok(Unpacked { rom: cartridge.assemble()?, options: Some(cartridge.options) })
+        },
+    }
+}
+
+/// Returns the ROM of a zip archive: its only file, or its only file with
+/// the extension of a ROM
+fn unzip(data: &[u8]) -> Result<Vec<u8>, String> {
+    let mut archive =
+        ZipArchive::new(Cursor::new(data)).map_err(|e| format!("invalid zip archive: {}", e))?;
+
+    let files: Vec<String> = archive
+        .file_names()
+        .filter(|name| !name.ends_with('/'))
+        .map(String::from)
+        .collect();
+    let roms: Vec<&String> = files
+        .iter()
+        .filter(|name| {
+            let extension = name.rsplit('.').next().unwrap_or("").to_lowercase();
+            ROM_EXTENSIONS.contains(&extension.as_str())
+        })
+        .collect();
+
+    let name = match (&files[..], &roms[..]) {
+        ([file], _) => file,
+        (_, [rom]) => *rom,
+        ([], _) => return Err("the zip archive is empty".to_string()),
+        (_, _) => {
+            return Err(format!(
+                "the zip archive holds several files, none of them or several of them ROMs: {}",
+                files.join(", ")
+            ))
+        },
+    };
+
+    // The size given by the archive can't be trusted, the extraction stops
+    // past the largest ROM
+    let mut rom = vec![];
+    archive
+        .by_name(name)
+        .and_then(|file| Ok(file.take(MAX_ROM_SIZE as u64 + 1).read_to_end(&mut rom)?))
+        .map_err(|e| format!("can't extract {} from the zip archive: {}", name, e))?;
+    if rom.len() > MAX_ROM_SIZE {
+        return Err(format!("{} is too large to be a ROM", name));
+    }
+
+    Ok(rom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rom::cartridge::tests::cartridge_gif;
+    use std::io::Write;
+    use zip::write::SimpleFileOptions;
+    use zip::ZipWriter;
+
+    /// Creates a zip archive holding the given files
+    fn archive(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut zip = ZipWriter::new(Cursor::new(vec![]));
+        for (name, content) in files {
+            zip.start_file(*name, SimpleFileOptions::default()).unwrap();
+            zip.write_all(content).unwrap();
+        }
+        zip.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn raw_roms_are_kept() {
+        let rom = [0x12, 0x00];
+        assert_eq!(Format::detect(&rom), Format::Raw);
+        assert_eq!(unpack(&rom).unwrap(), rom);
+    }
+
+    #[test]
+    fn rom_is_extracted_from_zip() {
+        let zip = archive(&[("game.bin", &[0x12, 0x00])]);
+        assert_eq!(Format::detect(&zip), Format::Zip);
+        assert_eq!(unpack(&zip).unwrap(), [0x12, 0x00]);
+
+        let zip = archive(&[("readme.txt", b"Hello"), ("game.ch8", &[0x00, 0xE0])]);
+        assert_eq!(unpack(&zip).unwrap(), [0x00, 0xE0]);
+
+        let zip = archive(&[("a.ch8", &[0x00, 0xE0]), ("b.ch8", &[0x00, 0xE0])]);
+        assert!(unpack(&zip).is_err());
+    }
+
+    #[test]
+    fn huge_files_are_refused() {
+        let zip = archive(&[("game.ch8", &[0; MAX_ROM_SIZE])]);
+        assert_eq!(unpack(&zip).unwrap().len(), MAX_ROM_SIZE);
+
+        let zip = archive(&[("game.ch8", &[0; MAX_ROM_SIZE + 1])]);
+        assert_eq!(unpack(&zip).unwrap_err(), "game.ch8 is too large to be a ROM");
+    }
+
+    #[test]
+    fn octo_cartridges_are_assembled() {
+        let gif = cartridge_gif(r#"{"program": ": main v0 := 2 ;", "options": {"tickrate": 7}}"#);
+        assert_eq!(Format::detect(&gif), Format::OctoCartridge);
+
+        let unpacked = load(&gif).unwrap();
+        assert_eq!(unpacked.rom, [0x12, 0x02, 0x60, 0x02, 0x00, 0xEE]);
+        assert_eq!(unpacked.options.unwrap().ips(), Some(420));
+
+        let gif = cartridge_gif(r#"{"program": ": main jump nowhere"}"#);
+        assert!(unpack(&gif).unwrap_err().contains("undefined label"));
+    }
+}
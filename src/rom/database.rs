@@ -0,0 +1,258 @@
+//! ROM database, in the format of the community CHIP-8 database
+//!
+//! The database is made of three JSON files: `programs.json` (the programs
+//! and their ROMs by SHA-1 hash), `sha1-hashes.json` (the index of the
+//! program of each hash) and `platforms.json` (the speed and quirks of each
+//! platform). Fields this emulator doesn't use are ignored.
+
+use crate::emu::quirks::Quirks;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// Programs of the embedded database
+const PROGRAMS: &str = include_str!("../../data/database/programs.json");
+/// Hashes of the embedded database
+const HASHES: &str = include_str!("../../data/database/sha1-hashes.json");
+/// Platforms of the embedded database
+const PLATFORMS: &str = include_str!("../../data/database/platforms.json");
+
+/// A program, which can have several ROMs (versions, ports)
+#[derive(Debug, Clone, Deserialize)]
+pub struct Program {
+    /// Title of the program
+    pub title: String,
+    /// Authors of the program
+    #[serde(default)]
+    pub authors: Vec<String>,
+    /// ROMs of the program, by SHA-1 hash
+    #[serde(default)]
+    pub roms: HashMap<String, RomEntry>,
+}
+
+/// A ROM of a program
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RomEntry {
+    /// Name of the file of the ROM
+    pub file: Option<String>,
+    /// Identifiers of the platforms the ROM runs on, the preferred first
+    #[serde(default)]
+    pub platforms: Vec<String>,
+    /// Instructions executed per frame
+    pub tickrate: Option<u64>,
+    /// Console keys used by the ROM, by role: up, down, left, right, a, b...
+    #[serde(default)]
+    pub keys: HashMap<String, u8>,
+    /// Colors of the ROM
+    pub colors: Option<Colors>,
+}
+
+/// Colors of a ROM
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Colors {
+    /// Colors of the pixels, as "#RRGGBB": unlit first, then lit
+    #[serde(default)]
+    pub pixels: Vec<String>,
+}
+
+/// A platform of the database
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Platform {
+    /// Identifier of the platform
+    pub id: String,
+    /// Name of the platform
+    pub name: String,
+    /// Instructions executed per frame
+    pub default_tickrate: Option<u64>,
+    /// Quirks of the platform, by name
+    #[serde(default)]
+    pub quirks: HashMap<String, bool>,
+}
+
+impl Platform {
+    /// Returns the quirks of the platform
+    pub fn quirks(&self) -> Quirks {
+        Quirks::from_names(&self.quirks)
+    }
+
+    /// Returns true if the platform adds instructions to CHIP-8
+    pub fn extends_chip8(&self) -> bool {
+        !matches!(self.id.as_str(), "originalChip8" | "modernChip8")
+    }
+}
+
+/// What the database knows about a ROM
+#[derive(Debug, Clone, Copy)]
+pub struct RomInfo<'a> {
+    /// Program of the ROM
+    pub program: &'a Program,
+    /// The ROM itself
+    pub rom: &'a RomEntry,
+    /// Preferred platform of the ROM, if known by the database
+    pub platform: Option<&'a Platform>,
+}
+
+impl RomInfo<'_> {
+    /// Returns the instructions executed per second
+    pub fn ips(&self) -> Option<u64> {
+        self.rom
+            .tickrate
+            .or_else(|| self.platform.and_then(|p| p.default_tickrate))
+            .and_then(|tickrate| tickrate.checked_mul(60))
+    }
+
+    /// Returns the lit and unlit colors, as "RRGGBB,RRGGBB"
+    pub fn palette(&self) -> Option<String> {
+        let pixels = &self.rom.colors.as_ref()?.pixels;
+        match &pixels[..] {
+            [background, foreground, ..] => Some(format!(
+                "{},{}",
+                foreground.trim_start_matches('#'),
+                background.trim_start_matches('#')
+            )),
+            _ => None,
+        }
+    }
+}
+
+/// The ROM database
+#[derive(Debug, Clone)]
+pub struct Database {
+    /// Programs
+    programs: Vec<Program>,
+    /// Index of the program of each SHA-1 hash
+    hashes: HashMap<String, usize>,
+    /// Platforms
+    platforms: Vec<Platform>,
+}
+
+impl Database {
+    /// Loads the database embedded into the emulator
+    pub fn embedded() -> Self {
+        Self::parse(PROGRAMS, HASHES, PLATFORMS).expect("Invalid embedded database")
+    }
+
+    /// Loads a database from a directory holding its three files
+    /// The embedded platforms are used if the directory has none
+    pub fn load(dir: &Path) -> Result<Self, String> {
+        let read = |name: &str| {
+            let path = dir.join(name);
+            fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))
+        };
+
+        let platforms = if dir.join("platforms.json").exists() {
+            read("platforms.json")?
+        } else {
+            PLATFORMS.to_string()
+        };
+
+        Self::parse(&read("programs.json")?, &read("sha1-hashes.json")?, &platforms)
+            .map_err(|e| format!("{}: {}", dir.display(), e))
+    }
+
+    /// Parses the three files of a database
+    pub fn parse(programs: &str, hashes: &str, platforms: &str) -> Result<Self, String> {
+        let programs: Vec<Program> =
+            serde_json::from_str(programs).map_err(|e| format!("programs.json: {}", e))?;
+        let hashes: HashMap<String, usize> =
+            serde_json::from_str(hashes).map_err(|e| format!("sha1-hashes.json: {}", e))?;
+        let platforms: Vec<Platform> =
+            serde_json::from_str(platforms).map_err(|e| format!("platforms.json: {}", e))?;
+
+        Ok(Database { programs, hashes, platforms })
+    }
+
+    /// Looks up a ROM by its SHA-1 hash
+    pub fn lookup(&self, sha1: &str) -> Option<RomInfo<'_>> {
+        let program = self.programs.get(*self.hashes.get(sha1)?)?;
+        let rom = program.roms.get(sha1)?;
+        let platform = rom.platforms.iter().find_map(|id| self.platform(id));
+
+        Some(RomInfo { program, rom, platform })
+    }
+
+    /// Returns the platform with the given identifier
+    pub fn platform(&self, id: &str) -> Option<&Platform> {
+        self.platforms.iter().find(|p| p.id == id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Programs of the test database
+    const TEST_PROGRAMS: &str = r##"[
+        {
+            "title": "Test Game",
+            "authors": ["Someone"],
+            "release": "2024",
+            "roms": {
+                "aaaa": {
+                    "file": "test.ch8",
+                    "platforms": ["unknownPlatform", "superchip"],
+                    "keys": { "left": 4, "right": 6, "a": 5 },
+                    "colors": { "pixels": ["#101010", "#ffcc00"], "buzzer": "#ffffff" },
+                    "embeddedTitle": "TEST"
+                }
+            }
+        }
+    ]"##;
+
+    #[test]
+    fn embedded_database_parses() {
+        let db = Database::embedded();
+        assert!(db.platform("originalChip8").is_some());
+        assert!(db.lookup("0000").is_none());
+    }
+
+    #[test]
+    fn embedded_database_knows_the_distributed_roms() {
+        let db = Database::embedded();
+        let info = db.lookup("961bd4da1bd7e5091fe59f53cc6328574db022df").unwrap();
+        assert_eq!(info.program.title, "Bounce");
+        assert_eq!(info.platform.unwrap().id, "modernChip8");
+        assert_eq!(info.ips(), Some(20 * 60));
+
+        // The programs of the crate are merged into the community ones
+        let own: Vec<Program> =
+            serde_json::from_str(include_str!("../../data/database/crate-programs.json")).unwrap();
+        for program in &own {
+            for sha1 in program.roms.keys() {
+                assert_eq!(db.lookup(sha1).unwrap().program.title, program.title);
+            }
+        }
+
+        for name in ["bounce", "maze", "sort"] {
+            let path = format!("{}/benches/roms/{}.ch8", env!("CARGO_MANIFEST_DIR"), name);
+            let sha1 = crate::rom::sha1_hex(&fs::read(&path).unwrap());
+            let info = db.lookup(&sha1).unwrap_or_else(|| panic!("{} isn't in the database", path));
+            assert_eq!(info.rom.file.as_deref(), Some(format!("{}.ch8", name).as_str()));
+        }
+    }
+
+    #[test]
+    fn lookup_by_hash() {
+        let db = Database::parse(TEST_PROGRAMS, r#"{ "aaaa": 0 }"#, PLATFORMS).unwrap();
+        let info = db.lookup("aaaa").unwrap();
+
+        assert_eq!(info.program.title, "Test Game");
+        assert_eq!(info.platform.unwrap().id, "superchip");
+        assert_eq!(info.ips(), Some(30 * 60));
+        assert_eq!(info.palette().as_deref(), Some("ffcc00,101010"));
+        assert_eq!(info.rom.keys["a"], 5);
+        assert!(info.platform.unwrap().quirks().jump);
+
+        assert!(db.lookup("bbbb").is_none());
+    }
+
+    #[test]
+    fn huge_tickrates_are_ignored() {
+        let programs = r#"[{ "title": "Fast", "roms": { "aaaa": { "tickrate": 18446744073709551615 } } }]"#;
+        let db = Database::parse(programs, r#"{ "aaaa": 0 }"#, PLATFORMS).unwrap();
+        assert_eq!(db.lookup("aaaa").unwrap().ips(), None);
+    }
+}
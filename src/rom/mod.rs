@@ -0,0 +1,84 @@
+//! Contains code handling ROM files, outside of the console
+
+use crate::emu::instructions::Instr;
+use sha1::{Digest, Sha1};
+use std::collections::HashMap;
+
+pub mod analysis;
+pub mod cartridge;
+pub mod container;
+pub mod database;
+pub mod octo;
+pub mod patch;
+
+/// Returns the SHA-1 hash of a ROM, as lowercase hexadecimal
+/// It identifies the ROM in the configuration
+pub fn sha1_hex(rom: &[u8]) -> String {
+    Sha1::digest(rom)
+        .iter()
+        .map(|bt| format!("{:02x}", bt))
+        .collect()
+}
+
+/// Counts the instructions of a ROM by kind, most frequent first
+/// Every pair of bytes is decoded, data included, and unknown opcodes are
+/// counted as "unknown"
+pub fn opcode_stats(rom: &[u8]) -> Vec<(String, usize)> {
+    let mut counts: HashMap<String, usize> = HashMap::new();
+
+    for word in rom.chunks_exact(2) {
+        let instr = Instr::decode(u16::from_be_bytes([word[0], word[1]]));
+        let name = match instr {
+            Instr::NIL => "unknown".to_string(),
+            // Name of the variant, without its operands
+            _ => format!("{:?}", instr).split('(').next().unwrap_or("").to_string(),
+        };
+        *counts.entry(name).or_insert(0) += 1;
+    }
+
+    let mut stats: Vec<_> = counts.into_iter().collect();
+    stats.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    stats
+}
+
+/// Guesses the platform of a ROM missing from the database, from the
+/// analysis of its code loaded at start_addr
+/// Returns the identifier of the platform in the database, or None if the
+/// code doesn't depend on the platform
+pub fn guess_platform(rom: &[u8], start_addr: u16) -> Option<&'static str> {
+    analysis::analyse(rom, start_addr).suggested_platform()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn guess_platform_from_opcodes() {
+        assert_eq!(guess_platform(&[0x60, 0x01, 0x12, 0x00], 0x200), None);
+        assert_eq!(guess_platform(&[0x00, 0xFF, 0x12, 0x00], 0x200), Some("superchip"));
+        assert_eq!(guess_platform(&[0xF3, 0x85], 0x200), Some("superchip"));
+        assert_eq!(guess_platform(&[0x00, 0xFF, 0xF0, 0x00, 0x12, 0x34], 0x200), Some("xochip"));
+        assert_eq!(guess_platform(&[0x51, 0x23], 0x200), Some("xochip"));
+    }
+
+    #[test]
+    fn opcode_stats_counts_by_kind() {
+        let rom = [
+            0x60, 0x01, // LD V0, 0x01
+            0x61, 0x02, // LD V1, 0x02
+            0x00, 0xE0, // CLS
+            0xFF, 0xFF, // unknown
+            0x12, // odd byte, ignored
+        ];
+
+        assert_eq!(
+            opcode_stats(&rom),
+            vec![
+                ("LD".to_string(), 2),
+                ("CLS".to_string(), 1),
+                ("unknown".to_string(), 1),
+            ]
+        );
+    }
+}
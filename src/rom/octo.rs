@@ -0,0 +1,1121 @@
+//! Assembler of Octo, the language of the Octo CHIP-8 IDE
+//!
+//! Octo cartridges hold the source code of the game rather than the ROM, so
+//! it's assembled here. The statements, the control flow (`if`, `begin`,
+//! `loop`, `while`), labels, constants, aliases, macros and `:calc`
+//! expressions are supported, including the SUPER-CHIP and XO-CHIP
+//! instructions. `:stringmode` isn't.
+//!
+//! As in Octo, programs are loaded at 0x200 and start with a jump to the
+//! `main` label. Expressions of `:calc` have no precedence: they are
+//! evaluated from right to left, unless parenthesized.
+
+use std::collections::HashMap;
+
+/// Address where the program is loaded
+const START_ADDR: usize = 0x200;
+/// Largest address of the memory
+const MAX_ADDR: usize = 0xFFFF;
+/// Number of macro expansions after which the assembly stops, to catch
+/// macros expanding themselves
+const MAX_EXPANSIONS: usize = 100_000;
+/// Largest number of tokens of an expression, which is evaluated
+/// recursively
+const MAX_EXPRESSION_TOKENS: usize = 1000;
+
+/// Assembles Octo source code into a ROM loaded at 0x200
+/// Errors give the line where the assembly stopped
+pub fn assemble(source: &str) -> Result<Vec<u8>, String> {
+    let mut assembler = Assembler::new(tokenize(source)?);
+    assembler.run()?;
+    Ok(assembler.rom)
+}
+
+/// A word of the source code
+#[derive(Debug, Clone, PartialEq)]
+struct Token {
+    /// The word, or the content of a string
+    text: String,
+    /// Line of the word, from 1
+    line: usize,
+}
+
+/// Splits source code into tokens, dropping the comments
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = vec![];
+
+    for (index, text) in source.lines().enumerate() {
+        let line = index + 1;
+        let mut chars = text.chars().peekable();
+
+        while let Some(&c) = chars.peek() {
+            if c.is_whitespace() {
+                chars.next();
+            } else if c == '#' {
+                break;
+            } else if c == '"' {
+                chars.next();
+                let mut string = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some(c) => string.push(c),
+                        None => return Err(format!("line {}: unterminated string", line)),
+                    }
+                }
+                tokens.push(Token { text: string, line });
+            } else {
+                let mut word = String::new();
+                while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                    word.push(c);
+                }
+                tokens.push(Token { text: word, line });
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// Parses a number of the source code: decimal, 0x hexadecimal or 0b binary,
+/// optionally negative
+fn parse_number(text: &str) -> Option<i64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+
+    let number = if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(binary) = digits.strip_prefix("0b").or_else(|| digits.strip_prefix("0B")) {
+        i64::from_str_radix(binary, 2).ok()?
+    } else if !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()) {
+        digits.parse().ok()?
+    } else {
+        return None;
+    };
+
+    Some(if negative { -number } else { number })
+}
+
+/// Parses the name of a register, v0 to vf
+fn parse_register(text: &str) -> Option<u8> {
+    let digit = text.strip_prefix('v').or_else(|| text.strip_prefix('V'))?;
+    if digit.len() != 1 {
+        return None;
+    }
+    u8::from_str_radix(digit, 16).ok()
+}
+
+/// Address resolved once its label is defined
+#[derive(Debug)]
+enum Fixup {
+    /// 12 bits address of an instruction, such as a jump
+    Nnn(usize),
+    /// 16 bits address, such as the one following `i := long`
+    Long(usize),
+    /// Address split into the bytes of `v0 := ...` and `v1 := ...`, the
+    /// high byte combined with a nibble
+    Unpack(usize, u8),
+}
+
+/// Block of control flow waiting for its end
+#[derive(Debug)]
+enum Control {
+    /// `if ... begin`, with the address of its jump to `else` or `end`
+    If(usize),
+    /// `else`, with the address of its jump to `end`
+    Else(usize),
+    /// `loop`, with its start and the addresses of the jumps of its
+    /// `while`s
+    Loop(usize, Vec<usize>),
+}
+
+/// Macro defined with `:macro`
+#[derive(Debug, Clone)]
+struct Macro {
+    /// Names of the arguments
+    params: Vec<String>,
+    /// Tokens of the body
+    body: Vec<Token>,
+}
+
+/// Comparison of a condition
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Comparison {
+    /// `==`
+    Equal,
+    /// `!=`
+    NotEqual,
+    /// `<`
+    Less,
+    /// `>`
+    Greater,
+    /// `<=`
+    LessOrEqual,
+    /// `>=`
+    GreaterOrEqual,
+    /// `key`
+    Key,
+    /// `-key`
+    NotKey,
+}
+
+/// Condition of an `if` or a `while`, compiled into instructions
+#[derive(Debug)]
+struct Condition {
+    /// Instructions computing the condition into vf, if needed
+    prefix: Vec<u16>,
+    /// Instruction skipping the next one if the condition is false
+    skip: u16,
+}
+
+impl Condition {
+    /// Returns the instruction skipping the next one if the condition is
+    /// true instead
+    fn inverted_skip(&self) -> u16 {
+        match self.skip & 0xF000 {
+            0x3000 => self.skip + 0x1000,
+            0x4000 => self.skip - 0x1000,
+            0x5000 => self.skip + 0x4000,
+            0x9000 => self.skip - 0x4000,
+            // Ex9E and ExA1
+            _ if self.skip & 0xFF == 0x9E => self.skip + 0x03,
+            _ => self.skip - 0x03,
+        }
+    }
+}
+
+/// State of the assembly
+struct Assembler {
+    /// Tokens left to assemble, in reverse order, macros being expanded in
+    /// place
+    tokens: Vec<Token>,
+    /// Line of the last token read
+    line: usize,
+    /// The ROM, from START_ADDR
+    rom: Vec<u8>,
+    /// Address of the next byte
+    here: usize,
+    /// Addresses of the labels
+    labels: HashMap<String, usize>,
+    /// Values of the constants, including `:calc` results
+    constants: HashMap<String, f64>,
+    /// Registers of the aliases
+    aliases: HashMap<String, u8>,
+    /// Macros by name
+    macros: HashMap<String, Macro>,
+    /// Addresses waiting for labels, along with the line using them
+    fixups: Vec<(String, Fixup, usize)>,
+    /// Blocks of control flow waiting for their end
+    control: Vec<Control>,
+    /// Number of macro expansions
+    expansions: usize,
+}
+
+impl Assembler {
+    /// Creates the assembler of the given tokens
+    fn new(mut tokens: Vec<Token>) -> Self {
+        tokens.reverse();
+        Assembler {
+            tokens,
+            line: 1,
+            rom: vec![],
+            here: START_ADDR,
+            labels: HashMap::new(),
+            constants: HashMap::new(),
+            aliases: HashMap::new(),
+            macros: HashMap::new(),
+            fixups: vec![],
+            control: vec![],
+            expansions: 0,
+        }
+    }
+
+    /// Assembles every token, then resolves the addresses
+    fn run(&mut self) -> Result<(), String> {
+        // Jump to main
+        self.jump_to_label(0x1000, "main".to_string());
+
+        while let Some(token) = self.next_token() {
+            self.statement(token).map_err(|e| format!("line {}: {}", self.line, e))?;
+        }
+
+        if let Some(control) = self.control.last() {
+            let block = match control {
+                Control::If(_) | Control::Else(_) => "if ... begin",
+                Control::Loop(_, _) => "loop",
+            };
+            return Err(format!("line {}: unterminated {}", self.line, block));
+        }
+
+        for (label, fixup, line) in std::mem::take(&mut self.fixups) {
+            let addr = *self
+                .labels
+                .get(&label)
+                .ok_or_else(|| format!("line {}: undefined label '{}'", line, label))?;
+            self.resolve(fixup, addr).map_err(|e| format!("line {}: {}", line, e))?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the next token
+    fn next_token(&mut self) -> Option<Token> {
+        let token = self.tokens.pop()?;
+        self.line = token.line;
+        Some(token)
+    }
+
+    /// Returns the text of the next token, failing at the end of the source
+    fn expect_token(&mut self, what: &str) -> Result<String, String> {
+        self.next_token()
+            .map(|token| token.text)
+            .ok_or_else(|| format!("expected {} at the end of the source", what))
+    }
+
+    /// Reads the next token, which must be the given one
+    fn expect(&mut self, expected: &str) -> Result<(), String> {
+        match self.expect_token(&format!("'{}'", expected))? {
+            text if text == expected => Ok(()),
+            text => Err(format!("expected '{}', found '{}'", expected, text)),
+        }
+    }
+
+    /// Returns true and skips the next token if it's the given one
+    fn accept(&mut self, text: &str) -> bool {
+        if self.tokens.last().is_some_and(|token| token.text == text) {
+            self.next_token();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Assembles the statement starting with the given token
+    fn statement(&mut self, token: Token) -> Result<(), String> {
+        let text = token.text.as_str();
+
+        match text {
+            ":" => {
+                let name = self.expect_token("a label")?;
+                self.define_label(name, self.here)
+            },
+            ":next" => {
+                let name = self.expect_token("a label")?;
+                self.define_label(name, self.here + 1)
+            },
+            ":alias" => {
+                let name = self.expect_token("a name")?;
+                let register = self.register()?;
+                self.aliases.insert(name, register);
+                Ok(())
+            },
+            ":const" => {
+                let name = self.expect_token("a name")?;
+                let value = self.constant()?;
+                self.constants.insert(name, value);
+                Ok(())
+            },
+            ":calc" => {
+                let name = self.expect_token("a name")?;
+                let value = self.braced_expression()?;
+                self.constants.insert(name, value);
+                Ok(())
+            },
+            ":byte" => {
+                let value = self.constant()?;
+                self.emit_byte(value as i64)
+            },
+            ":org" => {
+                let addr = self.constant()? as i64;
+                if addr < START_ADDR as i64 || addr > MAX_ADDR as i64 {
+                    return Err(format!("address {:#x} out of the program", addr));
+                }
+                self.here = addr as usize;
+                Ok(())
+            },
+            ":pointer" => {
+                let name = self.expect_token("a label")?;
+                self.emit_address(Fixup::Long(self.here), name)?;
+                self.emit_byte(0)?;
+                self.emit_byte(0)
+            },
+            ":unpack" => {
+                let nibble = match self.expect_token("a nibble")?.as_str() {
+                    "long" => 0,
+                    text => self.number_value(text)? as u8 & 0xF,
+                };
+                let name = self.expect_token("a label")?;
+                self.emit_address(Fixup::Unpack(self.here, nibble), name)?;
+                self.emit(0x6000)?;
+                self.emit(0x6100)
+            },
+            ":call" => {
+                let name = self.expect_token("a label")?;
+                self.emit_call(name)
+            },
+            ":macro" => self.define_macro(),
+            ":breakpoint" => self.expect_token("a name").map(|_| ()),
+            ":monitor" => {
+                self.expect_token("an address")?;
+                self.expect_token("a length or format")?;
+                Ok(())
+            },
+            ":assert" => {
+                let message = match self.tokens.last() {
+                    Some(token) if token.text != "{" => self.expect_token("a message")?,
+                    _ => "assertion failed".to_string(),
+                };
+                if self.braced_expression()? == 0.0 {
+                    return Err(message);
+                }
+                Ok(())
+            },
+            ":stringmode" => Err(":stringmode isn't supported".to_string()),
+            ";" | "return" => self.emit(0x00EE),
+            "clear" => self.emit(0x00E0),
+            "exit" => self.emit(0x00FD),
+            "lores" => self.emit(0x00FE),
+            "hires" => self.emit(0x00FF),
+            "scroll-right" => self.emit(0x00FB),
+            "scroll-left" => self.emit(0x00FC),
+            "scroll-down" => {
+                let rows = self.nibble()?;
+                self.emit(0x00C0 | rows)
+            },
+            "scroll-up" => {
+                let rows = self.nibble()?;
+                self.emit(0x00D0 | rows)
+            },
+            "audio" => self.emit(0xF002),
+            "plane" => {
+                let plane = self.nibble()?;
+                self.emit(0xF001 | plane << 8)
+            },
+            "jump" | "jump0" | "native" => {
+                let opcode = match text {
+                    "jump" => 0x1000,
+                    "jump0" => 0xB000,
+                    _ => 0x0000,
+                };
+                let target = self.expect_token("an address")?;
+                self.emit_nnn(opcode, target)
+            },
+            "sprite" => {
+                let x = self.register()?;
+                let y = self.register()?;
+                let rows = self.nibble()?;
+                self.emit(0xD000 | reg(x, 8) | reg(y, 4) | rows)
+            },
+            "save" | "load" => {
+                let x = self.register()?;
+                if self.accept("-") {
+                    let y = self.register()?;
+                    let opcode = if text == "save" { 0x5002 } else { 0x5003 };
+                    self.emit(opcode | reg(x, 8) | reg(y, 4))
+                } else {
+                    let opcode = if text == "save" { 0xF055 } else { 0xF065 };
+                    self.emit(opcode | reg(x, 8))
+                }
+            },
+            "saveflags" | "loadflags" | "bcd" | "bighex" => {
+                let x = self.register()?;
+                let opcode = match text {
+                    "saveflags" => 0xF075,
+                    "loadflags" => 0xF085,
+                    "bcd" => 0xF033,
+                    _ => 0xF030,
+                };
+                self.emit(opcode | reg(x, 8))
+            },
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let x = self.register()?;
+                let opcode = match text {
+                    "delay" => 0xF015,
+                    "buzzer" => 0xF018,
+                    _ => 0xF03A,
+                };
+                self.emit(opcode | reg(x, 8))
+            },
+            "i" | "I" => self.index_statement(),
+            "if" => self.if_statement(),
+            "else" => match self.control.pop() {
+                Some(Control::If(jump)) => {
+                    let addr = self.here;
+                    self.emit(0x1000)?;
+                    self.patch_jump(jump, self.here);
+                    self.control.push(Control::Else(addr));
+                    Ok(())
+                },
+                _ => Err("'else' without 'if ... begin'".to_string()),
+            },
+            "end" => match self.control.pop() {
+                Some(Control::If(jump)) | Some(Control::Else(jump)) => {
+                    self.patch_jump(jump, self.here);
+                    Ok(())
+                },
+                _ => Err("'end' without 'if ... begin'".to_string()),
+            },
+            "loop" => {
+                self.control.push(Control::Loop(self.here, vec![]));
+                Ok(())
+            },
+            "while" => {
+                let condition = self.condition()?;
+                self.emit_all(&condition.prefix)?;
+                self.emit(condition.inverted_skip())?;
+                let jump = self.here;
+                self.emit(0x1000)?;
+                match self.control.iter_mut().rev().find(|c| matches!(c, Control::Loop(_, _))) {
+                    Some(Control::Loop(_, whiles)) => {
+                        whiles.push(jump);
+                        Ok(())
+                    },
+                    _ => Err("'while' outside of a loop".to_string()),
+                }
+            },
+            "again" => match self.control.pop() {
+                Some(Control::Loop(start, whiles)) => {
+                    self.emit(0x1000 | start as u16 & 0xFFF)?;
+                    for jump in whiles {
+                        self.patch_jump(jump, self.here);
+                    }
+                    Ok(())
+                },
+                _ => Err("'again' without 'loop'".to_string()),
+            },
+            _ => {
+                if let Some(x) = self.register_value(text) {
+                    return self.register_statement(x);
+                }
+                // Numbers and constants alone are data, labels are called
+                let data = parse_number(text).or_else(|| self.constants.get(text).map(|c| *c as i64));
+                if let Some(value) = data {
+                    return self.emit_byte(value);
+                }
+                if let Some(definition) = self.macros.get(text).cloned() {
+                    return self.expand_macro(definition);
+                }
+                if text.starts_with(':') || text.starts_with('{') || text.starts_with('}') {
+                    return Err(format!("unexpected '{}'", text));
+                }
+
+                // Call of a label, possibly defined later
+                self.emit_call(token.text)
+            },
+        }
+    }
+
+    /// Assembles a statement setting I
+    fn index_statement(&mut self) -> Result<(), String> {
+        match self.expect_token("an operator")?.as_str() {
+            "+=" => {
+                let x = self.register()?;
+                self.emit(0xF01E | reg(x, 8))
+            },
+            ":=" => match self.expect_token("a value")?.as_str() {
+                "hex" => {
+                    let x = self.register()?;
+                    self.emit(0xF029 | reg(x, 8))
+                },
+                "bighex" => {
+                    let x = self.register()?;
+                    self.emit(0xF030 | reg(x, 8))
+                },
+                "long" => {
+                    let target = self.expect_token("an address")?;
+                    self.emit(0xF000)?;
+                    match self.value(&target) {
+                        Some(addr) => self.emit(addr as i64 as u16),
+                        None => {
+                            self.emit_address(Fixup::Long(self.here), target)?;
+                            self.emit(0x0000)
+                        },
+                    }
+                },
+                target => self.emit_nnn(0xA000, target.to_string()),
+            },
+            operator => Err(format!("unknown operator 'i {}'", operator)),
+        }
+    }
+
+    /// Assembles a statement on the register x
+    fn register_statement(&mut self, x: u8) -> Result<(), String> {
+        let operator = self.expect_token("an operator")?;
+        let operand = self.expect_token("an operand")?;
+        let xy = |y: u8, op: u16| 0x8000 | reg(x, 8) | reg(y, 4) | op;
+
+        if let Some(y) = self.register_value(&operand) {
+            let opcode = match operator.as_str() {
+                ":=" => xy(y, 0x0),
+                "|=" => xy(y, 0x1),
+                "&=" => xy(y, 0x2),
+                "^=" => xy(y, 0x3),
+                "+=" => xy(y, 0x4),
+                "-=" => xy(y, 0x5),
+                ">>=" => xy(y, 0x6),
+                "=-" => xy(y, 0x7),
+                "<<=" => xy(y, 0xE),
+                _ => return Err(format!("unknown operator '{}'", operator)),
+            };
+            return self.emit(opcode);
+        }
+
+        match (operator.as_str(), operand.as_str()) {
+            (":=", "delay") => self.emit(0xF007 | reg(x, 8)),
+            (":=", "key") => self.emit(0xF00A | reg(x, 8)),
+            (":=", "random") => {
+                let mask = self.byte()?;
+                self.emit(0xC000 | reg(x, 8) | mask)
+            },
+            (":=", value) => {
+                let value = self.byte_value(value)?;
+                self.emit(0x6000 | reg(x, 8) | value)
+            },
+            ("+=", value) => {
+                let value = self.byte_value(value)?;
+                self.emit(0x7000 | reg(x, 8) | value)
+            },
+            ("-=", value) => {
+                let value = self.number_value(value)?;
+                self.emit(0x7000 | reg(x, 8) | (-value as i64 as u16 & 0xFF))
+            },
+            _ => Err(format!("unknown operator '{}' with '{}'", operator, operand)),
+        }
+    }
+
+    /// Assembles an `if` and its `then` or `begin`
+    fn if_statement(&mut self) -> Result<(), String> {
+        let condition = self.condition()?;
+        self.emit_all(&condition.prefix)?;
+
+        match self.expect_token("'then' or 'begin'")?.as_str() {
+            "then" => self.emit(condition.skip),
+            "begin" => {
+                self.emit(condition.inverted_skip())?;
+                self.control.push(Control::If(self.here));
+                self.emit(0x1000)
+            },
+            text => Err(format!("expected 'then' or 'begin', found '{}'", text)),
+        }
+    }
+
+    /// Reads the condition of an `if` or a `while`
+    fn condition(&mut self) -> Result<Condition, String> {
+        let x = self.register()?;
+        let comparison = match self.expect_token("a comparison")?.as_str() {
+            "==" => Comparison::Equal,
+            "!=" => Comparison::NotEqual,
+            "<" => Comparison::Less,
+            ">" => Comparison::Greater,
+            "<=" => Comparison::LessOrEqual,
+            ">=" => Comparison::GreaterOrEqual,
+            "key" => Comparison::Key,
+            "-key" => Comparison::NotKey,
+            text => return Err(format!("unknown comparison '{}'", text)),
+        };
+        let skip = |skip: u16| Ok(Condition { prefix: vec![], skip });
+
+        match comparison {
+            Comparison::Key => return skip(0xE0A1 | reg(x, 8)),
+            Comparison::NotKey => return skip(0xE09E | reg(x, 8)),
+            _ => (),
+        }
+
+        let operand = self.expect_token("an operand")?;
+        let y = self.register_value(&operand);
+        let value = match y {
+            Some(_) => 0,
+            None => self.byte_value(&operand)?,
+        };
+
+        match (comparison, y) {
+            (Comparison::Equal, Some(y)) => return skip(0x9000 | reg(x, 8) | reg(y, 4)),
+            (Comparison::Equal, None) => return skip(0x4000 | reg(x, 8) | value),
+            (Comparison::NotEqual, Some(y)) => return skip(0x5000 | reg(x, 8) | reg(y, 4)),
+            (Comparison::NotEqual, None) => return skip(0x3000 | reg(x, 8) | value),
+            _ => (),
+        }
+
+        // The subtraction sets vf to 1 when there's no borrow, that is when
+        // the first operand is greater than or equal to the second one
+        let swap = matches!(comparison, Comparison::Greater | Comparison::LessOrEqual);
+        let prefix = match (y, swap) {
+            (Some(y), false) => vec![0x8F00 | reg(x, 4), 0x8F05 | reg(y, 4)],
+            (Some(y), true) => vec![0x8F00 | reg(y, 4), 0x8F05 | reg(x, 4)],
+            (None, false) => vec![0x6F00 | value, 0x8F07 | reg(x, 4)],
+            (None, true) => vec![0x6F00 | value, 0x8F05 | reg(x, 4)],
+        };
+        let skip = match comparison {
+            Comparison::Less | Comparison::Greater => 0x4F00,
+            _ => 0x4F01,
+        };
+
+        Ok(Condition { prefix, skip })
+    }
+
+    /// Reads the definition of a macro
+    fn define_macro(&mut self) -> Result<(), String> {
+        let name = self.expect_token("a name")?;
+        let mut params = vec![];
+        loop {
+            match self.expect_token("'{'")? {
+                text if text == "{" => break,
+                text => params.push(text),
+            }
+        }
+
+        let body = self.braced_tokens()?;
+        self.macros.insert(name, Macro { params, body });
+        Ok(())
+    }
+
+    /// Expands a macro in place of its call
+    fn expand_macro(&mut self, definition: Macro) -> Result<(), String> {
+        self.expansions += 1;
+        if self.expansions > MAX_EXPANSIONS {
+            return Err("too many macro expansions".to_string());
+        }
+
+        let mut args = HashMap::new();
+        for param in &definition.params {
+            args.insert(param.clone(), self.expect_token("an argument")?);
+        }
+
+        let line = self.line;
+        for token in definition.body.iter().rev() {
+            let text = args.get(&token.text).unwrap_or(&token.text).clone();
+            self.tokens.push(Token { text, line });
+        }
+        Ok(())
+    }
+
+    /// Reads tokens up to the closing brace, the opening one being read
+    fn braced_tokens(&mut self) -> Result<Vec<Token>, String> {
+        let mut tokens = vec![];
+        let mut depth = 0;
+
+        loop {
+            let token = self.next_token().ok_or("expected '}' at the end of the source")?;
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" if depth == 0 => return Ok(tokens),
+                "}" => depth -= 1,
+                _ => (),
+            }
+            tokens.push(token);
+        }
+    }
+
+    /// Reads an expression in braces and evaluates it
+    fn braced_expression(&mut self) -> Result<f64, String> {
+        self.expect("{")?;
+        let tokens = self.braced_tokens()?;
+        if tokens.len() > MAX_EXPRESSION_TOKENS {
+            return Err("the expression is too long".to_string());
+        }
+        let texts: Vec<&str> = tokens.iter().map(|token| token.text.as_str()).collect();
+
+        let (value, rest) = self.evaluate(&texts)?;
+        match rest.first() {
+            None => Ok(value),
+            Some(text) => Err(format!("unexpected '{}' in the expression", text)),
+        }
+    }
+
+    /// Evaluates an expression, from right to left
+    /// Returns its value and the tokens following it
+    fn evaluate<'t>(&self, tokens: &'t [&'t str]) -> Result<(f64, &'t [&'t str]), String> {
+        let (left, rest) = self.term(tokens)?;
+
+        let operator = match rest.first() {
+            Some(&operator) if operator != ")" => operator,
+            _ => return Ok((left, rest)),
+        };
+        let (right, rest) = self.evaluate(&rest[1..])?;
+        let (a, b) = (left as i64, right as i64);
+
+        let value = match operator {
+            "+" => left + right,
+            "-" => left - right,
+            "*" => left * right,
+            "/" if right == 0.0 => return Err("division by zero".to_string()),
+            "/" => left / right,
+            "%" if b == 0 => return Err("division by zero".to_string()),
+            "%" => a.wrapping_rem(b) as f64,
+            "&" => (a & b) as f64,
+            "|" => (a | b) as f64,
+            "^" => (a ^ b) as f64,
+            "<<" => (a << (b & 63)) as f64,
+            ">>" => (a >> (b & 63)) as f64,
+            "pow" => left.powf(right),
+            "min" => left.min(right),
+            "max" => left.max(right),
+            "<" => (left < right) as i64 as f64,
+            ">" => (left > right) as i64 as f64,
+            "<=" => (left <= right) as i64 as f64,
+            ">=" => (left >= right) as i64 as f64,
+            "==" => (left == right) as i64 as f64,
+            "!=" => (left != right) as i64 as f64,
+            _ => return Err(format!("unknown operator '{}' in the expression", operator)),
+        };
+
+        Ok((value, rest))
+    }
+
+    /// Evaluates a term of an expression: a value, a parenthesized
+    /// expression or an unary operator and its term
+    fn term<'t>(&self, tokens: &'t [&'t str]) -> Result<(f64, &'t [&'t str]), String> {
+        let (&first, rest) = tokens.split_first().ok_or("incomplete expression")?;
+
+        match first {
+            "(" => {
+                let (value, rest) = self.evaluate(rest)?;
+                match rest.split_first() {
+                    Some((&")", rest)) => Ok((value, rest)),
+                    _ => Err("expected ')' in the expression".to_string()),
+                }
+            },
+            "-" | "~" | "!" | "floor" | "abs" => {
+                let (value, rest) = self.term(rest)?;
+                let value = match first {
+                    "-" => -value,
+                    "~" => !(value as i64) as f64,
+                    "!" => (value == 0.0) as i64 as f64,
+                    "floor" => value.floor(),
+                    _ => value.abs(),
+                };
+                Ok((value, rest))
+            },
+            "HERE" => Ok((self.here as f64, rest)),
+            "PI" => Ok((std::f64::consts::PI, rest)),
+            "E" => Ok((std::f64::consts::E, rest)),
+            _ => match self.value(first) {
+                Some(value) => Ok((value, rest)),
+                None => Err(format!("unknown value '{}' in the expression", first)),
+            },
+        }
+    }
+
+    /// Returns the value of a number, a constant or a defined label
+    fn value(&self, text: &str) -> Option<f64> {
+        parse_number(text)
+            .map(|n| n as f64)
+            .or_else(|| self.constants.get(text).copied())
+            .or_else(|| self.labels.get(text).map(|addr| *addr as f64))
+    }
+
+    /// Returns the value of a token which must be a number
+    fn number_value(&self, text: &str) -> Result<f64, String> {
+        self.value(text).ok_or_else(|| format!("expected a number, found '{}'", text))
+    }
+
+    /// Returns the value of a token which must fit into a byte, negative
+    /// numbers being wrapped
+    fn byte_value(&self, text: &str) -> Result<u16, String> {
+        let value = self.number_value(text)? as i64;
+        if !(-128..=255).contains(&value) {
+            return Err(format!("{} doesn't fit into a byte", value));
+        }
+        Ok(value as u16 & 0xFF)
+    }
+
+    /// Reads a value which must fit into a byte
+    fn byte(&mut self) -> Result<u16, String> {
+        let text = self.expect_token("a byte")?;
+        self.byte_value(&text)
+    }
+
+    /// Reads a value which must fit into a nibble
+    fn nibble(&mut self) -> Result<u16, String> {
+        let text = self.expect_token("a nibble")?;
+        let value = self.number_value(&text)? as i64;
+        if !(0..=15).contains(&value) {
+            return Err(format!("{} doesn't fit into a nibble", value));
+        }
+        Ok(value as u16)
+    }
+
+    /// Reads a constant value: a number, a constant, a label or an
+    /// expression in braces
+    fn constant(&mut self) -> Result<f64, String> {
+        if self.tokens.last().is_some_and(|token| token.text == "{") {
+            return self.braced_expression();
+        }
+        let text = self.expect_token("a value")?;
+        self.number_value(&text)
+    }
+
+    /// Returns the register of a name or an alias
+    fn register_value(&self, text: &str) -> Option<u8> {
+        parse_register(text).or_else(|| self.aliases.get(text).copied())
+    }
+
+    /// Reads a register
+    fn register(&mut self) -> Result<u8, String> {
+        let text = self.expect_token("a register")?;
+        self.register_value(&text)
+            .ok_or_else(|| format!("expected a register, found '{}'", text))
+    }
+
+    /// Defines a label at the given address
+    fn define_label(&mut self, name: String, addr: usize) -> Result<(), String> {
+        if parse_number(&name).is_some() || self.register_value(&name).is_some() {
+            return Err(format!("invalid label name '{}'", name));
+        }
+        if self.labels.insert(name.clone(), addr).is_some() {
+            return Err(format!("label '{}' defined twice", name));
+        }
+        Ok(())
+    }
+
+    /// Emits an instruction
+    fn emit(&mut self, opcode: u16) -> Result<(), String> {
+        self.emit_byte((opcode >> 8) as i64)?;
+        self.emit_byte((opcode & 0xFF) as i64)
+    }
+
+    /// Emits several instructions
+    fn emit_all(&mut self, opcodes: &[u16]) -> Result<(), String> {
+        opcodes.iter().try_for_each(|opcode| self.emit(*opcode))
+    }
+
+    /// Emits a byte, negative values being wrapped
+    fn emit_byte(&mut self, value: i64) -> Result<(), String> {
+        if !(-128..=255).contains(&value) {
+            return Err(format!("{} doesn't fit into a byte", value));
+        }
+        if self.here > MAX_ADDR {
+            return Err("the program doesn't fit into the memory".to_string());
+        }
+
+        let offset = self.here - START_ADDR;
+        if self.rom.len() <= offset {
+            self.rom.resize(offset + 1, 0);
+        }
+        self.rom[offset] = value as u8;
+        self.here += 1;
+        Ok(())
+    }
+
+    /// Emits an instruction with an address, the target being a value or a
+    /// label, possibly defined later
+    fn emit_nnn(&mut self, opcode: u16, target: String) -> Result<(), String> {
+        match self.value(&target) {
+            Some(addr) => {
+                let addr = addr as i64;
+                if !(0..=0xFFF).contains(&addr) {
+                    return Err(format!("address {:#x} out of reach", addr));
+                }
+                self.emit(opcode | addr as u16)
+            },
+            None => {
+                self.emit_address(Fixup::Nnn(self.here), target)?;
+                self.emit(opcode)
+            },
+        }
+    }
+
+    /// Emits a call to a label
+    fn emit_call(&mut self, name: String) -> Result<(), String> {
+        if self.register_value(&name).is_some() || parse_number(&name).is_some() {
+            return Err(format!("can't call '{}'", name));
+        }
+        self.emit_nnn(0x2000, name)
+    }
+
+    /// Emits a jump to a label, resolved at the end
+    fn jump_to_label(&mut self, opcode: u16, name: String) {
+        self.fixups.push((name, Fixup::Nnn(self.here), self.line));
+        self.emit(opcode).expect("The program starts in the memory");
+    }
+
+    /// Records an address to resolve, the label being defined or not
+    fn emit_address(&mut self, fixup: Fixup, name: String) -> Result<(), String> {
+        if parse_number(&name).is_some() || self.register_value(&name).is_some() {
+            return Err(format!("expected a label, found '{}'", name));
+        }
+        self.fixups.push((name, fixup, self.line));
+        Ok(())
+    }
+
+    /// Writes the address of a label where it's used
+    fn resolve(&mut self, fixup: Fixup, addr: usize) -> Result<(), String> {
+        let byte = |assembler: &mut Assembler, at: usize, value: u8, mask: u8| {
+            let offset = at - START_ADDR;
+            assembler.rom[offset] = assembler.rom[offset] & !mask | value & mask;
+        };
+
+        match fixup {
+            Fixup::Nnn(at) => {
+                if addr > 0xFFF {
+                    return Err(format!("address {:#x} out of reach, use 'i := long'", addr));
+                }
+                byte(self, at, (addr >> 8) as u8, 0x0F);
+                byte(self, at + 1, addr as u8, 0xFF);
+            },
+            Fixup::Long(at) => {
+                byte(self, at, (addr >> 8) as u8, 0xFF);
+                byte(self, at + 1, addr as u8, 0xFF);
+            },
+            Fixup::Unpack(at, nibble) => {
+                byte(self, at + 1, nibble << 4 | (addr >> 8) as u8, 0xFF);
+                byte(self, at + 3, addr as u8, 0xFF);
+            },
+        }
+
+        Ok(())
+    }
+
+    /// Makes the jump at the given address go to the target
+    fn patch_jump(&mut self, at: usize, target: usize) {
+        let offset = at - START_ADDR;
+        self.rom[offset] = 0x10 | (target >> 8) as u8 & 0x0F;
+        self.rom[offset + 1] = target as u8;
+    }
+}
+
+/// Places a register at the given bit of an opcode
+fn reg(x: u8, shift: u16) -> u16 {
+    (x as u16) << shift
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Assembles a program, its main label following the jump to it
+    fn words(source: &str) -> Vec<u16> {
+        let rom = assemble(source).unwrap();
+        rom.chunks_exact(2).map(|w| u16::from_be_bytes([w[0], w[1]])).collect()
+    }
+
+    #[test]
+    fn statements() {
+        let source = "
+            : main          # comment
+              clear
+              v0 := 5  v1 := v0  va += 1  vb += v1  v2 -= 1  v2 -= v3  v2 =- v3
+              v4 |= v5  v4 &= v5  v4 ^= v5  v4 >>= v5  v4 <<= v5
+              v6 := random 0xFF  v7 := key  v8 := delay  delay := v8  buzzer := v8
+              i := 0x300  i += v1  i := hex v2  bcd v3  save v4  load v5
+              sprite v0 v1 5  saveflags v2  loadflags v3  jump0 0x300  ;
+        ";
+
+        assert_eq!(words(source), [
+            0x1202, 0x00E0, 0x6005, 0x8100, 0x7A01, 0x8B14, 0x72FF, 0x8235, 0x8237,
+            0x8451, 0x8452, 0x8453, 0x8456, 0x845E,
+            0xC6FF, 0xF70A, 0xF807, 0xF815, 0xF818,
+            0xA300, 0xF11E, 0xF229, 0xF333, 0xF455, 0xF565,
+            0xD015, 0xF275, 0xF385, 0xB300, 0x00EE,
+        ]);
+    }
+
+    #[test]
+    fn control_flow() {
+        let source = "
+            : main
+              if v0 == 1 then v1 := 2
+              if v0 != v1 begin v2 := 3 else v2 := 4 end
+              loop
+                v0 += 1
+                while v0 < 10
+                if v0 key then ;
+              again
+        ";
+
+        assert_eq!(words(source), [
+            0x1202,
+            0x4001, 0x6102,                 // 0x202: if ... then
+            0x9010, 0x120E, 0x6203, 0x1210, // 0x206: if ... begin ... else
+            0x6204,                         // 0x20E: end
+            0x7001,                         // 0x210: loop
+            0x6F0A, 0x8F07, 0x3F00, 0x1220, // 0x212: while v0 < 10
+            0xE0A1, 0x00EE,                 // 0x21A: if v0 key then
+            0x1210,                         // 0x21E: again
+        ]);
+    }
+
+    #[test]
+    fn comparisons_set_vf() {
+        let source = ": main if v1 > v2 then ; if v1 <= 3 then ; if v1 >= v2 then ;";
+
+        assert_eq!(words(source), [
+            0x1202,
+            0x8F20, 0x8F15, 0x4F00, 0x00EE,
+            0x6F03, 0x8F15, 0x4F01, 0x00EE,
+            0x8F10, 0x8F25, 0x4F01, 0x00EE,
+        ]);
+    }
+
+    #[test]
+    fn labels_and_data() {
+        let source = "
+            : main
+              i := sprite  draw  :unpack 0xA data
+              i := long data
+              jump main
+            : draw
+              sprite v0 v0 1
+              :next value v3 := 0
+              ;
+            : sprite 0b10000001 -1
+            :org 0x300
+            : data :pointer draw :byte { 2 * 3 + 1 }
+        ";
+
+        assert_eq!(words(source)[..12], [
+            0x1202,
+            0xA216, 0x2210, 0x60A3, 0x6100, 0xF000, 0x0300, 0x1202, // 0x202
+            0xD001, 0x6300, 0x00EE,                                 // 0x210: draw
+            0x81FF,                                                 // 0x216: sprite
+        ]);
+        let rom = assemble(source).unwrap();
+        assert_eq!(rom.len(), 0x103);
+        // 2 * (3 + 1), from right to left
+        assert_eq!(rom[0x100..], [0x02, 0x10, 8]);
+    }
+
+    #[test]
+    fn constants_aliases_and_macros() {
+        let source = "
+            :const SPEED 3
+            :alias x v4
+            :calc TWICE { SPEED * 2 }
+            :calc LEFT { 10 - 4 - 2 }
+            :macro move reg amount { reg += amount }
+            : main
+              move x SPEED
+              move v5 TWICE
+              x := LEFT
+        ";
+
+        // The expression is evaluated from right to left: 10 - (4 - 2)
+        assert_eq!(words(source), [0x1202, 0x7403, 0x7506, 0x6408]);
+    }
+
+    #[test]
+    fn errors_give_the_line() {
+        assert_eq!(assemble(": main\n  jump nowhere\n").unwrap_err(), "line 2: undefined label 'nowhere'");
+        assert_eq!(assemble("v0 := 300").unwrap_err(), "line 1: 300 doesn't fit into a byte");
+        assert_eq!(assemble(": main\nloop\n").unwrap_err(), "line 2: unterminated loop");
+        assert_eq!(assemble(": main : main").unwrap_err(), "line 1: label 'main' defined twice");
+        assert_eq!(assemble("v0 += 1").unwrap_err(), "line 1: undefined label 'main'");
+        assert_eq!(
+            assemble(":macro forever { forever }\n: main forever").unwrap_err(),
+            "line 2: too many macro expansions"
+        );
+        assert_eq!(assemble(": main :assert \"too big\" { 3 > 4 }").unwrap_err(), "line 1: too big");
+    }
+
+    #[test]
+    fn crafted_expressions_are_handled() {
+        let source = ":calc X { ( 0 - 9223372036854775807 ) % -1 } : main v0 := X";
+        assert_eq!(words(source), [0x1202, 0x6000]);
+
+        let source = format!(":calc X {{ {} 1 }} : main", "1 + ".repeat(100_000));
+        assert_eq!(assemble(&source).unwrap_err(), "line 1: the expression is too long");
+    }
+}
@@ -0,0 +1,376 @@
+//! Patches applied to ROMs before running them
+//!
+//! Two formats are supported, recognized by their first bytes: IPS, which
+//! overwrites bytes at given offsets, and BPS, which builds the patched ROM
+//! from copies of the original and checks both with CRC32 checksums.
+
+use std::convert::{TryFrom, TryInto};
+
+/// Magic number of IPS patches
+const IPS_MAGIC: &[u8] = b"PATCH";
+/// Marker of the end of the records of an IPS patch
+const IPS_EOF: &[u8] = b"EOF";
+/// Magic number of BPS patches
+const BPS_MAGIC: &[u8] = b"BPS1";
+/// Size of the footer of a BPS patch: the checksums of the source, the
+/// target and the patch
+const BPS_FOOTER_SIZE: usize = 12;
+
+/// BPS action copying the byte of the source at the same offset
+const SOURCE_READ: u64 = 0;
+/// BPS action copying bytes of the patch
+const TARGET_READ: u64 = 1;
+/// BPS action copying bytes of the source from anywhere
+const SOURCE_COPY: u64 = 2;
+/// BPS action copying bytes already written to the target
+const TARGET_COPY: u64 = 3;
+
+/// Largest size of a patched ROM, the largest memory of the machines
+const MAX_TARGET_SIZE: usize = 0x10000;
+
+/// Minimum number of unchanged bytes worth a source read when creating a
+/// patch, shorter runs are stored in the patch
+const MIN_SOURCE_RUN: usize = 4;
+
+/// Applies a patch to a ROM, returning the patched ROM
+pub fn apply(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, String> {
+    if patch.starts_with(IPS_MAGIC) {
+        apply_ips(rom, patch)
+    } else if patch.starts_with(BPS_MAGIC) {
+        apply_bps(rom, patch)
+    } else {
+        Err("unknown patch format (expected IPS or BPS)".to_string())
+    }
+}
+
+/// Reads the bytes of a patch
+struct Reader<'a> {
+    /// The patch
+    data: &'a [u8],
+    /// Offset of the next byte
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    /// Reads a number of bytes
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let end = self.pos.checked_add(len).ok_or("invalid number in the patch")?;
+        let bytes = self.data.get(self.pos..end).ok_or("the patch is truncated")?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    /// Reads a big endian number
+    fn number(&mut self, len: usize) -> Result<usize, String> {
+        Ok(self.bytes(len)?.iter().fold(0, |n, bt| n << 8 | *bt as usize))
+    }
+
+    /// Reads a BPS variable length number
+    fn varint(&mut self) -> Result<u64, String> {
+        let mut number: u64 = 0;
+        let mut shift: u64 = 1;
+        loop {
+            let bt = self.bytes(1)?[0] as u64;
+            number = (bt & 0x7F)
+                .checked_mul(shift)
+                .and_then(|n| n.checked_add(number))
+                .ok_or("invalid number in the patch")?;
+            if bt & 0x80 != 0 {
+                return Ok(number);
+            }
+            shift = shift.checked_mul(0x80).ok_or("invalid number in the patch")?;
+            number = number.checked_add(shift).ok_or("invalid number in the patch")?;
+        }
+    }
+}
+
+/// Applies an IPS patch
+fn apply_ips(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, String> {
+    let mut out = rom.to_vec();
+    let mut reader = Reader { data: patch, pos: IPS_MAGIC.len() };
+
+    loop {
+        if reader.bytes(3)? == IPS_EOF {
+            break;
+        }
+        reader.pos -= 3;
+
+        let offset = reader.number(3)?;
+        let (len, bytes) = match reader.number(2)? {
+            // Run of the same byte
+            0 => {
+                let len = reader.number(2)?;
+                (len, vec![reader.bytes(1)?[0]; len])
+            },
+            len => (len, reader.bytes(len)?.to_vec()),
+        };
+
+        if out.len() < offset + len {
+            out.resize(offset + len, 0);
+        }
+        out[offset..offset + len].copy_from_slice(&bytes);
+    }
+
+    // Optional size of the patched ROM
+    if let Ok(size) = reader.number(3) {
+        out.truncate(size);
+    }
+
+    Ok(out)
+}
+
+/// Applies a BPS patch, checking the checksums of the ROM, the patched ROM
+/// and the patch
+fn apply_bps(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, String> {
+    if patch.len() < BPS_MAGIC.len() + BPS_FOOTER_SIZE {
+        return Err("the patch is truncated".to_string());
+    }
+    let actions_end = patch.len() - BPS_FOOTER_SIZE;
+    let footer = &patch[actions_end..];
+    let crc = |i: usize| u32::from_le_bytes(footer[i * 4..i * 4 + 4].try_into().unwrap());
+
+    if crc32fast::hash(&patch[..patch.len() - 4]) != crc(2) {
+        return Err("the patch is corrupted (wrong checksum)".to_string());
+    }
+    if crc32fast::hash(rom) != crc(0) {
+        return Err("the patch is for another ROM (wrong checksum)".to_string());
+    }
+
+    let mut reader = Reader { data: &patch[..actions_end], pos: BPS_MAGIC.len() };
+    let source_size = reader.varint()? as usize;
+    let target_size = reader.varint()? as usize;
+    let metadata_size = reader.varint()? as usize;
+    reader.bytes(metadata_size)?;
+    if source_size != rom.len() {
+        return Err("the patch is for another ROM (wrong size)".to_string());
+    }
+    if target_size > MAX_TARGET_SIZE {
+        return Err("the patched ROM is too large".to_string());
+    }
+
+    let mut out: Vec<u8> = Vec::with_capacity(target_size);
+    let mut source_offset: i64 = 0;
+    let mut target_offset: i64 = 0;
+    let out_of_bounds = || "the patch copies bytes out of bounds".to_string();
+
+    while reader.pos < actions_end {
+        let action = reader.varint()?;
+        let len = (action >> 2) as usize + 1;
+        if out.len() + len > target_size {
+            return Err("the patch writes past the end of the ROM".to_string());
+        }
+
+        match action & 3 {
+            SOURCE_READ => {
+                let start = out.len();
+                out.extend_from_slice(rom.get(start..start + len).ok_or_else(out_of_bounds)?);
+            },
+            TARGET_READ => out.extend_from_slice(reader.bytes(len)?),
+            SOURCE_COPY => {
+                source_offset = source_offset
+                    .checked_add(relative_offset(reader.varint()?))
+                    .ok_or_else(out_of_bounds)?;
+                let start = usize::try_from(source_offset).map_err(|_| out_of_bounds())?;
+                out.extend_from_slice(rom.get(start..start + len).ok_or_else(out_of_bounds)?);
+                source_offset += len as i64;
+            },
+            TARGET_COPY => {
+                target_offset = target_offset
+                    .checked_add(relative_offset(reader.varint()?))
+                    .ok_or_else(out_of_bounds)?;
+                // Byte by byte, as the copy can overlap what it writes
+                for _ in 0..len {
+                    let bt = usize::try_from(target_offset)
+                        .ok()
+                        .and_then(|offset| out.get(offset).copied())
+                        .ok_or_else(out_of_bounds)?;
+                    out.push(bt);
+                    target_offset += 1;
+                }
+            },
+            _ => unreachable!(),
+        }
+    }
+
+    if out.len() != target_size || crc32fast::hash(&out) != crc(1) {
+        return Err("the patched ROM is wrong (wrong checksum)".to_string());
+    }
+
+    Ok(out)
+}
+
+/// Decodes the relative offset of a BPS copy: a sign bit, then the offset
+fn relative_offset(value: u64) -> i64 {
+    let offset = (value >> 1) as i64;
+    if value & 1 != 0 {
+        -offset
+    } else {
+        offset
+    }
+}
+
+/// Creates a BPS patch turning a ROM into a modified ROM
+/// Unchanged bytes are read from the ROM, the others are stored in the patch
+pub fn create_bps(source: &[u8], target: &[u8]) -> Vec<u8> {
+    let mut patch = BPS_MAGIC.to_vec();
+    write_varint(&mut patch, source.len() as u64);
+    write_varint(&mut patch, target.len() as u64);
+    write_varint(&mut patch, 0);
+
+    let unchanged = |i: usize| source.get(i) == Some(&target[i]);
+    let mut pos = 0;
+    while pos < target.len() {
+        let same = (pos..target.len()).take_while(|i| unchanged(*i)).count();
+        if same >= MIN_SOURCE_RUN || (same > 0 && pos + same == target.len()) {
+            write_varint(&mut patch, (same as u64 - 1) << 2 | SOURCE_READ);
+            pos += same;
+            continue;
+        }
+
+        // Changed bytes, up to the next run of unchanged ones
+        let mut end = pos + same.max(1);
+        while end < target.len()
+            && (end..target.len().min(end + MIN_SOURCE_RUN)).any(|i| !unchanged(i))
+        {
+            end += 1;
+        }
+        write_varint(&mut patch, ((end - pos) as u64 - 1) << 2 | TARGET_READ);
+        patch.extend_from_slice(&target[pos..end]);
+        pos = end;
+    }
+
+    patch.extend_from_slice(&crc32fast::hash(source).to_le_bytes());
+    patch.extend_from_slice(&crc32fast::hash(target).to_le_bytes());
+    let patch_crc = crc32fast::hash(&patch);
+    patch.extend_from_slice(&patch_crc.to_le_bytes());
+    patch
+}
+
+/// Writes a BPS variable length number
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let bt = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(bt | 0x80);
+            return;
+        }
+        out.push(bt);
+        value -= 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ips_records_and_runs() {
+        let rom = [0u8; 8];
+        let mut patch = b"PATCH".to_vec();
+        patch.extend_from_slice(&[0, 0, 1, 0, 2, 0xAA, 0xBB]); // 2 bytes at 1
+        patch.extend_from_slice(&[0, 0, 6, 0, 0, 0, 4, 0xCC]); // 4 times 0xCC at 6
+        patch.extend_from_slice(b"EOF");
+
+        let patched = apply(&rom, &patch).unwrap();
+        assert_eq!(patched, [0, 0xAA, 0xBB, 0, 0, 0, 0xCC, 0xCC, 0xCC, 0xCC]);
+
+        patch.extend_from_slice(&[0, 0, 4]);
+        assert_eq!(apply(&rom, &patch).unwrap(), [0, 0xAA, 0xBB, 0]);
+
+        assert!(apply(&rom, b"PATCH\x00\x00").is_err());
+    }
+
+    #[test]
+    fn varints_round_trip() {
+        for value in [0, 1, 0x7F, 0x80, 0x4000, 123_456_789] {
+            let mut data = vec![];
+            write_varint(&mut data, value);
+            let mut reader = Reader { data: &data, pos: 0 };
+            assert_eq!(reader.varint().unwrap(), value);
+            assert_eq!(reader.pos, data.len());
+        }
+    }
+
+    #[test]
+    fn bps_created_then_applied() {
+        let source: Vec<u8> = (0..=255).collect();
+        let mut target = source.clone();
+        target[10] = 0;
+        target[11] = 1;
+        target[200..210].copy_from_slice(&[7; 10]);
+        target.extend_from_slice(b"new bytes");
+
+        let patch = create_bps(&source, &target);
+        assert!(patch.len() < 64);
+        assert_eq!(apply(&source, &patch).unwrap(), target);
+
+        let shorter = &source[..100];
+        assert_eq!(apply(&source, &create_bps(&source, shorter)).unwrap(), shorter);
+    }
+
+    #[test]
+    fn bps_copies() {
+        // Target "abcabcab" from source "xabc": a source copy, then an
+        // overlapping target copy
+        let source = b"xabc";
+        let mut patch = b"BPS1".to_vec();
+        write_varint(&mut patch, 4);
+        write_varint(&mut patch, 8);
+        write_varint(&mut patch, 0);
+        write_varint(&mut patch, 2 << 2 | SOURCE_COPY);
+        write_varint(&mut patch, 1 << 1); // +1
+        write_varint(&mut patch, 4 << 2 | TARGET_COPY);
+        write_varint(&mut patch, 0);
+        patch.extend_from_slice(&crc32fast::hash(source).to_le_bytes());
+        patch.extend_from_slice(&crc32fast::hash(b"abcabcab").to_le_bytes());
+        let crc = crc32fast::hash(&patch);
+        patch.extend_from_slice(&crc.to_le_bytes());
+
+        assert_eq!(apply(source, &patch).unwrap(), b"abcabcab");
+    }
+
+    #[test]
+    fn bps_checksums_are_verified() {
+        let patch = create_bps(b"original", b"modified");
+        assert!(apply(b"Original", &patch).unwrap_err().contains("another ROM"));
+
+        let mut corrupted = patch.clone();
+        corrupted[8] ^= 1;
+        assert!(apply(b"original", &corrupted).unwrap_err().contains("corrupted"));
+    }
+
+    /// Returns a BPS patch of the given body with valid checksums
+    fn bps_with_body(source: &[u8], body: &[u8]) -> Vec<u8> {
+        let mut patch = b"BPS1".to_vec();
+        patch.extend_from_slice(body);
+        patch.extend_from_slice(&crc32fast::hash(source).to_le_bytes());
+        patch.extend_from_slice(&0u32.to_le_bytes());
+        let crc = crc32fast::hash(&patch);
+        patch.extend_from_slice(&crc.to_le_bytes());
+        patch
+    }
+
+    #[test]
+    fn bps_huge_numbers_are_refused() {
+        let source = b"rom";
+        let mut body = vec![];
+        write_varint(&mut body, 3);
+        write_varint(&mut body, 1 << 60);
+        write_varint(&mut body, 0);
+        let error = apply(source, &bps_with_body(source, &body)).unwrap_err();
+        assert!(error.contains("too large"));
+
+        // Varint longer than 64 bits
+        let error = apply(source, &bps_with_body(source, &[0; 12])).unwrap_err();
+        assert_eq!(error, "invalid number in the patch");
+
+        // Metadata larger than the address space
+        let mut body = vec![];
+        write_varint(&mut body, 3);
+        write_varint(&mut body, 3);
+        write_varint(&mut body, u64::MAX);
+        let error = apply(source, &bps_with_body(source, &body)).unwrap_err();
+        assert_eq!(error, "invalid number in the patch");
+    }
+}
@@ -0,0 +1,47 @@
+//! Checks the audio rendered from the sound timer, the way the headless mode
+//! records it
+
+use chip_8_rust::audio::wav::WavWriter;
+use chip_8_rust::audio::{FrameAudio, ToneSettings};
+use chip_8_rust::emu::console::Console;
+use std::io::Cursor;
+
+/// Sample rate of the rendered audio
+const SAMPLE_RATE: u32 = 44100;
+
+/// Sets the sound timer to 30 (half a second), then loops forever
+const BEEP: [u8; 6] = [
+    0x60, 0x1E, // 0x200: LD V0, 30
+    0xF0, 0x18, // 0x202: LD ST, V0
+    0x12, 0x04, // 0x204: JP 0x204
+];
+
+#[test]
+fn sound_timer_beeps_for_exactly_its_duration() {
+    let mut cons = Console::new();
+    cons.load_rom_bytes(&BEEP);
+
+    let settings = ToneSettings {
+        attack: 0.0,
+        release: 0.0,
+        ..ToneSettings::default()
+    };
+    let mut audio = FrameAudio::new(settings, SAMPLE_RATE);
+    let mut wav = WavWriter::new(Cursor::new(vec![]), SAMPLE_RATE).unwrap();
+
+    for _ in 0..60 {
+        let frame = cons.run_frame(12, false);
+        wav.write(&audio.render(frame.sound)).unwrap();
+    }
+
+    let bytes = wav.finish().unwrap().into_inner();
+    let samples: Vec<i16> = bytes[44..]
+        .chunks(2)
+        .map(|b| i16::from_le_bytes([b[0], b[1]]))
+        .collect();
+
+    assert_eq!(samples.len(), SAMPLE_RATE as usize);
+    let tone = samples.iter().filter(|&&s| s != 0).count();
+    assert_eq!(tone, SAMPLE_RATE as usize / 2);
+    assert!(samples[SAMPLE_RATE as usize / 2..].iter().all(|&s| s == 0));
+}
@@ -0,0 +1,218 @@
+//! Runs test ROMs headlessly and compares their screen with golden images
+//!
+//! The test ROMs of the crate always run. The community test ROMs aren't
+//! distributed with the crate, so their test is ignored: see
+//! `tests/roms/README.md` for where to get them, then run it with
+//! `cargo test -- --ignored`.
+//!
+//! Golden images are stored in `tests/golden`, one line per row of the
+//! buffer, with `#` for a lit pixel and `.` for an unlit one. Running the
+//! tests with `UPDATE_GOLDEN=1` writes the current screens as golden images,
+//! which must then be checked by hand against the expected results.
+
+use chip_8_rust::emu::console::{Console, BUFF_HEIGHT, BUFF_WIDTH};
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+/// Number of cycles executed per frame
+const CYCLES_PER_FRAME: usize = 12;
+
+/// Where the ROM of a test comes from
+enum Source {
+    /// ROM built into the test
+    Bytes(&'static [u8]),
+    /// ROM file in `tests/roms`
+    File(&'static str),
+}
+
+/// A ROM to run and the conditions of its run
+struct TestRom {
+    /// Name of the golden image
+    name: &'static str,
+    /// The ROM itself
+    source: Source,
+    /// Number of frames to run
+    frames: usize,
+    /// Bytes written into the memory before running
+    memory: &'static [(usize, u8)],
+    /// Key presses (frame, key, down)
+    keys: &'static [(usize, usize, bool)],
+}
+
+/// Draws the digits 0 to 9 on one line, then loops forever
+const DIGITS: [u8; 20] = [
+    0x60, 0x00, // 0x200: LD V0, 0
+    0x61, 0x02, // 0x202: LD V1, 2
+    0x62, 0x02, // 0x204: LD V2, 2
+    0xF0, 0x29, // 0x206: LD F, V0
+    0xD1, 0x25, // 0x208: DRW V1, V2, 5
+    0x70, 0x01, // 0x20A: ADD V0, 1
+    0x71, 0x06, // 0x20C: ADD V1, 6
+    0x30, 0x0A, // 0x20E: SE V0, 10
+    0x12, 0x06, // 0x210: JP 0x206
+    0x12, 0x12, // 0x212: JP 0x212
+];
+
+/// Address read by the Timendus test ROMs to select a test without a menu
+const MENU_CHOICE: usize = 0x1FF;
+
+/// Test ROMs of the crate
+const CRATE_ROMS: &[TestRom] = &[
+    TestRom {
+        name: "digits",
+        source: Source::Bytes(&DIGITS),
+        frames: 10,
+        memory: &[],
+        keys: &[],
+    },
+    TestRom {
+        name: "alu",
+        source: Source::File("alu.ch8"),
+        frames: 100,
+        memory: &[],
+        keys: &[],
+    },
+    TestRom {
+        name: "keys",
+        source: Source::File("keys.ch8"),
+        frames: 150,
+        memory: &[],
+        // Presses and releases 5 then A, then holds C
+        keys: &[
+            (10, 0x5, true),
+            (15, 0x5, false),
+            (20, 0xA, true),
+            (25, 0xA, false),
+            (30, 0xC, true),
+        ],
+    },
+];
+
+/// Test ROMs of Timendus' test suite
+const TIMENDUS_ROMS: &[TestRom] = &[
+    TestRom {
+        name: "corax+",
+        source: Source::File("3-corax+.ch8"),
+        frames: 100,
+        memory: &[],
+        keys: &[],
+    },
+    TestRom {
+        name: "flags",
+        source: Source::File("4-flags.ch8"),
+        frames: 200,
+        memory: &[],
+        keys: &[],
+    },
+    TestRom {
+        name: "quirks",
+        source: Source::File("5-quirks.ch8"),
+        frames: 600,
+        // Selects the CHIP-8 platform
+        memory: &[(MENU_CHOICE, 1)],
+        keys: &[],
+    },
+    TestRom {
+        name: "keypad",
+        source: Source::File("6-keypad.ch8"),
+        frames: 200,
+        // Selects the FX0A test
+        memory: &[(MENU_CHOICE, 3)],
+        keys: &[(50, 0x5, true), (60, 0x5, false)],
+    },
+];
+
+/// Path of a file in the tests directory
+fn tests_path(dir: &str, file: &str) -> PathBuf {
+    [env!("CARGO_MANIFEST_DIR"), "tests", dir, file].iter().collect()
+}
+
+/// Runs the ROM and returns its screen
+fn run(test: &TestRom) -> Result<String, String> {
+    let rom = match test.source {
+        Source::Bytes(bytes) => bytes.to_vec(),
+        Source::File(file) => {
+            let path = tests_path("roms", file);
+            fs::read(&path).map_err(|e| format!("can't read {}: {}", path.display(), e))?
+        },
+    };
+
+    let mut cons = Console::new();
+    cons.load_rom_bytes(&rom);
+    for &(addr, val) in test.memory {
+        cons.set_memory(addr, val);
+    }
+
+    for frame in 0..test.frames {
+        for &(_, key, down) in test.keys.iter().filter(|k| k.0 == frame) {
+            cons.set_key(key, down);
+        }
+        cons.run_frame(CYCLES_PER_FRAME, false);
+    }
+
+    Ok(screen(&cons))
+}
+
+/// Converts the buffer into the golden image format
+fn screen(cons: &Console) -> String {
+    let mut out = String::with_capacity((BUFF_WIDTH + 1) * BUFF_HEIGHT);
+    for y in 0..BUFF_HEIGHT {
+        for x in 0..BUFF_WIDTH {
+            out.push(if cons.get_bit(x, y) { '#' } else { '.' });
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// Runs the ROMs and compares their screen with their golden image, or
+/// writes it with `UPDATE_GOLDEN=1`
+/// A missing ROM or golden image is a failure
+fn check_golden_images(tests: &[TestRom]) {
+    let update = env::var_os("UPDATE_GOLDEN").is_some();
+    let mut failures = vec![];
+
+    for test in tests {
+        let actual = match run(test) {
+            Ok(actual) => actual,
+            Err(e) => {
+                failures.push(format!("{}: {}", test.name, e));
+                continue;
+            }
+        };
+
+        let golden_path = tests_path("golden", &format!("{}.txt", test.name));
+
+        if update {
+            fs::write(&golden_path, &actual).expect("Error writing golden image");
+            continue;
+        }
+
+        match fs::read_to_string(&golden_path) {
+            Ok(expected) if expected == actual => (),
+            Ok(expected) => failures.push(format!(
+                "{}: screen differs\nexpected:\n{}actual:\n{}",
+                test.name, expected, actual
+            )),
+            Err(_) => failures.push(format!(
+                "{}: no golden image at {}, run with UPDATE_GOLDEN=1",
+                test.name,
+                golden_path.display()
+            )),
+        }
+    }
+
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
+
+#[test]
+fn crate_roms_match_golden_images() {
+    check_golden_images(CRATE_ROMS);
+}
+
+#[test]
+#[ignore = "needs the Timendus test ROMs, see tests/roms/README.md"]
+fn timendus_roms_match_golden_images() {
+    check_golden_images(TIMENDUS_ROMS);
+}